@@ -0,0 +1,64 @@
+//! t2s 解码每个 token 的耗时随序列长度的变化
+//!
+//! cargo bench --bench t2s_decode -- [model_dir] [ref_wav] [prompt_text]
+//!
+//! 按每 100 个 token 分窗口输出平均耗时，k/v cache 不再每步拷贝后，后面的窗口不应明显变慢
+
+use std::time::Instant;
+use rs_tokenizer::bert_utils::t2s_decode;
+use rs_tokenizer::metrics::SynthesisMetrics;
+use rs_tokenizer::tts_engine::{SynthesisParams, TtsEngine};
+
+const WINDOW: usize = 100;
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).filter(|a| !a.starts_with("--")).collect();
+    let model_dir = args.first().map(String::as_str).unwrap_or("./data");
+    let ref_wav_path = args.get(1).map(String::as_str).unwrap_or("./data/xxx.wav");
+    let prompt_text = args.get(2).map(String::as_str).unwrap_or("我注意到了，没有人说图书馆，我刚到广州就因为广州图书馆住在珠江新城附近。");
+
+    let engine = TtsEngine::from_model_dir(model_dir).unwrap();
+    let reference = engine.reference_voice(ref_wav_path, prompt_text).unwrap();
+
+    // 一段足够长的文字，不切分，尽量生成到 max_semantic_tokens
+    let text = "每个人的理想不一样，扎出来的风筝也不一样，所有的风筝中，要数小音乐家根子的最棒了，那是一架竖琴，让她到天上去好好想想吧，风筝的后脑勺上还拖着一条马尾巴似的长辫子，在地面上，我们一边放线一边跑着，手里的线越放越长，风筝也带着我们的理想越飞越远，越飞越高。";
+    let (bert_features2, phones_list_unpack2, _) = engine.text_features(text).unwrap();
+    let params = SynthesisParams {
+        seed: Some(0),
+        max_semantic_tokens: Some(800),
+        early_stop: None,
+        retry: None,
+        ..SynthesisParams::default()
+    };
+
+    let mut metrics = SynthesisMetrics::default();
+    let mut last = Instant::now();
+    let mut latencies: Vec<f64> = vec![];
+    let (tokens, _) = t2s_decode(
+        &engine.t2s_first_stage_decoder,
+        &engine.t2s_stage_decoder,
+        &engine.t2s_device,
+        &reference.prompt_semantic,
+        &reference.prompt_bert,
+        &bert_features2,
+        &reference.prompt_phones,
+        &phones_list_unpack2,
+        &params,
+        &mut metrics,
+        &mut |_| {
+            latencies.push(last.elapsed().as_secs_f64() * 1000.0);
+            last = Instant::now();
+            Ok(())
+        },
+    ).unwrap();
+
+    println!("tokens={} first_stage={:.1}ms decoder={:.1}ms", tokens.len(), metrics.first_stage_ms, metrics.decoder_ms);
+    println!("{:>12} {:>10}", "tokens", "mean ms");
+    let means: Vec<f64> = latencies.chunks(WINDOW).map(|w| w.iter().sum::<f64>() / w.len() as f64).collect();
+    for (i, mean) in means.iter().enumerate() {
+        println!("{:>5}..{:<5} {:>10.2}", i * WINDOW, (i * WINDOW + WINDOW).min(latencies.len()), mean);
+    }
+    if let (Some(first), Some(last)) = (means.first(), means.last()) {
+        println!("last / first window: {:.2}", last / first);
+    }
+}
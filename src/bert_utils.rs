@@ -1,15 +1,17 @@
-use std::path::Path;
-use std::time::{Duration, Instant};
-use ndarray::{Array, Array1, Array2, Array3, Array4, ArrayView1, ArrayView2, ArrayViewD, Axis, IxDyn, s};
-use ort::{CPUExecutionProvider, CUDAExecutionProvider, ExecutionProvider, ExecutionProviderDispatch, GraphOptimizationLevel, inputs, Session, SessionBuilder, Tensor};
-use rsmpeg::ffi::cos;
-// use sdl2::audio::AudioSpecDesired;
+use std::time::Instant;
+use ndarray::{Array1, Array2, Array3, Array4, ArrayView1, ArrayView2, Axis, Ix1, Ix2, Ix3, Ix4, IxDyn, s};
+use ort::{inputs, DynValue, IntoTensorElementType, IoBinding, Session, Tensor, TensorRefMut};
 use tokenizers::Tokenizer;
-use crate::ffmpeg_util::ffmpeg_utils::FfmpegUtils;
-use crate::ffmpeg_utils::FfmpegUtils;
-use crate::infer_commands::INFER_WAV_SESSION;
-use crate::text_utils::{CHINESE_LANG, TextUtils};
-use crate::tts_sovits::text_utils::{CHINESE_LANG, TextUtils};
+use crate::text_utils::CHINESE_LANG;
+use crate::tts_engine::{SynthesisParams, TtsEngine};
+use crate::error::{extract_tensor, TtsError};
+use crate::diagnostics::GenerationDiagnostics;
+use crate::model_bundle::{BERT_DIM, HOP_LENGTH, WIN_LENGTH};
+use crate::sampling::{EOS_TOKEN, exposes_logits, Sampler};
+use log::{info, warn};
+use crate::execution::{ExecutionConfig, Provider};
+use crate::metrics::{elapsed_ms, SynthesisMetrics};
+use crate::post_process::PostProcessConfig;
 
 pub struct ChBertUtils {
     pub tokenizer: Tokenizer,
@@ -26,9 +28,7 @@ pub fn hanning(M: i64) -> Array1<f32> {
         } else {
             let n: Vec<f32> = (1 - M..M).step_by(2).map(|x| {
                 let v1 = pi * (x as f32) / (M - 1) as f32;
-                let v2 = unsafe {
-                    0.5 + 0.5 * cos(v1 as f64)
-                };
+                let v2 = 0.5 + 0.5 * (v1 as f64).cos();
                 v2 as f32
             }).collect();
             Array1::from_vec(n)
@@ -38,34 +38,38 @@ pub fn hanning(M: i64) -> Array1<f32> {
 }
 
 impl ChBertUtils {
-    pub fn init(tokenizer_json_path: &str) -> Self {
-        let tokenizer = Tokenizer::from_file(tokenizer_json_path).unwrap();
-        ChBertUtils { tokenizer }
+    pub fn init(tokenizer_json_path: &str) -> Result<Self, TtsError> {
+        let tokenizer = Tokenizer::from_file(tokenizer_json_path).map_err(|e| TtsError::dictionary(tokenizer_json_path, e))?;
+        Ok(ChBertUtils { tokenizer })
     }
 
-    pub fn load_model(bert_model_path: &str) -> ort::Result<Session> {
-        let model_bytes = std::fs::read(bert_model_path).unwrap();
-
-        let cuda_build = CUDAExecutionProvider::default().build();
-        let cuda_is_available = cuda_build.is_available().unwrap();
-        println!("load on gpu:{}", cuda_is_available);
-
-        let execution_providers = vec![cuda_build];
-        let mut session_nrf_builder = SessionBuilder::new();
-
-        let mut session_nrf = session_nrf_builder.unwrap()
-            .with_optimization_level(GraphOptimizationLevel::Level3).unwrap()
-            .with_execution_providers(execution_providers).unwrap()
-            .commit_from_memory(&model_bytes);
-        if session_nrf.is_err() {
-            let execution_providers = vec![CPUExecutionProvider::default().build()];
-            session_nrf = SessionBuilder::new().unwrap()
-                .with_optimization_level(GraphOptimizationLevel::Level3).unwrap()
-                .with_execution_providers(execution_providers).unwrap()
-                .commit_from_memory(&model_bytes);
-        }
+    /// 按 execution.providers 的顺序创建 Session，前面的失败就用后面的
+    pub fn load_model(bert_model_path: &str, execution: &ExecutionConfig) -> Result<Session, TtsError> {
+        ChBertUtils::load_model_on(bert_model_path, execution).map(|(session, _)| session)
+    }
 
-        return session_nrf;
+    /// 同 load_model，同时返回实际用上的 provider
+    pub fn load_model_on(bert_model_path: &str, execution: &ExecutionConfig) -> Result<(Session, Provider), TtsError> {
+        let model_load_err = |e: &dyn std::fmt::Display| TtsError::ModelLoad { path: bert_model_path.to_string(), message: e.to_string() };
+        let model_bytes = std::fs::read(bert_model_path).map_err(|e| model_load_err(&e))?;
+
+        let mut errors = vec![];
+        for (provider, builder) in execution.session_builders() {
+            match builder.and_then(|b| b.commit_from_memory(&model_bytes)) {
+                Ok(session) => {
+                    info!("load {} on {:?}", bert_model_path, provider);
+                    return Ok((session, provider));
+                }
+                Err(e) => {
+                    warn!("load {} on {:?} failed: {}", bert_model_path, provider, e);
+                    errors.push(format!("{:?}: {}", provider, e));
+                }
+            }
+        }
+        if errors.is_empty() {
+            errors.push("no execution provider".to_string());
+        }
+        Err(model_load_err(&errors.join("; ")))
     }
 
 
@@ -76,7 +80,7 @@ impl ChBertUtils {
                              word2ph_list: &Vec<Vec<usize>>,
                              norm_text_list: &Vec<String>,
                              language_list: &Vec<String>)
-                             -> (Array2<f32>, Vec<usize>, String) {
+                             -> Result<(Array2<f32>, Vec<usize>, String), TtsError> {
         let mut bert_features = vec![];
         let mut phones_list_unpack = vec![];
         let mut norm_text_str = "".to_string();
@@ -102,13 +106,16 @@ impl ChBertUtils {
                 let attention_mask: Array2<i64> = ndarray::Array1::from_vec(attention_mask.to_vec()).insert_axis(Axis(0)).mapv(|x| x as i64);
                 let token_type_ids: Array2<i64> = ndarray::Array1::from_vec(token_type_ids.to_vec()).insert_axis(Axis(0)).mapv(|x| x as i64);
 
-                let input_tensor_value = inputs![input_ids, attention_mask, token_type_ids].unwrap();
-                let generator_source = bert_model.run(input_tensor_value);
-                let generator_source = generator_source.unwrap();
+                let input_tensor_value = inputs![input_ids, attention_mask, token_type_ids]?;
+                let generator_source = bert_model.run(input_tensor_value)?;
 
-                let hidden_states = generator_source["hidden_states"].try_extract_tensor().unwrap();
+                let hidden_states = extract_tensor!(generator_source, "hidden_states", f32, Ix3);
                 // [1, 32, 1024] -> [0,1:-1,:]
-                let hidden_states: Array2<f32> = hidden_states.view().slice(s![0,1..-1,..;1]).to_owned();
+                let hidden_states: Array2<f32> = hidden_states.slice(s![0,1..-1,..;1]).to_owned();
+                if word2ph.len() > hidden_states.shape()[0] {
+                    return Err(TtsError::TextFrontend(format!(
+                        "word2ph len {} > bert tokens {}: {}", word2ph.len(), hidden_states.shape()[0], norm_text_list[i])));
+                }
 
                 let mut phone_level_feature = vec![];
                 for (i, &w2) in word2ph.iter().enumerate() {
@@ -120,79 +127,92 @@ impl ChBertUtils {
 
                     let repeat_features_view: Vec<ArrayView1<f32>> = repeat_features.iter().map(|v| v.view()).collect();
 
-                    let repeat_feature: Array2<f32> = ndarray::stack(Axis(0), &repeat_features_view).unwrap();
+                    let repeat_feature: Array2<f32> = ndarray::stack(Axis(0), &repeat_features_view).map_err(|e| TtsError::shape("bert", e))?;
                     phone_level_feature.push(repeat_feature);
                 }
 
                 let phone_level_feature: Vec<ArrayView2<f32>> = phone_level_feature.iter().map(|v| v.view()).collect();
 
-                let phone_level_feature = ndarray::concatenate(Axis(0), &phone_level_feature).unwrap();
+                let phone_level_feature = ndarray::concatenate(Axis(0), &phone_level_feature).map_err(|e| TtsError::shape("bert", e))?;
                 let phone_level_feature_t: Array2<f32> = ndarray::ArrayBase::t(&phone_level_feature).to_owned();
                 bert_features.push(phone_level_feature_t);
             } else {
-                let bert: Array2<f32> = Array2::zeros((1024, phones_len));
+                let bert: Array2<f32> = Array2::zeros((BERT_DIM, phones_len));
                 bert_features.push(bert);
             }
         }
         let bert_features_view: Vec<ArrayView2<f32>> = bert_features.iter().map(|v| v.view()).collect();
-        let bert_features: Array2<f32> = ndarray::concatenate(Axis(1), &bert_features_view).unwrap();
+        let bert_features: Array2<f32> = ndarray::concatenate(Axis(1), &bert_features_view).map_err(|e| TtsError::shape("bert", e))?;
+        if bert_features.shape()[1] != phones_list_unpack.len() {
+            return Err(TtsError::TextFrontend(format!(
+                "bert features {} != phones {}: {}", bert_features.shape()[1], phones_list_unpack.len(), norm_text_str)));
+        }
 
-        (bert_features, phones_list_unpack, norm_text_str)
+        Ok((bert_features, phones_list_unpack, norm_text_str))
     }
 }
 
 /*
-生成音频
+参考音频 -> prompt semantic codes [1, T]
 **/
-pub fn wav_maker(
+pub fn get_prompt_semantic(
     ssl_model: &Session,
     vq_model_latent: &Session,
-    t2s_first_stage_decoder: &Session,
-    t2s_stage_decoder: &Session,
-    vq_model: &Session,
     wav16k_arr: &Array2<f32>,
-    wav32k_arr: &Array2<f32>,
-    bert_features1: &Array2<f32>,
-    bert_features2: &Array2<f32>,
-    phones_list_unpack1: &Vec<usize>,
-    phones_list_unpack2: &Vec<usize>,
-    top_k: i64,
-    temperature: f32,
-) -> Result<Vec<i16>, String> {
-    let input_wav16k = inputs![wav16k_arr.clone()].unwrap();
-    let ssl_content = ssl_model.run(input_wav16k);
-    let ssl_content = ssl_content.unwrap();
-    let ssl_content = ssl_content["output"].try_extract_tensor::<f32>().unwrap();
-
-    let hop_length = 640;
-    let win_length = 2048;
-    let hann_window = hanning(win_length);
+) -> Result<Array2<i64>, TtsError> {
+    let input_wav16k = inputs![wav16k_arr.clone()]?;
+    let ssl_content = ssl_model.run(input_wav16k)?;
 
     // [1, 768, 239]
-    let ssl_content: Array3<f32> = ssl_content.view().slice(s![..,..,..]).to_owned();
+    let ssl_content: Array3<f32> = extract_tensor!(ssl_content, "output", f32, Ix3).to_owned();
+
+    let input_ssl_content = inputs![ssl_content]?;
+    let codes = vq_model_latent.run(input_ssl_content)?;
+    let codes = extract_tensor!(codes, "output", i64, Ix3);
+    let prompt: Array2<i64> = codes.slice(s![0,..,..]).to_owned();
+
+    Ok(prompt)
+}
 
-    let input_ssl_content = inputs![ssl_content].unwrap();
-    let codes = vq_model_latent.run(input_ssl_content);
-    let codes = codes.unwrap();
-    let codes = codes["output"].try_extract_tensor::<i64>().unwrap();
-    let prompt: Array2<i64> = codes.view().slice(s![0,..,..]).to_owned();
+/// t2s_first_stage_decoder 的输出，x_len: 参考 + 这段文字的音素数
+struct FirstStage {
+    y: Array2<i64>,
+    k: Array4<f32>,
+    v: Array4<f32>,
+    y_emb: Array3<f32>,
+    x_len: usize,
+}
 
-    let top_k: Array1<i64> = ndarray::Array1::from(vec![top_k]);
-    let temperature: Array1<f32> = ndarray::Array1::from(vec![temperature]);
+/*
+参考 + 一段文字 -> t2s_first_stage_decoder，batch 为 1
+**/
+fn t2s_first_stage(
+    t2s_first_stage_decoder: &Session,
+    prompt: &Array2<i64>,
+    bert_features1: &Array2<f32>,
+    bert_features2: &Array2<f32>,
+    phones_list_unpack1: &Vec<usize>,
+    phones_list_unpack2: &Vec<usize>,
+    params: &SynthesisParams,
+) -> Result<FirstStage, TtsError> {
+    let top_k: Array1<i64> = ndarray::Array1::from(vec![params.top_k]);
+    let temperature: Array1<f32> = ndarray::Array1::from(vec![params.temperature]);
     //  合并参考的声音
-    let bert: Array3<f32> = ndarray::concatenate(Axis(1), &[bert_features1.view(), bert_features2.view()]).unwrap().insert_axis(Axis(0));
+    let bert: Array3<f32> = ndarray::concatenate(Axis(1), &[bert_features1.view(), bert_features2.view()])
+        .map_err(|e| TtsError::shape("bert", e))?.insert_axis(Axis(0));
 
     // 会清空
     let mut _phones_list_unpack1 = phones_list_unpack1.clone();
     let mut _phones_list_unpack2 = phones_list_unpack2.clone();
     _phones_list_unpack1.append(&mut _phones_list_unpack2);
 
-    let all_phoneme_ids: Array2<i64> = Array1::from_vec(_phones_list_unpack1).insert_axis(Axis(0)).mapv(|x| x as i64);
-    let text: Array2<i64> = Array1::from_vec(phones_list_unpack2.clone()).insert_axis(Axis(0)).mapv(|x| x as i64);
+    if bert.shape()[2] != _phones_list_unpack1.len() {
+        return Err(TtsError::shape("bert", format!("{} features for {} phones", bert.shape()[2], _phones_list_unpack1.len())));
+    }
 
-    let x_example: Array2<f32> = Array2::zeros((all_phoneme_ids.shape()[0], all_phoneme_ids.shape()[1]));
+    let all_phoneme_ids: Array2<i64> = Array1::from_vec(_phones_list_unpack1).insert_axis(Axis(0)).mapv(|x| x as i64);
+    let x_len = all_phoneme_ids.shape()[1];
 
-    let start_loop = Instant::now();
     // let first_stage_decoder_input = inputs![all_phoneme_ids,bert,prompt,&top_k,&temperature].unwrap();
     let first_stage_decoder_input = inputs![
         "all_phoneme_ids" => all_phoneme_ids.view(),
@@ -200,229 +220,506 @@ pub fn wav_maker(
         "prompt" => prompt.view(),
         "top_k" => top_k.view(),
         "temperature" => temperature.view(),
-    ].unwrap();
-    let t2s_first_stage_out = t2s_first_stage_decoder.run(first_stage_decoder_input);
-    let t2s_first_stage_out = t2s_first_stage_out.unwrap();
-
-    let mut y: Array2<i64> = t2s_first_stage_out["y"].try_extract_tensor::<i64>().unwrap().view().slice(s![..,..]).into_owned();
-    let mut k: Array4<f32> = t2s_first_stage_out["k"].try_extract_tensor::<f32>().unwrap().view().slice(s![..,..,..,..]).into_owned();
-    let mut v: Array4<f32> = t2s_first_stage_out["v"].try_extract_tensor::<f32>().unwrap().view().slice(s![..,..,..,..]).into_owned();
-    let mut y_emb: Array3<f32> = t2s_first_stage_out["y_emb"].try_extract_tensor::<f32>().unwrap().view().slice(s![..,..,..]).into_owned();
-
-    let mut y_example: Array2<f32> = Array2::zeros((1, y_emb.shape()[1]));
-    let y_example_0: Array2<f32> = Array2::zeros((1, 1));
-
-
-    let mut loop_idx = 0;
-    for idx in 1..1500 {
-        y_example = ndarray::concatenate(Axis(1), &[y_example.view(), y_example_0.view()]).unwrap();
-        let xy_attn_mask: Array4<f32> = ndarray::concatenate(Axis(1), &[x_example.view(), y_example.view()])
-            .unwrap().insert_axis(Axis(0)).insert_axis(Axis(0));
-
-
-        let t2s_stage_decoder_input = inputs![
-        "y" => y.view(),
-        "k" => k.view(),
-        "v" => v.view(),
-        "y_emb" => y_emb.view(),
-        "xy_attn_mask" => xy_attn_mask.view(),
-        "top_k" => top_k.view(),
-        "temperature" => temperature.view(),
-        ].unwrap();
-
-        let t2s_stage_decoder_out = t2s_stage_decoder.run(t2s_stage_decoder_input);
-        let t2s_stage_decoder_out = t2s_stage_decoder_out.unwrap();
-
-        k = t2s_stage_decoder_out["o_k"].try_extract_tensor::<f32>().unwrap().view().slice(s![..,..,..,..]).into_owned();
-        v = t2s_stage_decoder_out["o_v"].try_extract_tensor::<f32>().unwrap().view().slice(s![..,..,..,..]).into_owned();
-        y_emb = t2s_stage_decoder_out["o_y_emb"].try_extract_tensor::<f32>().unwrap().view().slice(s![..,..,..]).into_owned();
-        let logits: Array1<i64> = t2s_stage_decoder_out["logits"].try_extract_tensor::<i64>().unwrap().view().slice(s![..]).into_owned();
-        let samples: Array2<i64> = t2s_stage_decoder_out["samples"].try_extract_tensor::<i64>().unwrap().view().slice(s![..,..]).into_owned();
+    ]?;
+    let t2s_first_stage_out = t2s_first_stage_decoder.run(first_stage_decoder_input)?;
+
+    Ok(FirstStage {
+        y: extract_tensor!(t2s_first_stage_out, "y", i64, Ix2).into_owned(),
+        k: extract_tensor!(t2s_first_stage_out, "k", f32, Ix4).into_owned(),
+        v: extract_tensor!(t2s_first_stage_out, "v", f32, Ix4).into_owned(),
+        y_emb: extract_tensor!(t2s_first_stage_out, "y_emb", f32, Ix3).into_owned(),
+        x_len,
+    })
+}
 
-        y = ndarray::concatenate(Axis(1), &[y.view(), samples.view()]).unwrap();
+/// t2s_stage_decoder 一步的输出
+enum StageStep {
+    /// float logits [batch * vocab]，在 Rust 这边采样
+    Logits(Vec<f32>),
+    /// 模型里采样好的 (logits, samples)，batch 为 1
+    Sampled(i64, i64),
+}
 
-        let sample = samples.get((0, 0)).unwrap();
-        let logit = logits.get(0).unwrap();
+/*
+t2s_stage_decoder 的自回归循环
+k/v/y_emb 通过 IoBinding 留在 provider 的内存里，上一步的输出直接当下一步的输入，不拷到 ndarray；
+y 和 xy_attn_mask 按最大步数预留好内存，每步原地加一列，直接把这块内存绑定成输入，不再每步拷贝
+**/
+struct StageLoop<'s> {
+    binding: IoBinding<'s>,
+    k: DynValue,
+    v: DynValue,
+    y_emb: DynValue,
+    // [batch, y_len]
+    y: GrowingInput<i64>,
+    // [batch, 1, 1, mask_len]，新 token 的位置是 0
+    mask: GrowingInput<f32>,
+    rust_sampling: bool,
+}
 
-        if *logit == 1024 || *sample == 1024 {
-            loop_idx = idx;
-            break;
+impl<'s> StageLoop<'s> {
+    /// mask: first stage 之后的 [batch, x_len + y_emb 长度]
+    fn new(
+        t2s_stage_decoder: &'s Session,
+        t2s_device: &Provider,
+        first_stage: (Array2<i64>, Array4<f32>, Array4<f32>, Array3<f32>),
+        mask: Array2<f32>,
+        max_steps: usize,
+        params: &SynthesisParams,
+    ) -> Result<Self, TtsError> {
+        let (y, k, v, y_emb) = first_stage;
+        // 导出的 stage decoder 有 float logits 时在这边采样，seed 固定就能复现
+        let rust_sampling = exposes_logits(t2s_stage_decoder);
+        if !rust_sampling {
+            warn_in_graph_sampling(params);
         }
-    }
 
-    *y.get_mut((0, y.shape()[1] - 1)).unwrap() = 0;
+        let mut binding = t2s_stage_decoder.create_binding()?;
+        binding.bind_input("top_k", &Tensor::from_array(Array1::from(vec![params.top_k]))?)?;
+        binding.bind_input("temperature", &Tensor::from_array(Array1::from(vec![params.temperature]))?)?;
+        let kv_memory = t2s_device.memory_info()?;
+        for name in ["o_k", "o_v", "o_y_emb"] {
+            binding.bind_output_to_device(name, &kv_memory)?;
+        }
+        // 采样要在 cpu 上读
+        let cpu_memory = Provider::Cpu.memory_info()?;
+        binding.bind_output_to_device("logits", &cpu_memory)?;
+        if !rust_sampling {
+            binding.bind_output_to_device("samples", &cpu_memory)?;
+        }
 
-    let pred_semantic: Array3<i64> = y.slice(s![..,y.shape()[1]-loop_idx..]).into_owned().insert_axis(Axis(0));
+        Ok(StageLoop {
+            binding,
+            k: Tensor::from_array(k)?.into_dyn(),
+            v: Tensor::from_array(v)?.into_dyn(),
+            y_emb: Tensor::from_array(y_emb)?.into_dyn(),
+            y: GrowingInput::new(y.view(), max_steps),
+            mask: GrowingInput::new(mask.view(), max_steps),
+            rust_sampling,
+        })
+    }
 
-    let y_len = (pred_semantic.shape()[2] * 2) as i64;
-    let y_lengths: Array1<i64> = ndarray::Array1::from(vec![y_len]);
-    let text_lengths: Array1<i64> = ndarray::Array1::from(vec![text.shape()[0] as i64]);
-    let T = (wav32k_arr.shape()[1] - hop_length) / hop_length + 1;
-    let refer_mask: Array3<i64> = Array3::ones((pred_semantic.shape()[0], pred_semantic.shape()[1], T));
+    /// 跑一步，之后要用 push 写入这一步的 token
+    fn step(&mut self) -> Result<StageStep, TtsError> {
+        let zeros = vec![0.0; self.mask.batch];
+        self.mask.push(&zeros)?;
+        let (batch, y_len, mask_len) = (self.y.batch as i64, self.y.len as i64, self.mask.len as i64);
+        // 两个视图借着 self.y、self.mask，run 完之前不能 push
+        let y = self.y.tensor(vec![batch, y_len])?;
+        let xy_attn_mask = self.mask.tensor(vec![batch, 1, 1, mask_len])?;
+        self.binding.bind_input("y", &*y)?;
+        self.binding.bind_input("xy_attn_mask", &*xy_attn_mask)?;
+        self.binding.bind_input("k", &self.k)?;
+        self.binding.bind_input("v", &self.v)?;
+        self.binding.bind_input("y_emb", &self.y_emb)?;
+
+        let mut outputs = self.binding.run()?;
+        let step = if self.rust_sampling {
+            StageStep::Logits(extract_tensor!(outputs, "logits", f32, IxDyn).iter().copied().collect())
+        } else {
+            let logits = extract_tensor!(outputs, "logits", i64, Ix1);
+            let samples = extract_tensor!(outputs, "samples", i64, Ix2);
+            let sample = *samples.get((0, 0)).ok_or_else(|| TtsError::shape("samples", "empty"))?;
+            let logit = *logits.get(0).ok_or_else(|| TtsError::shape("logits", "empty"))?;
+            StageStep::Sampled(logit, sample)
+        };
+
+        let mut take = |name: &str| outputs.remove(name).ok_or_else(|| TtsError::MissingTensor { name: name.to_string() });
+        let (k, v, y_emb) = (take("o_k")?, take("o_v")?, take("o_y_emb")?);
+        self.k = k;
+        self.v = v;
+        self.y_emb = y_emb;
+        Ok(step)
+    }
 
-    let vq_model_input = inputs![
-        "pred_semantic"=>pred_semantic.view(),
-        "text"=>text.view(),
-        "org_audio"=>wav32k_arr.view(),
-        "hann_window"=>hann_window.view(),
-        "refer_mask"=>refer_mask.view(),
-        "y_lengths"=>y_lengths.view(),
-        "text_lengths"=>text_lengths.view(),
-    ].unwrap();
+    /// samples: 这一步每行的 token
+    fn push(&mut self, samples: &[i64]) -> Result<(), TtsError> {
+        self.y.push(samples)
+    }
+}
 
-    let vq_model_out = vq_model.run(vq_model_input);
-    if vq_model_out.is_err() {
-        return Err(String::from("Infer error"));
+/// 模型里采样只用 top_k、temperature；其它采样参数改了默认值不会生效，提醒一下
+fn warn_in_graph_sampling(params: &SynthesisParams) {
+    let defaults = SynthesisParams::default();
+    let mut ignored = vec![];
+    if params.top_p != defaults.top_p {
+        ignored.push("top_p");
+    }
+    if params.repetition_penalty != defaults.repetition_penalty {
+        ignored.push("repetition_penalty");
+    }
+    if params.seed.is_some() {
+        ignored.push("seed");
+    }
+    if !ignored.is_empty() {
+        warn!("t2s_stage_decoder has no float logits and samples in the graph: {} ignored, output is not reproducible", ignored.join(", "));
     }
+}
 
-    let vq_model_out = vq_model_out.unwrap();
+/// 按行紧密排列的 [batch, len]，容量按最大步数一次分配好；
+/// 加一列时从最后一行往前原地挪，内存地址不变，可以直接当 onnx 输入
+struct GrowingInput<T> {
+    data: Vec<T>,
+    batch: usize,
+    len: usize,
+}
 
-    let mut audio: Array1<f32> = vq_model_out["audio"].try_extract_tensor::<f32>().unwrap().view().slice(s![0,0,..]).into_owned();
+impl<T: Copy + Default> GrowingInput<T> {
+    fn new(init: ArrayView2<T>, max_steps: usize) -> Self {
+        let (batch, len) = init.dim();
+        let mut data = Vec::with_capacity(batch * (len + max_steps));
+        data.extend(init.iter().copied());
+        GrowingInput { data, batch, len }
+    }
 
-    let mut audio: Vec<f32> = audio.to_vec();
-    let max_audio = {
-        let mut max_v = 0.0;
-        for &v in &audio {
-            let v = num::abs(v);
-            if v > max_v {
-                max_v = v;
-            }
+    /// column: 每行新加的一个值；超过预留的步数会重新分配内存，绑定过的地址就失效了，直接报错
+    fn push(&mut self, column: &[T]) -> Result<(), TtsError> {
+        let (batch, len) = (self.batch, self.len);
+        if column.len() != batch || self.data.capacity() < batch * (len + 1) {
+            return Err(TtsError::Inference(format!(
+                "t2s input over reserved steps: batch {}, length {}, capacity {}", batch, len + 1, self.data.capacity()
+            )));
         }
-        max_v
-    };
-
-    let audio_norm = {
-        if max_audio > 1.0 {
-            let v: Vec<i16> = audio.iter().map(|&x| {
-                ((x / max_audio) * 32768.0) as i16
-            }).collect();
-            v
-        } else {
-            let v: Vec<i16> = audio.iter().map(|&x| {
-                (x * 32768.0) as i16
-            }).collect();
-            v
+        self.data.resize(batch * (len + 1), T::default());
+        for row in (0..batch).rev() {
+            self.data.copy_within(row * len..(row + 1) * len, row * (len + 1));
+            self.data[row * (len + 1) + len] = column[row];
         }
-    };
-
-    Ok(audio_norm)
+        self.len += 1;
+        Ok(())
+    }
 }
 
+impl<T: Copy + Default + IntoTensorElementType + std::fmt::Debug> GrowingInput<T> {
+    /// 不拷贝的 tensor 视图，借着 &mut self：视图还在的时候不能 push
+    fn tensor(&mut self, shape: Vec<i64>) -> Result<TensorRefMut<'_, T>, TtsError> {
+        let elements = shape.iter().try_fold(1usize, |n, &d| usize::try_from(d).ok().and_then(|d| n.checked_mul(d)));
+        if elements != Some(self.data.len()) {
+            return Err(TtsError::shape("t2s input", format!("shape {:?} for {} elements", shape, self.data.len())));
+        }
+        let memory = Provider::Cpu.memory_info()?;
+        // data 的长度和 shape 一致，视图的生命周期不超过 &mut self，期间 data 不会被改动或重新分配
+        Ok(unsafe { TensorRefMut::from_raw(memory, self.data.as_mut_ptr() as *mut _, shape)? })
+    }
+}
 
-fn test_infer_wav(
-    ssl_model: &Session,
-    vq_model_latent: &Session,
+/*
+t2s 自回归生成 semantic tokens，on_token: 每生成一个 token 回调一次（不含结束符）
+返回 pred_semantic（最后一个结束符位置置 0）和生成情况
+**/
+pub fn t2s_decode(
     t2s_first_stage_decoder: &Session,
     t2s_stage_decoder: &Session,
-    vq_model: &Session,
-    wav16k_arr: &Array2<f32>,
-    wav32k_arr: &Array2<f32>,
+    t2s_device: &Provider,
+    prompt: &Array2<i64>,
     bert_features1: &Array2<f32>,
     bert_features2: &Array2<f32>,
     phones_list_unpack1: &Vec<usize>,
     phones_list_unpack2: &Vec<usize>,
-)
-{
-    let input_wav16k = inputs![wav16k_arr.clone()].unwrap();
-    let ssl_content = ssl_model.run(input_wav16k);
-    let ssl_content = ssl_content.unwrap();
-    let ssl_content = ssl_content["output"].try_extract_tensor::<f32>().unwrap();
-
-    let sampling_rate: i32 = 32000;
-    let hop_length = 640;
-    let win_length = 2048;
-    let hann_window = hanning(win_length);
-
-    // [1, 768, 239]
-    let ssl_content: Array3<f32> = ssl_content.view().slice(s![..,..,..]).to_owned();
-
-    let input_ssl_content = inputs![ssl_content].unwrap();
-    let codes = vq_model_latent.run(input_ssl_content);
-    let codes = codes.unwrap();
-    let codes = codes["output"].try_extract_tensor::<i64>().unwrap();
-    let prompt: Array2<i64> = codes.view().slice(s![0,..,..]).to_owned();
+    params: &SynthesisParams,
+    metrics: &mut SynthesisMetrics,
+    on_token: &mut dyn FnMut(&[i64]) -> Result<(), TtsError>,
+) -> Result<(Vec<i64>, GenerationDiagnostics), TtsError> {
+    let start_first_stage = Instant::now();
+    let max_tokens = params.max_tokens(phones_list_unpack2.len());
+    let mut sampler = Sampler::init(params.top_k, params.top_p, params.temperature, params.repetition_penalty, params.seed);
+
+    let FirstStage { y, k, v, y_emb, x_len } = t2s_first_stage(
+        t2s_first_stage_decoder, prompt, bert_features1, bert_features2, phones_list_unpack1, phones_list_unpack2, params,
+    )?;
+    let mask: Array2<f32> = Array2::zeros((1, x_len + y_emb.shape()[1]));
+    let mut stage = StageLoop::new(t2s_stage_decoder, t2s_device, (y, k, v, y_emb), mask, max_tokens, params)?;
+
+    metrics.first_stage_ms += elapsed_ms(start_first_stage);
+
+    // stage decoder 生成的 token
+    let mut pred_semantic: Vec<i64> = Vec::with_capacity(max_tokens);
+
+    let mut finished = false;
+    let mut early_stopped = false;
+    let start_decoder = Instant::now();
+    for _ in 0..max_tokens {
+        params.check_cancelled()?;
+        let start_token = Instant::now();
+        let (sample, eos) = match stage.step()? {
+            StageStep::Logits(logits) => {
+                let sample = sampler.sample(&logits, &pred_semantic);
+                (sample, sample == EOS_TOKEN)
+            }
+            StageStep::Sampled(logit, sample) => (sample, logit == EOS_TOKEN || sample == EOS_TOKEN),
+        };
 
-    let top_k: Array1<i64> = ndarray::Array1::from(vec![20]);
-    let temperature: Array1<f32> = ndarray::Array1::from(vec![0.8]);
-    //  合并参考的声音
-    let bert: Array3<f32> = ndarray::concatenate(Axis(1), &[bert_features1.view(), bert_features2.view()]).unwrap().insert_axis(Axis(0));
+        stage.push(&[sample])?;
+        pred_semantic.push(sample);
+        metrics.token_latency.record(elapsed_ms(start_token));
 
-    // 会清空
-    let mut _phones_list_unpack1 = phones_list_unpack1.clone();
-    let mut _phones_list_unpack2 = phones_list_unpack2.clone();
-    _phones_list_unpack1.append(&mut _phones_list_unpack2);
+        if eos {
+            finished = true;
+            break;
+        }
+        if let Some(keep) = params.early_stop.as_ref().and_then(|early_stop| early_stop.check(&pred_semantic)) {
+            warn!("t2s repeating, stop at {} of {} tokens", keep, pred_semantic.len());
+            pred_semantic.truncate(keep);
+            early_stopped = true;
+            break;
+        }
+        on_token(&pred_semantic)?;
+    }
+    // 包括 on_token 里流式解码的时间，vocoder_ms 另外统计
+    metrics.decoder_ms += elapsed_ms(start_decoder);
+    metrics.tokens += pred_semantic.len();
+
+    if finished {
+        // 结束符置 0
+        if let Some(last) = pred_semantic.last_mut() {
+            *last = 0;
+        }
+    } else if pred_semantic.len() >= max_tokens {
+        warn!("t2s reached max_semantic_tokens {} without EOS", max_tokens);
+    }
 
-    let all_phoneme_ids: Array2<i64> = Array1::from_vec(_phones_list_unpack1).insert_axis(Axis(0)).mapv(|x| x as i64);
-    let text: Array2<i64> = Array1::from_vec(phones_list_unpack2.clone()).insert_axis(Axis(0)).mapv(|x| x as i64);
+    let diagnostics = GenerationDiagnostics::new(&pred_semantic, phones_list_unpack2.len(), finished, early_stopped, sampler.seed(), params);
+    Ok((pred_semantic, diagnostics))
+}
 
-    let x_example: Array2<f32> = Array2::zeros((all_phoneme_ids.shape()[0], all_phoneme_ids.shape()[1]));
+/// 加性 attention mask 里屏蔽的位置
+const MASKED: f32 = f32::NEG_INFINITY;
 
-    let start_loop = Instant::now();
-    // let first_stage_decoder_input = inputs![all_phoneme_ids,bert,prompt,&top_k,&temperature].unwrap();
-    let first_stage_decoder_input = inputs![
-        "all_phoneme_ids" => all_phoneme_ids.view(),
-        "bert" => bert.view(),
-        "prompt" => prompt.view(),
-        "top_k" => top_k.view(),
-        "temperature" => temperature.view(),
-    ].unwrap();
-    let start_loop1 = Instant::now();
-    let t2s_first_stage_out = t2s_first_stage_decoder.run(first_stage_decoder_input);
-    println!("t2s_first_stage time: {}ms", start_loop1.elapsed().as_millis());
-    let t2s_first_stage_out = t2s_first_stage_out.unwrap();
-
-    let mut y: Array2<i64> = t2s_first_stage_out["y"].try_extract_tensor::<i64>().unwrap().view().slice(s![..,..]).into_owned();
-    let mut k: Array4<f32> = t2s_first_stage_out["k"].try_extract_tensor::<f32>().unwrap().view().slice(s![..,..,..,..]).into_owned();
-    let mut v: Array4<f32> = t2s_first_stage_out["v"].try_extract_tensor::<f32>().unwrap().view().slice(s![..,..,..,..]).into_owned();
-    let mut y_emb: Array3<f32> = t2s_first_stage_out["y_emb"].try_extract_tensor::<f32>().unwrap().view().slice(s![..,..,..]).into_owned();
-
-    let mut y_example: Array2<f32> = Array2::zeros((1, y_emb.shape()[1]));
-    let y_example_0: Array2<f32> = Array2::zeros((1, 1));
-
-
-    let mut loop_idx = 0;
-    for idx in 1..1500 {
-        y_example = ndarray::concatenate(Axis(1), &[y_example.view(), y_example_0.view()]).unwrap();
-        let xy_attn_mask: Array4<f32> = ndarray::concatenate(Axis(1), &[x_example.view(), y_example.view()])
-            .unwrap().insert_axis(Axis(0)).insert_axis(Axis(0));
-
-
-        let t2s_stage_decoder_input = inputs![
-        "y" => y.view(),
-        "k" => k.view(),
-        "v" => v.view(),
-        "y_emb" => y_emb.view(),
-        "xy_attn_mask" => xy_attn_mask.view(),
-        "top_k" => top_k.view(),
-        "temperature" => temperature.view(),
-        ].unwrap();
+/*
+多段文字一起跑 t2s：segments 是每段的 (bert_features2, phones_list_unpack2)，参考音色相同
+导出的 first stage 不支持 padding mask，逐段跑完后把 k/v cache 的音素部分补齐到同样长度；
+stage decoder 每步 batch 跑，xy_attn_mask 按行屏蔽补齐的位置，每行各自采样、各自判断 EOS / 复读 / 长度上限，
+全部结束后按行拆开。只支持导出了 float logits、通过 probe_batch_mask 的 stage decoder
+**/
+pub fn t2s_decode_batch(
+    t2s_first_stage_decoder: &Session,
+    t2s_stage_decoder: &Session,
+    t2s_device: &Provider,
+    prompt: &Array2<i64>,
+    bert_features1: &Array2<f32>,
+    phones_list_unpack1: &Vec<usize>,
+    segments: &[(&Array2<f32>, &Vec<usize>)],
+    params: &SynthesisParams,
+    metrics: &mut SynthesisMetrics,
+) -> Result<Vec<(Vec<i64>, GenerationDiagnostics)>, TtsError> {
+    if !exposes_logits(t2s_stage_decoder) {
+        return Err(TtsError::Inference("batched t2s needs a stage decoder exporting float logits".to_string()));
+    }
+    if segments.is_empty() {
+        return Ok(vec![]);
+    }
+    let batch = segments.len();
+    let max_tokens: Vec<usize> = segments.iter().map(|(_, phones)| params.max_tokens(phones.len())).collect();
+    let max_steps = *max_tokens.iter().max().unwrap_or(&0);
+
+    let start_first_stage = Instant::now();
+    let mut stages = vec![];
+    for (bert_features2, phones_list_unpack2) in segments {
+        stages.push(t2s_first_stage(t2s_first_stage_decoder, prompt, bert_features1, bert_features2, phones_list_unpack1, phones_list_unpack2, params)?);
+    }
+    let x_lens: Vec<usize> = stages.iter().map(|stage| stage.x_len).collect();
+    let max_x = *x_lens.iter().max().unwrap_or(&0);
+
+    let ks: Vec<&Array4<f32>> = stages.iter().map(|stage| &stage.k).collect();
+    let vs: Vec<&Array4<f32>> = stages.iter().map(|stage| &stage.v).collect();
+    let k = pad_cache("k", &ks, &x_lens)?;
+    let v = pad_cache("v", &vs, &x_lens)?;
+    // 参考相同，prompt 部分一样长
+    let ys: Vec<ArrayView2<i64>> = stages.iter().map(|stage| stage.y.view()).collect();
+    let y: Array2<i64> = ndarray::concatenate(Axis(0), &ys).map_err(|e| TtsError::shape("y", e))?;
+    let y_embs: Vec<_> = stages.iter().map(|stage| stage.y_emb.view()).collect();
+    let y_emb: Array3<f32> = ndarray::concatenate(Axis(0), &y_embs).map_err(|e| TtsError::shape("y_emb", e))?;
+    drop(stages);
+
+    let mask = padding_mask(&x_lens, max_x, y_emb.shape()[1]);
+    let mut stage = StageLoop::new(t2s_stage_decoder, t2s_device, (y, k, v, y_emb), mask, max_steps, params)?;
+
+    metrics.first_stage_ms += elapsed_ms(start_first_stage);
+
+    // 每行各自的随机序列，seed 固定时第 row 行用 seed + row
+    let mut samplers: Vec<Sampler> = (0..batch)
+        .map(|row| Sampler::init(params.top_k, params.top_p, params.temperature, params.repetition_penalty, params.seed.map(|seed| seed.wrapping_add(row as u64))))
+        .collect();
+    let mut pred_semantics: Vec<Vec<i64>> = vec![vec![]; batch];
+    let mut finished = vec![false; batch];
+    let mut early_stopped = vec![false; batch];
+    let mut done = vec![false; batch];
+
+    let start_decoder = Instant::now();
+    while done.iter().any(|d| !d) {
+        params.check_cancelled()?;
+        let start_token = Instant::now();
+        let logits = match stage.step()? {
+            StageStep::Logits(logits) => logits,
+            StageStep::Sampled(..) => return Err(TtsError::Inference("batched t2s needs float logits".to_string())),
+        };
+        if logits.is_empty() || logits.len() % batch != 0 {
+            return Err(TtsError::shape("logits", format!("{} values for batch {}", logits.len(), batch)));
+        }
+        let vocab = logits.len() / batch;
 
-        let start_loop_t2s_stage_decoder = Instant::now();
-        let t2s_stage_decoder_out = t2s_stage_decoder.run(t2s_stage_decoder_input);
-        println!("stage_decoder: {}ms", start_loop_t2s_stage_decoder.elapsed().as_millis());
-        let t2s_stage_decoder_out = t2s_stage_decoder_out.unwrap();
+        // 已经结束的行照样要喂一个 token，结果不用
+        let mut samples = vec![0; batch];
+        for row in 0..batch {
+            if done[row] {
+                continue;
+            }
+            let sample = samplers[row].sample(&logits[row * vocab..(row + 1) * vocab], &pred_semantics[row]);
+            samples[row] = sample;
+            pred_semantics[row].push(sample);
+
+            if sample == EOS_TOKEN {
+                finished[row] = true;
+                done[row] = true;
+            } else if let Some(keep) = params.early_stop.as_ref().and_then(|early_stop| early_stop.check(&pred_semantics[row])) {
+                warn!("t2s row {} repeating, stop at {} of {} tokens", row, keep, pred_semantics[row].len());
+                pred_semantics[row].truncate(keep);
+                early_stopped[row] = true;
+                done[row] = true;
+            } else if pred_semantics[row].len() >= max_tokens[row] {
+                warn!("t2s row {} reached max_semantic_tokens {} without EOS", row, max_tokens[row]);
+                done[row] = true;
+            }
+        }
+        stage.push(&samples)?;
+        // 一步出 batch 个 token
+        metrics.token_latency.record(elapsed_ms(start_token));
+    }
+    metrics.decoder_ms += elapsed_ms(start_decoder);
+
+    let mut results = vec![];
+    for (row, mut pred_semantic) in pred_semantics.into_iter().enumerate() {
+        if finished[row] {
+            // 结束符置 0
+            if let Some(last) = pred_semantic.last_mut() {
+                *last = 0;
+            }
+        }
+        metrics.tokens += pred_semantic.len();
+        let diagnostics = GenerationDiagnostics::new(&pred_semantic, segments[row].1.len(), finished[row], early_stopped[row], samplers[row].seed(), params);
+        results.push((pred_semantic, diagnostics));
+    }
+    Ok(results)
+}
 
-        k = t2s_stage_decoder_out["o_k"].try_extract_tensor::<f32>().unwrap().view().slice(s![..,..,..,..]).into_owned();
-        v = t2s_stage_decoder_out["o_v"].try_extract_tensor::<f32>().unwrap().view().slice(s![..,..,..,..]).into_owned();
-        y_emb = t2s_stage_decoder_out["o_y_emb"].try_extract_tensor::<f32>().unwrap().view().slice(s![..,..,..]).into_owned();
-        let logits: Array1<i64> = t2s_stage_decoder_out["logits"].try_extract_tensor::<i64>().unwrap().view().slice(s![..]).into_owned();
-        let samples: Array2<i64> = t2s_stage_decoder_out["samples"].try_extract_tensor::<i64>().unwrap().view().slice(s![..,..]).into_owned();
+/*
+加载时检查 stage decoder 能不能 batch：同一段单独跑一步，和补上一截屏蔽掉的音素、两行一起跑一步，logits 应该一样；
+上游导出的 stage decoder 在模型里重新生成 xy_attn_mask，补齐的位置也会参与 attention，这时返回 false
+输入都是编出来的，只看两次结果是否一致
+**/
+pub fn probe_batch_mask(
+    t2s_first_stage_decoder: &Session,
+    t2s_stage_decoder: &Session,
+    t2s_device: &Provider,
+) -> Result<bool, TtsError> {
+    if !exposes_logits(t2s_stage_decoder) {
+        return Ok(false);
+    }
+    let params = SynthesisParams::default();
+    let prompt: Array2<i64> = Array2::from_shape_fn((1, 8), |(_, i)| i as i64 + 1);
+    let phones1: Vec<usize> = (10..14).collect();
+    let phones2: Vec<usize> = (20..26).collect();
+    let bert1: Array2<f32> = Array2::zeros((BERT_DIM, phones1.len()));
+    let bert2: Array2<f32> = Array2::zeros((BERT_DIM, phones2.len()));
+    let FirstStage { y, k, v, y_emb, x_len } = t2s_first_stage(t2s_first_stage_decoder, &prompt, &bert1, &bert2, &phones1, &phones2, &params)?;
+    let y_emb_len = y_emb.shape()[1];
+
+    let first_step = |stage: (Array2<i64>, Array4<f32>, Array4<f32>, Array3<f32>), mask: Array2<f32>| -> Result<Vec<f32>, TtsError> {
+        match StageLoop::new(t2s_stage_decoder, t2s_device, stage, mask, 1, &params)?.step()? {
+            StageStep::Logits(logits) => Ok(logits),
+            StageStep::Sampled(..) => Err(TtsError::Inference("expected float logits".to_string())),
+        }
+    };
+    let single = first_step((y.clone(), k.clone(), v.clone(), y_emb.clone()), Array2::zeros((1, x_len + y_emb_len)))?;
+
+    // 两行都在音素后面补 PROBE_PAD 个不是 0 的位置，mask 屏蔽掉
+    const PROBE_PAD: usize = 3;
+    let pad = |cache: &Array4<f32>| -> Array4<f32> {
+        let (layers, _, len, dim) = cache.dim();
+        let mut out: Array4<f32> = Array4::ones((layers, 2, len + PROBE_PAD, dim));
+        for row in 0..2 {
+            out.slice_mut(s![.., row, ..x_len, ..]).assign(&cache.slice(s![.., 0, ..x_len, ..]));
+            out.slice_mut(s![.., row, x_len + PROBE_PAD.., ..]).assign(&cache.slice(s![.., 0, x_len.., ..]));
+        }
+        out
+    };
+    let ys: Array2<i64> = ndarray::concatenate(Axis(0), &[y.view(), y.view()]).map_err(|e| TtsError::shape("y", e))?;
+    let y_embs: Array3<f32> = ndarray::concatenate(Axis(0), &[y_emb.view(), y_emb.view()]).map_err(|e| TtsError::shape("y_emb", e))?;
+    let mask = padding_mask(&[x_len, x_len], x_len + PROBE_PAD, y_emb_len);
+    let batched = first_step((ys, pad(&k), pad(&v), y_embs), mask)?;
 
-        y = ndarray::concatenate(Axis(1), &[y.view(), samples.view()]).unwrap();
+    if batched.len() != single.len() * 2 {
+        return Ok(false);
+    }
+    let close = |a: f32, b: f32| (a - b).abs() <= 1e-3 + 1e-3 * a.abs();
+    Ok(batched.chunks(single.len()).all(|row| row.iter().zip(&single).all(|(&a, &b)| close(a, b))))
+}
 
-        let sample = samples.get((0, 0)).unwrap();
-        let logit = logits.get(0).unwrap();
+/*
+加载时检查 t2s 能不能用空的 prompt（ref_free、没有参考文字的音色）：prompt [1, 0]、参考 bert [1024, 0]，跑 first stage 和一步 stage；
+有的导出把 prompt 长度写死、或者 concat 不了空张量，不检查的话请求里才报 onnxruntime 的形状错误
+**/
+pub fn probe_empty_prompt(
+    t2s_first_stage_decoder: &Session,
+    t2s_stage_decoder: &Session,
+    t2s_device: &Provider,
+) -> Result<(), TtsError> {
+    let params = SynthesisParams::default();
+    let prompt: Array2<i64> = Array2::zeros((1, 0));
+    let phones1: Vec<usize> = vec![];
+    let phones2: Vec<usize> = (20..26).collect();
+    let bert1: Array2<f32> = Array2::zeros((BERT_DIM, 0));
+    let bert2: Array2<f32> = Array2::zeros((BERT_DIM, phones2.len()));
+    let FirstStage { y, k, v, y_emb, x_len } = t2s_first_stage(t2s_first_stage_decoder, &prompt, &bert1, &bert2, &phones1, &phones2, &params)?;
+    let mask = Array2::zeros((1, x_len + y_emb.shape()[1]));
+    StageLoop::new(t2s_stage_decoder, t2s_device, (y, k, v, y_emb), mask, 1, &params)?.step()?;
+    Ok(())
+}
 
-        if *logit == 1024 || *sample == 1024 {
-            loop_idx = idx;
-            break;
+/*
+每行的 cache [layers, 1, x_len + y_len, dim] -> [layers, batch, max_x + y_len, dim]
+音素部分放在前面、后面补 0，y 部分对齐到 max_x 之后
+**/
+fn pad_cache(name: &str, caches: &[&Array4<f32>], x_lens: &[usize]) -> Result<Array4<f32>, TtsError> {
+    let max_x = *x_lens.iter().max().unwrap_or(&0);
+    let first = caches.first().ok_or_else(|| TtsError::shape(name, "empty batch"))?;
+    let (layers, dim) = (first.shape()[0], first.shape()[3]);
+    let y_len = first.shape()[2] - x_lens[0];
+
+    let mut out: Array4<f32> = Array4::zeros((layers, caches.len(), max_x + y_len, dim));
+    for (row, (cache, &x_len)) in caches.iter().zip(x_lens).enumerate() {
+        if cache.shape()[2] != x_len + y_len || cache.shape()[0] != layers || cache.shape()[3] != dim {
+            return Err(TtsError::shape(name, format!("row {} has shape {:?}, expected y length {}", row, cache.shape(), y_len)));
         }
+        out.slice_mut(s![.., row, ..x_len, ..]).assign(&cache.slice(s![.., 0, ..x_len, ..]));
+        out.slice_mut(s![.., row, max_x.., ..]).assign(&cache.slice(s![.., 0, x_len.., ..]));
     }
-    println!("{}ms , loop_idx:{}", start_loop.elapsed().as_millis(), loop_idx);
+    Ok(out)
+}
+
+/// [batch, max_x + y_len]：补齐的音素位置是 MASKED，其它是 0
+fn padding_mask(x_lens: &[usize], max_x: usize, y_len: usize) -> Array2<f32> {
+    let mut mask: Array2<f32> = Array2::zeros((x_lens.len(), max_x + y_len));
+    for (row, &x_len) in x_lens.iter().enumerate() {
+        mask.slice_mut(s![row, x_len..max_x]).fill(MASKED);
+    }
+    mask
+}
 
-    *y.get_mut((0, y.shape()[1] - 1)).unwrap() = 0;
+/*
+semantic tokens -> 32k 音频 [-1, 1]
+**/
+pub fn vq_decode(
+    vq_model: &Session,
+    pred_semantic: &[i64],
+    phones_list_unpack2: &Vec<usize>,
+    wav32k_arr: &Array2<f32>,
+) -> Result<Vec<f32>, TtsError> {
+    let hop_length = HOP_LENGTH;
+    let hann_window = hanning(WIN_LENGTH as i64);
 
-    let pred_semantic: Array3<i64> = y.slice(s![..,y.shape()[1]-loop_idx..]).into_owned().insert_axis(Axis(0));
+    let text: Array2<i64> = Array1::from_vec(phones_list_unpack2.clone()).insert_axis(Axis(0)).mapv(|x| x as i64);
+    let pred_semantic: Array3<i64> = Array1::from_vec(pred_semantic.to_vec()).insert_axis(Axis(0)).insert_axis(Axis(0));
 
     let y_len = (pred_semantic.shape()[2] * 2) as i64;
     let y_lengths: Array1<i64> = ndarray::Array1::from(vec![y_len]);
@@ -438,164 +735,113 @@ fn test_infer_wav(
         "refer_mask"=>refer_mask.view(),
         "y_lengths"=>y_lengths.view(),
         "text_lengths"=>text_lengths.view(),
-    ].unwrap();
-    let start_vq_model = Instant::now();
-    let vq_model_out = vq_model.run(vq_model_input);
-    let vq_model_out = vq_model_out.unwrap();
-    let start_vq_model2 = Instant::now();
-
-    let mut audio: Array1<f32> = vq_model_out["audio"].try_extract_tensor::<f32>().unwrap().view().slice(s![0,0,..]).into_owned();
-    println!("time:{}, audio:{:?}", (start_vq_model2 - start_vq_model).as_millis(), audio.shape());
-
-    let mut audio: Vec<f32> = audio.to_vec();
-    let max_audio = {
-        let mut max_v = 0.0;
-        for &v in &audio {
-            let v = num::abs(v);
-            if v > max_v {
-                max_v = v;
-            }
-        }
-        max_v
-    };
-    let mut audio_norm = {
-        if max_audio > 1.0 {
-            let v: Vec<i16> = audio.iter().map(|&x| {
-                ((x / max_audio) * 32768.0) as i16
-            }).collect();
-            v
-        } else {
-            let v: Vec<i16> = audio.iter().map(|&x| {
-                (x * 32768.0) as i16
-            }).collect();
-            v
-        }
-    };
-    // 保存结果
-    FfmpegUtils::decode_data_to_path(&audio_norm, "./make_32k.wav", 32000, 1024).unwrap();
-
-    // SDL 播放
-    // let sdl_context = sdl2::init().unwrap();
-    // let audio_subsystem = sdl_context.audio().unwrap();
-    // let desired_spec = AudioSpecDesired {
-    //     freq: Some(sampling_rate),
-    //     channels: Some(1),
-    //     // mono  -
-    //     samples: None, // default sample size
-    // };
-    //
-    // let device = audio_subsystem.open_queue::<i16, _>(None, &desired_spec).unwrap();
-
-    // let t = audio_norm.len() as f32 / sampling_rate as f32;
-    // println!("all t:{}s", t);
-    //
-    // let mut sum_t = 0.0;
-    // // 分片给
-    // for (i, sample) in audio_norm.chunks(sampling_rate as usize).enumerate() {
-    //     device.queue_audio(&sample).unwrap();
-    //     if i == 0 {
-    //         device.resume();
-    //     }
-    //     let sample_t = sample.len() as f32 / sampling_rate as f32;
-    //     sum_t += sample_t;
-    //     println!("all t:{}/{}s ", sum_t, t);
-    //     let sample_t = (sample_t * 1000.0) as u64;
-    //     std::thread::sleep(Duration::from_millis(sample_t));
-    // }
-    // device.clear();
-}
-
+    ]?;
 
-pub fn infer() {
-    let tokenizer_path = Path::new("../data/tokenizer.json");
-    let bert_model_path = Path::new("./data/bert_model.onnx");
+    let vq_model_out = vq_model.run(vq_model_input)?;
 
-    // cnhubert_base_path
-    let ssl_model_path = Path::new("./data/ssl_model.onnx");
+    let audio: Vec<f32> = extract_tensor!(vq_model_out, "audio", f32, Ix3).slice(s![0,0,..]).to_vec();
+    Ok(audio)
+}
 
-    // sovits_path
-    let vq_model_latent_path = Path::new("./data/vq_model_latent.onnx");
-    let vq_model_path = Path::new("./data/vq_model.onnx");
-    // gpt_path
-    let t2s_first_stage_decoder_path = Path::new("./data/t2s_first_stage_decoder.onnx");
-    let t2s_stage_decoder_path = Path::new("./data/t2s_stage_decoder.onnx");
+/*
+生成音频
+**/
+pub fn wav_maker(
+    t2s_first_stage_decoder: &Session,
+    t2s_stage_decoder: &Session,
+    t2s_device: &Provider,
+    vq_model: &Session,
+    prompt: &Array2<i64>,
+    wav32k_arr: &Array2<f32>,
+    bert_features1: &Array2<f32>,
+    bert_features2: &Array2<f32>,
+    phones_list_unpack1: &Vec<usize>,
+    phones_list_unpack2: &Vec<usize>,
+    params: &SynthesisParams,
+    sampling_rate: i32,
+    metrics: &mut SynthesisMetrics,
+) -> Result<(Vec<i16>, GenerationDiagnostics), TtsError> {
+    let (pred_semantic, diagnostics) = t2s_decode(
+        t2s_first_stage_decoder,
+        t2s_stage_decoder,
+        t2s_device,
+        prompt,
+        bert_features1,
+        bert_features2,
+        phones_list_unpack1,
+        phones_list_unpack2,
+        params,
+        metrics,
+        &mut |_| Ok(()),
+    )?;
+
+    let audio = vq_decode_pcm16(vq_model, &pred_semantic, phones_list_unpack2, wav32k_arr, &params.post_process, sampling_rate, metrics)?;
+    Ok((audio, diagnostics))
+}
 
-    let ch_bert_util = ChBertUtils::init(tokenizer_path.to_str().unwrap());
+/*
+t2s 生成的 pred_semantic -> 后处理 -> pcm16，没有 token 时返回空音频
+**/
+pub fn vq_decode_pcm16(
+    vq_model: &Session,
+    pred_semantic: &[i64],
+    phones_list_unpack2: &Vec<usize>,
+    wav32k_arr: &Array2<f32>,
+    post_process: &PostProcessConfig,
+    sampling_rate: i32,
+    metrics: &mut SynthesisMetrics,
+) -> Result<Vec<i16>, TtsError> {
+    if pred_semantic.is_empty() {
+        return Ok(vec![]);
+    }
+    let start_vocoder = Instant::now();
+    let audio = vq_decode(vq_model, pred_semantic, phones_list_unpack2, wav32k_arr)?;
+    metrics.vocoder_ms += elapsed_ms(start_vocoder);
 
-    let sampling_rate: i32 = 32000;
+    Ok(post_process.process(audio, sampling_rate))
+}
 
-    let zero_sampling_len = (sampling_rate as f32 * 0.3) as usize;
-    let zero_wav: Array1<f32> = Array1::zeros((zero_sampling_len, ));
-    println!("zero_wav:{:?}", zero_wav.shape());
 
-    let start = Instant::now();
+pub fn infer() {
+    let engine = TtsEngine::from_model_dir("./data").unwrap();
 
     // 参考音色音频文件
     let ref_wav_path = "./data/xxx.wav";
     // 参考音色音频对应的文字
-    let prompt_text = "我注意到了，没有人说图书馆，我刚到广州就因为广州图书馆住在珠江新城附近。".to_string();
-
-    let wav16k: Vec<i16> = FfmpegUtils::decode_path_to_datas(ref_wav_path, 16000).unwrap();
-    let wav32k: Vec<i16> = FfmpegUtils::decode_path_to_datas(ref_wav_path, 32000).unwrap();
-
-    let wav16k: Vec<f32> = wav16k.iter().map(|&x| x as f32 / 32768.0).collect();
-    let wav32k: Vec<f32> = wav32k.iter().map(|&x| x as f32 / 32768.0).collect();
-
-
-    println!("time_t:{} ms ,16k len:{}, 32k len:{}", start.elapsed().as_millis(), wav16k.len(), wav32k.len());
-
-    let wav16k_arr: Array1<f32> = Array1::from_vec(wav16k);
-    let wav32k_arr: Array1<f32> = Array1::from_vec(wav32k);
+    let prompt_text = "我注意到了，没有人说图书馆，我刚到广州就因为广州图书馆住在珠江新城附近。";
+    let reference = engine.reference_voice(ref_wav_path, prompt_text).unwrap();
 
-    let wav16k_sum = wav16k_arr.sum();
-    let wav32k_sum = wav32k_arr.sum();
+    let text = "每个人的理想不一样，扎出来的风筝也不一样。所有的风筝中，要数小音乐家根子的最棒了，那是一架竖琴。让她到天上去好好想想吧！哈，风筝的后脑勺上还拖着一条马尾巴似的长辫子！在地面上，我们一边放线一边跑着，手里的线越放越长，风筝也带着我们的理想越飞越远，越飞越高如果把眼前的一池荷花看作一大幅活的画，那画家的本领可真了不起。";
 
-    println!("wav16k_sum:{},wav32k_sum:{}", wav16k_sum, wav32k_sum);
+    let audio = engine.synthesize(text, &reference, &SynthesisParams::default()).unwrap();
+    println!("{}", audio.metrics);
 
-    let wav16k_arr: Array2<f32> = ndarray::concatenate(Axis(0), &[wav16k_arr.view(), zero_wav.view()]).unwrap().insert_axis(Axis(0));
-    let wav32k_arr: Array2<f32> = wav32k_arr.insert_axis(Axis(0));
-    println!("wav16k_arr:{:?} ", wav16k_arr.shape());
-
-    let text = "每个人的理想不一样，扎出来的风筝也不一样。所有的风筝中，要数小音乐家根子的最棒了，那是一架竖琴。让她到天上去好好想想吧！哈，风筝的后脑勺上还拖着一条马尾巴似的长辫子！在地面上，我们一边放线一边跑着，手里的线越放越长，风筝也带着我们的理想越飞越远，越飞越高如果把眼前的一池荷花看作一大幅活的画，那画家的本领可真了不起。".to_string();
-
-
-    let text_util = TextUtils::init(
-        "./data/eng_dict.json",
-        "./data/rep_map.json",
-        "./data/model.npz",
-        "./data/PHRASES_DICT.json",
-        "./data/PINYIN_DICT.json",
-    ).unwrap();
-
-    let texts = text_util.lang_seg.cut_texts(&text, prompt_text.chars().count());
-
-    println!("texts:{}", texts.join("\n"));
-
-    let start = Instant::now();
-    let (mut phones_list, word2ph_list, lang_list, norm_text_list) = text_util.get_cleaned_text_final(&prompt_text);
-    println!("time_t2:{} ms", start.elapsed().as_millis());
-
-    let bert_model = ChBertUtils::load_model(bert_model_path.to_str().unwrap()).unwrap();
-    let ssl_model = ChBertUtils::load_model(ssl_model_path.to_str().unwrap()).unwrap();
-    let vq_model_latent = ChBertUtils::load_model(vq_model_latent_path.to_str().unwrap()).unwrap();
-    let t2s_first_stage_decoder = ChBertUtils::load_model(t2s_first_stage_decoder_path.to_str().unwrap()).unwrap();
-    let t2s_stage_decoder = ChBertUtils::load_model(t2s_stage_decoder_path.to_str().unwrap()).unwrap();
-    let vq_model = ChBertUtils::load_model(vq_model_path.to_str().unwrap()).unwrap();
-
-    let start = Instant::now();
-    let (bert_features1, phones_list_unpack1, norm_text_str1) = ChBertUtils::get_bert_features(&ch_bert_util.tokenizer, &bert_model, &mut phones_list, &word2ph_list, &norm_text_list, &lang_list);
-    println!("norm_text_str1:{},phones_list_unpack1:{},time_t3:{} ms", norm_text_str1, phones_list_unpack1.len(), start.elapsed().as_millis());
-
-    println!("bert_features1.shape:{:?}", bert_features1.shape());
-
-    for text in texts {
-        let (mut phones_list, word2ph_list, lang_list, norm_text_list) = text_util.get_cleaned_text_final(&text);
-        let (bert_features2, phones_list_unpack2, norm_text_str2) = ChBertUtils::get_bert_features(&ch_bert_util.tokenizer, &bert_model, &mut phones_list, &word2ph_list, &norm_text_list, &lang_list);
-
-        println!("phones_list_unpack2:{:?}", phones_list_unpack2);
-        println!("text:{} ->{}", text, norm_text_str2);
+    // 保存结果
+    audio.save("./make_32k.wav").unwrap();
+}
 
-        test_infer_wav(&ssl_model, &vq_model_latent, &t2s_first_stage_decoder, &t2s_stage_decoder, &vq_model, &wav16k_arr, &wav32k_arr, &bert_features1, &bert_features2, &phones_list_unpack1, &phones_list_unpack2);
-        break;
-    }
+#[test]
+fn test_pad_cache() {
+    let a: Array4<f32> = Array4::from_shape_fn((1, 1, 3, 1), |(_, _, i, _)| i as f32 + 1.0);
+    let b: Array4<f32> = Array4::from_shape_fn((1, 1, 4, 1), |(_, _, i, _)| i as f32 + 10.0);
+    // x_len 2 / 3，y 部分都是 1
+    let out = pad_cache("k", &[&a, &b], &[2, 3]).unwrap();
+    assert_eq!(out.shape(), &[1, 2, 4, 1]);
+    assert_eq!(out.slice(s![0, 0, .., 0]).to_vec(), vec![1.0, 2.0, 0.0, 3.0]);
+    assert_eq!(out.slice(s![0, 1, .., 0]).to_vec(), vec![10.0, 11.0, 12.0, 13.0]);
+
+    let mask = padding_mask(&[2, 3], 3, 1);
+    assert_eq!(mask.row(0).to_vec(), vec![0.0, 0.0, MASKED, 0.0]);
+    assert!(mask.row(1).iter().all(|&m| m == 0.0));
+
+    let mut y = GrowingInput::new(Array2::from_shape_vec((2, 2), vec![1i64, 2, 3, 4]).unwrap().view(), 2);
+    let ptr = y.data.as_ptr();
+    y.push(&[5, 6]).unwrap();
+    y.push(&[7, 8]).unwrap();
+    assert_eq!(y.data, vec![1, 2, 5, 7, 3, 4, 6, 8]);
+    assert_eq!(y.data.as_ptr(), ptr);
+    // 超过预留的步数报错，不重新分配
+    let mut full = GrowingInput::new(Array2::from_shape_vec((1, 1), vec![1i64]).unwrap().view(), 0);
+    assert!(full.push(&[2]).is_err());
+    assert!(y.tensor(vec![2, 3]).is_err());
 }
@@ -0,0 +1,490 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::process::exit;
+use std::time::Instant;
+use clap::{Args, Parser, Subcommand};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use rs_tokenizer::error::TtsError;
+use rs_tokenizer::pause::PauseConfig;
+use rs_tokenizer::post_process::Loudness;
+use rs_tokenizer::reference_voice::{ReferenceClip, ReferenceVoice};
+use rs_tokenizer::text::symbols::SYMBOLS;
+use rs_tokenizer::text_utils::TextUtils;
+use rs_tokenizer::transcriber::AsrConfig;
+use rs_tokenizer::tts_engine::{SynthesisParams, TtsConfig, TtsEngine};
+
+/// GPT-SoVITS 命令行：合成、批量合成、查看音素
+#[derive(Parser, Debug)]
+#[command(name = "sovits-cli")]
+struct Cli {
+    #[command(flatten)]
+    paths: PathArgs,
+    #[command(subcommand)]
+    command: Command,
+}
+
+/// 模型、字典路径：先读 --config，再用 --model-dir，单独指定的文件最后覆盖
+#[derive(Args, Debug)]
+struct PathArgs {
+    /// json 配置文件，字段同 TtsConfig
+    #[arg(long, global = true)]
+    config: Option<String>,
+    /// onnx 模型和字典所在目录，默认 ./data
+    #[arg(long, global = true)]
+    model_dir: Option<String>,
+    #[arg(long, global = true)]
+    tokenizer: Option<String>,
+    #[arg(long, global = true)]
+    eng_dict: Option<String>,
+    #[arg(long, global = true)]
+    rep_map: Option<String>,
+    /// model.npz
+    #[arg(long, global = true)]
+    ph_model: Option<String>,
+    #[arg(long, global = true)]
+    phrases_dict: Option<String>,
+    #[arg(long, global = true)]
+    pinyin_dict: Option<String>,
+    /// 日文漢字读音词典 ja_dict.json
+    #[arg(long, global = true)]
+    ja_dict: Option<String>,
+    /// 语音识别模型，没有 --prompt-text 时识别参考音频
+    #[arg(long, global = true, requires = "asr_tokens")]
+    asr_model: Option<String>,
+    /// 语音识别模型的 tokens.txt
+    #[arg(long, global = true)]
+    asr_tokens: Option<String>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// 合成一段文字
+    Synth {
+        text: Option<String>,
+        #[arg(long)]
+        text_file: Option<String>,
+        /// 输出文件，格式由后缀决定：wav、mp3
+        #[arg(short, long)]
+        output: String,
+        #[command(flatten)]
+        voice: VoiceArgs,
+        #[command(flatten)]
+        sampling: SamplingArgs,
+    },
+    /// 按 jsonl / csv 清单批量合成：{id, text, ref_wav, prompt_text, params}
+    Batch {
+        manifest: String,
+        #[arg(long, default_value = "./out")]
+        out_dir: String,
+        /// 输出格式
+        #[arg(long, default_value = "wav")]
+        format: String,
+        // 清单里没写 ref_wav 的行用这个
+        #[command(flatten)]
+        voice: VoiceArgs,
+        #[command(flatten)]
+        sampling: SamplingArgs,
+    },
+    /// 打印 get_cleaned_text_final 的结果
+    Phonemize {
+        text: Option<String>,
+        #[arg(long)]
+        text_file: Option<String>,
+    },
+}
+
+#[derive(Args, Debug)]
+struct VoiceArgs {
+    /// 参考音频
+    #[arg(long)]
+    ref_wav: Option<String>,
+    /// 参考音频的文字
+    #[arg(long, default_value = "")]
+    prompt_text: String,
+    /// ReferenceVoice::save 保存的音色文件，代替 --ref-wav
+    #[arg(long)]
+    voice_file: Option<String>,
+    /// --ref-wav 的权重，有 --extra-ref 时才有用
+    #[arg(long, default_value_t = 1.0)]
+    ref_weight: f32,
+    /// 额外的参考音频，只参与音色混合：path 或 path:weight，可以写多次
+    #[arg(long)]
+    extra_ref: Vec<String>,
+}
+
+/// 清单里的 params 写错名字直接报错，不悄悄忽略
+#[derive(Args, Debug, Default, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SamplingArgs {
+    #[arg(long)]
+    top_k: Option<i64>,
+    #[arg(long)]
+    top_p: Option<f32>,
+    #[arg(long)]
+    temperature: Option<f32>,
+    #[arg(long)]
+    repetition_penalty: Option<f32>,
+    #[arg(long)]
+    seed: Option<u64>,
+    /// 分段之间的停顿：秒，所有边界一样长
+    #[arg(long)]
+    segment_silence: Option<f32>,
+    /// 换行处的停顿：秒
+    #[arg(long)]
+    pause_paragraph: Option<f32>,
+    /// 。！？ 处的停顿：秒
+    #[arg(long)]
+    pause_sentence: Option<f32>,
+    /// ，、 处的停顿：秒
+    #[arg(long)]
+    pause_comma: Option<f32>,
+    /// 一次送进 t2s 的分段数
+    #[arg(long)]
+    batch_size: Option<usize>,
+    /// 每段归一化到这个响度：LUFS
+    #[arg(long, allow_hyphen_values = true)]
+    loudness_lufs: Option<f32>,
+    /// 裁掉每段首尾低于这个电平的静音：dBFS
+    #[arg(long, allow_hyphen_values = true)]
+    trim_silence_db: Option<f32>,
+    /// 每段首尾淡入淡出：毫秒
+    #[arg(long)]
+    fade_ms: Option<f32>,
+    #[arg(long)]
+    remove_dc: Option<bool>,
+    /// 语速 0.3 - 2.0
+    #[arg(long)]
+    speed: Option<f32>,
+    /// 变调：半音
+    #[arg(long, allow_hyphen_values = true)]
+    pitch_semitones: Option<f32>,
+    /// 音量：dB
+    #[arg(long, allow_hyphen_values = true)]
+    volume_db: Option<f32>,
+    /// 不用参考文字合成，只克隆音色
+    #[arg(long)]
+    ref_free: Option<bool>,
+}
+
+/// 清单的一行
+#[derive(Debug, Deserialize)]
+struct ManifestRow {
+    /// 输出文件名，不能带路径
+    id: String,
+    text: String,
+    ref_wav: Option<String>,
+    prompt_text: Option<String>,
+    #[serde(default)]
+    params: SamplingArgs,
+}
+
+#[derive(Debug, Serialize)]
+struct RowReport {
+    id: String,
+    ok: bool,
+    output: Option<String>,
+    duration: f32,
+    elapsed: f32,
+    error: Option<String>,
+}
+
+impl PathArgs {
+    fn tts_config(&self) -> Result<TtsConfig, TtsError> {
+        let mut config = match (&self.config, &self.model_dir) {
+            (Some(config_path), _) => TtsConfig::from_file(config_path)?,
+            (None, Some(model_dir)) => TtsConfig::from_model_dir(model_dir),
+            (None, None) => TtsConfig::default(),
+        };
+        let overrides = [
+            (&self.tokenizer, &mut config.tokenizer_path),
+            (&self.eng_dict, &mut config.eng_dict_path),
+            (&self.rep_map, &mut config.rep_map_path),
+            (&self.ph_model, &mut config.ph_model_path),
+            (&self.phrases_dict, &mut config.phrases_dict_path),
+            (&self.pinyin_dict, &mut config.pinyin_dict_path),
+            (&self.ja_dict, &mut config.ja_dict_path),
+        ];
+        for (value, path) in overrides {
+            if let Some(value) = value {
+                *path = value.clone();
+            }
+        }
+        if let (Some(model_path), Some(tokens_path)) = (&self.asr_model, &self.asr_tokens) {
+            config.asr = Some(AsrConfig { model_path: model_path.clone(), tokens_path: tokens_path.clone() });
+        }
+        Ok(config)
+    }
+}
+
+impl SamplingArgs {
+    /// 没指定的用 base 的
+    fn apply(&self, base: &SynthesisParams) -> SynthesisParams {
+        let mut params = base.clone();
+        if let Some(top_k) = self.top_k {
+            params.top_k = top_k;
+        }
+        if let Some(top_p) = self.top_p {
+            params.top_p = top_p;
+        }
+        if let Some(temperature) = self.temperature {
+            params.temperature = temperature;
+        }
+        if let Some(repetition_penalty) = self.repetition_penalty {
+            params.repetition_penalty = repetition_penalty;
+        }
+        if self.seed.is_some() {
+            params.seed = self.seed;
+        }
+        if let Some(segment_silence) = self.segment_silence {
+            params.pauses = PauseConfig::uniform(segment_silence);
+        }
+        if let Some(paragraph) = self.pause_paragraph {
+            params.pauses.paragraph = paragraph;
+        }
+        if let Some(sentence) = self.pause_sentence {
+            params.pauses.sentence = sentence;
+        }
+        if let Some(comma) = self.pause_comma {
+            params.pauses.comma = comma;
+        }
+        if let Some(batch_size) = self.batch_size {
+            params.batch_size = batch_size.max(1);
+        }
+        if let Some(lufs) = self.loudness_lufs {
+            params.post_process.loudness = Some(Loudness::Lufs(lufs));
+        }
+        if self.trim_silence_db.is_some() {
+            params.post_process.trim_silence_db = self.trim_silence_db;
+        }
+        if let Some(fade_ms) = self.fade_ms {
+            params.post_process.fade_ms = fade_ms;
+        }
+        if let Some(remove_dc) = self.remove_dc {
+            params.post_process.remove_dc = remove_dc;
+        }
+        if let Some(speed) = self.speed {
+            params.speed = speed;
+        }
+        if let Some(pitch_semitones) = self.pitch_semitones {
+            params.pitch_semitones = pitch_semitones;
+        }
+        if let Some(volume_db) = self.volume_db {
+            params.volume_db = volume_db;
+        }
+        if let Some(ref_free) = self.ref_free {
+            params.ref_free = ref_free;
+        }
+        params
+    }
+}
+
+fn read_text(text: &Option<String>, text_file: &Option<String>) -> Result<String, TtsError> {
+    match (text, text_file) {
+        (_, Some(text_file)) => Ok(fs::read_to_string(text_file)?),
+        (Some(text), None) => Ok(text.clone()),
+        (None, None) => Err(TtsError::TextFrontend("text or --text-file is required".to_string())),
+    }
+}
+
+/// jsonl 每行一个对象；csv 第一行是表头，id,text,ref_wav,prompt_text 以外的列都当作 params
+fn read_manifest(manifest_path: &str) -> Result<Vec<ManifestRow>, TtsError> {
+    let config_error = |message: String| TtsError::Config { path: manifest_path.to_string(), message };
+    let mut values: Vec<Value> = vec![];
+
+    if manifest_path.ends_with(".csv") {
+        let mut reader = csv::Reader::from_path(manifest_path).map_err(|e| config_error(e.to_string()))?;
+        for record in reader.deserialize::<HashMap<String, String>>() {
+            let record = record.map_err(|e| config_error(e.to_string()))?;
+            let mut row = serde_json::Map::new();
+            let mut params = serde_json::Map::new();
+            for (k, v) in record {
+                if v.is_empty() {
+                    continue;
+                }
+                match k.as_str() {
+                    "id" | "text" | "ref_wav" | "prompt_text" => {
+                        row.insert(k, Value::String(v));
+                    }
+                    _ => {
+                        let v = serde_json::from_str::<Value>(&v).unwrap_or(Value::String(v));
+                        params.insert(k, v);
+                    }
+                }
+            }
+            row.insert("params".to_string(), Value::Object(params));
+            values.push(Value::Object(row));
+        }
+    } else {
+        for (i, line) in fs::read_to_string(manifest_path)?.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let value = serde_json::from_str(line).map_err(|e| config_error(format!("line {}: {}", i + 1, e)))?;
+            values.push(value);
+        }
+    }
+
+    values.into_iter()
+        .enumerate()
+        .map(|(i, v)| serde_json::from_value(v).map_err(|e| config_error(format!("row {}: {}", i + 1, e))))
+        .collect()
+}
+
+fn load_voice(engine: &TtsEngine, voice: &VoiceArgs) -> Result<ReferenceVoice, TtsError> {
+    match (&voice.voice_file, &voice.ref_wav) {
+        (Some(voice_file), _) => ReferenceVoice::load(voice_file),
+        (None, Some(ref_wav)) if voice.extra_ref.is_empty() => engine.reference_voice(ref_wav, &voice.prompt_text),
+        (None, Some(ref_wav)) => {
+            let mut clips = vec![ReferenceClip { wav_path: ref_wav.clone(), prompt_text: Some(voice.prompt_text.clone()), weight: voice.ref_weight }];
+            clips.extend(voice.extra_ref.iter().map(|extra| parse_extra_ref(extra)));
+            engine.reference_voice_clips(&clips)
+        }
+        (None, None) => Err(TtsError::VoiceFile("--ref-wav or --voice-file is required".to_string())),
+    }
+}
+
+// 不为空，不含路径分隔符、盘符和 ..
+fn is_file_stem(id: &str) -> bool {
+    !id.trim().is_empty() && !id.contains("..") && !id.contains(|c: char| c == '/' || c == '\\' || c == ':' || c == '\0')
+}
+
+// "path:weight"，冒号后面不是数字的整个当路径（比如 Windows 的盘符）
+fn parse_extra_ref(extra: &str) -> ReferenceClip {
+    let (wav_path, weight) = match extra.rsplit_once(':').and_then(|(path, weight)| weight.parse::<f32>().ok().map(|w| (path, w))) {
+        Some((path, weight)) => (path.to_string(), weight),
+        None => (extra.to_string(), 1.0),
+    };
+    ReferenceClip { wav_path, prompt_text: None, weight }
+}
+
+fn synth(config: TtsConfig, text: String, output: &str, voice: &VoiceArgs, sampling: &SamplingArgs) -> Result<(), TtsError> {
+    let engine = TtsEngine::init(config)?;
+    let reference = load_voice(&engine, voice)?;
+    let params = sampling.apply(&SynthesisParams::default());
+
+    let start = Instant::now();
+    let audio = engine.synthesize(&text, &reference, &params)?;
+    let elapsed = start.elapsed().as_secs_f32();
+    audio.save(output)?;
+    println!("{}: {:.2}s audio in {:.2}s", output, audio.duration(), elapsed);
+    Ok(())
+}
+
+fn batch(config: TtsConfig, manifest: &str, out_dir: &str, format: &str, voice: &VoiceArgs, sampling: &SamplingArgs) -> Result<bool, TtsError> {
+    let rows = read_manifest(manifest)?;
+    let engine = TtsEngine::init(config)?;
+    let base_params = sampling.apply(&SynthesisParams::default());
+    fs::create_dir_all(out_dir)?;
+
+    // 同一个参考音频只跑一次 ssl_model
+    let mut voices: HashMap<(String, String), ReferenceVoice> = HashMap::new();
+    let default_voice = match (&voice.voice_file, &voice.ref_wav) {
+        (None, None) => None,
+        _ => Some(load_voice(&engine, voice)?),
+    };
+
+    let mut reports: Vec<RowReport> = vec![];
+    for row in &rows {
+        let start = Instant::now();
+        let output = Path::new(out_dir).join(format!("{}.{}", row.id, format)).to_string_lossy().to_string();
+
+        let result = (|| -> Result<f32, TtsError> {
+            // 写到 out_dir 外面去
+            if !is_file_stem(&row.id) {
+                return Err(TtsError::Config { path: manifest.to_string(), message: format!("id {:?} must be a plain file name", row.id) });
+            }
+            let reference = match &row.ref_wav {
+                Some(ref_wav) => {
+                    let prompt_text = row.prompt_text.clone().unwrap_or_default();
+                    let key = (ref_wav.clone(), prompt_text.clone());
+                    if !voices.contains_key(&key) {
+                        voices.insert(key.clone(), engine.reference_voice(ref_wav, &prompt_text)?);
+                    }
+                    &voices[&key]
+                }
+                None => default_voice.as_ref()
+                    .ok_or_else(|| TtsError::VoiceFile(format!("row {} has no ref_wav and no default voice", row.id)))?,
+            };
+            let params = row.params.apply(&base_params);
+            let audio = engine.synthesize(&row.text, reference, &params)?;
+            audio.save(&output)?;
+            Ok(audio.duration())
+        })();
+
+        let elapsed = start.elapsed().as_secs_f32();
+        let report = match result {
+            Ok(duration) => RowReport { id: row.id.clone(), ok: true, output: Some(output), duration, elapsed, error: None },
+            Err(e) => RowReport { id: row.id.clone(), ok: false, output: None, duration: 0.0, elapsed, error: Some(e.to_string()) },
+        };
+        match &report.error {
+            None => println!("[ok]   {} {:.2}s audio in {:.2}s", report.id, report.duration, report.elapsed),
+            Some(e) => println!("[fail] {} {}", report.id, e),
+        }
+        reports.push(report);
+    }
+
+    let failed = reports.iter().filter(|r| !r.ok).count();
+    let audio_seconds: f32 = reports.iter().map(|r| r.duration).sum();
+    let elapsed_seconds: f32 = reports.iter().map(|r| r.elapsed).sum();
+    let summary = json!({
+        "total": reports.len(),
+        "ok": reports.len() - failed,
+        "failed": failed,
+        "audio_seconds": audio_seconds,
+        "elapsed_seconds": elapsed_seconds,
+        "rtf": if audio_seconds > 0.0 { elapsed_seconds / audio_seconds } else { 0.0 },
+        "rows": reports,
+    });
+    let summary_path = Path::new(out_dir).join("summary.json");
+    fs::write(&summary_path, serde_json::to_string_pretty(&summary).unwrap())?;
+    println!("{} ok, {} failed, {:.2}s audio in {:.2}s, summary: {}",
+             reports.len() - failed, failed, audio_seconds, elapsed_seconds, summary_path.display());
+    Ok(failed == 0)
+}
+
+fn phonemize(config: TtsConfig, text: String) -> Result<(), TtsError> {
+    let text_util = TextUtils::init(
+        &config.eng_dict_path,
+        &config.rep_map_path,
+        &config.ph_model_path,
+        &config.phrases_dict_path,
+        &config.pinyin_dict_path,
+        &config.ja_dict_path,
+    )?;
+    let (phones_list, word2ph_list, lang_list, norm_text_list) = text_util.get_cleaned_text_final(&text);
+    for i in 0..phones_list.len() {
+        let symbols: Vec<&str> = phones_list[i].iter().map(|&p| SYMBOLS.get(p).copied().unwrap_or("?")).collect();
+        println!("[{}] {}", lang_list[i], norm_text_list[i]);
+        println!("  phones:  {}", symbols.join(" "));
+        println!("  ids:     {:?}", phones_list[i]);
+        println!("  word2ph: {:?}", word2ph_list[i]);
+    }
+    Ok(())
+}
+
+fn main() {
+    let cli = Cli::parse();
+
+    let result = cli.paths.tts_config().and_then(|config| match &cli.command {
+        Command::Synth { text, text_file, output, voice, sampling } => {
+            synth(config, read_text(text, text_file)?, output, voice, sampling).map(|_| true)
+        }
+        Command::Batch { manifest, out_dir, format, voice, sampling } => {
+            batch(config, manifest, out_dir, format, voice, sampling)
+        }
+        Command::Phonemize { text, text_file } => {
+            phonemize(config, read_text(text, text_file)?).map(|_| true)
+        }
+    });
+
+    match result {
+        Ok(true) => {}
+        Ok(false) => exit(2),
+        Err(e) => {
+            eprintln!("error: {}", e);
+            exit(1);
+        }
+    }
+}
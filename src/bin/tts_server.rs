@@ -0,0 +1,647 @@
+use std::collections::{HashMap, VecDeque};
+use std::io::Read;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{channel, Receiver};
+use std::time::Duration;
+use clap::Parser;
+use log::{error, info, warn};
+use serde_json::{json, Value};
+use tiny_http::{Header, Method, Request, Response, Server, StatusCode};
+use rs_tokenizer::error::TtsError;
+use rs_tokenizer::post_process::Loudness;
+use rs_tokenizer::reference_voice::ReferenceVoice;
+use rs_tokenizer::token_stream::TokenStreamConfig;
+use rs_tokenizer::tts_engine::{SovitsWeights, SynthesisParams, TtsConfig, TtsEngine};
+use rs_tokenizer::wav::{pcm16_to_bytes, wav_header};
+use rs_tokenizer::worker_pool::{PoolConfig, WorkerPool};
+
+/// GPT-SoVITS http 服务，接口和 api.py 一致
+#[derive(Parser, Debug)]
+#[command(name = "tts-server")]
+struct Args {
+    /// onnx 模型和字典所在目录
+    #[arg(long, default_value = "./data")]
+    model_dir: String,
+    /// json 配置文件，字段同 TtsConfig，包括 execution；指定了就不用 --model-dir
+    #[arg(long)]
+    config: Option<String>,
+    #[arg(short = 'a', long, default_value = "127.0.0.1")]
+    bind_addr: String,
+    #[arg(short = 'p', long, default_value_t = 9880)]
+    port: u16,
+    /// 默认参考音频
+    #[arg(long = "default_refer_path", short = 'r')]
+    default_refer_path: Option<String>,
+    /// 默认参考音频的文字
+    #[arg(long = "default_refer_text", short = 't')]
+    default_refer_text: Option<String>,
+    /// 同时合成的请求数
+    #[arg(long, default_value_t = 2)]
+    workers: usize,
+    /// 排队等待合成的请求数，满了返回 503
+    #[arg(long, default_value_t = 16)]
+    queue_size: usize,
+    /// 处理 http 连接的线程数
+    #[arg(long, default_value_t = 8)]
+    http_threads: usize,
+    /// 请求里直接带 refer_wav_path 的音色最多缓存几个，0: 不缓存
+    #[arg(long, default_value_t = 32)]
+    voice_cache_size: usize,
+}
+
+/// POST body 最大字节数，超过返回 413
+const MAX_BODY_BYTES: usize = 1 << 20;
+
+struct Voice {
+    refer_wav_path: String,
+    prompt_text: String,
+    voice: Arc<ReferenceVoice>,
+}
+
+/// 最近用过的放在最后，满了去掉最前面的
+struct VoiceCache {
+    capacity: usize,
+    entries: VecDeque<Voice>,
+}
+
+impl VoiceCache {
+    fn new(capacity: usize) -> Self {
+        VoiceCache { capacity, entries: VecDeque::with_capacity(capacity) }
+    }
+
+    fn get(&mut self, refer_wav_path: &str, prompt_text: &str) -> Option<Arc<ReferenceVoice>> {
+        let index = self.entries.iter().position(|v| v.refer_wav_path == refer_wav_path && v.prompt_text == prompt_text)?;
+        let voice = self.entries.remove(index)?;
+        let cached = Arc::clone(&voice.voice);
+        self.entries.push_back(voice);
+        Some(cached)
+    }
+
+    fn insert(&mut self, voice: Voice) {
+        self.entries.retain(|v| v.refer_wav_path != voice.refer_wav_path || v.prompt_text != voice.prompt_text);
+        self.entries.push_back(voice);
+        while self.entries.len() > self.capacity {
+            self.entries.pop_front();
+        }
+    }
+
+    fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
+
+/// http 线程之间共享，合成都交给 pool
+struct ServerState {
+    pool: WorkerPool,
+    /// 注册的音色，换 SoVITS 权重时重新生成
+    voices: Mutex<HashMap<String, Voice>>,
+    /// 请求里直接带 refer_wav_path 的，按路径和文字缓存，有上限
+    request_voices: Mutex<VoiceCache>,
+    /// 正在生成的参考音色：(路径, 文字) -> 锁，同一个参考音频同时只生成一次
+    in_flight: Mutex<HashMap<(String, String), Arc<Mutex<()>>>>,
+    default_voice: Mutex<Option<String>>,
+}
+
+enum Reply {
+    Json(Value),
+    Wav(Vec<u8>),
+    Stream { text: String, voice: Arc<ReferenceVoice>, params: SynthesisParams },
+}
+
+/// 返回给客户端的错误：{"code": 400, "message": "..."}
+struct ApiError {
+    status: u16,
+    message: String,
+}
+
+impl ApiError {
+    fn bad_request(message: impl ToString) -> Self {
+        ApiError { status: 400, message: message.to_string() }
+    }
+
+    fn body_too_large() -> Self {
+        ApiError { status: 413, message: format!("body larger than {} bytes", MAX_BODY_BYTES) }
+    }
+}
+
+impl From<TtsError> for ApiError {
+    fn from(e: TtsError) -> Self {
+        let status = match e {
+            TtsError::AudioDecode(_) | TtsError::TextFrontend(_) | TtsError::VoiceFile(_) | TtsError::InvalidReference(_) => 400,
+            TtsError::QueueFull { .. } => 503,
+            TtsError::Cancelled { .. } => 504,
+            _ => 500,
+        };
+        ApiError { status, message: e.to_string() }
+    }
+}
+
+impl ServerState {
+    fn sample_rate(&self) -> i32 {
+        self.pool.engine().read().unwrap().config.sampling_rate
+    }
+
+    fn register_voice(&self, name: &str, refer_wav_path: &str, prompt_text: &str) -> Result<Arc<ReferenceVoice>, TtsError> {
+        let cached = || self.voices.lock().unwrap().get(name)
+            .filter(|v| v.refer_wav_path == refer_wav_path && v.prompt_text == prompt_text)
+            .map(|v| Arc::clone(&v.voice));
+        self.build_once(refer_wav_path, prompt_text, cached, |voice| {
+            self.voices.lock().unwrap().insert(name.to_string(), voice);
+        })
+    }
+
+    /// 请求里带的参考音频不注册，放到有上限的缓存里
+    fn request_voice(&self, refer_wav_path: &str, prompt_text: &str) -> Result<Arc<ReferenceVoice>, TtsError> {
+        let cached = || self.request_voices.lock().unwrap().get(refer_wav_path, prompt_text);
+        self.build_once(refer_wav_path, prompt_text, cached, |voice| {
+            self.request_voices.lock().unwrap().insert(voice);
+        })
+    }
+
+    /// 缓存里没有才生成；同一个参考音频正在生成时等它生成完，再查一次缓存
+    fn build_once(
+        &self,
+        refer_wav_path: &str,
+        prompt_text: &str,
+        cached: impl Fn() -> Option<Arc<ReferenceVoice>>,
+        insert: impl FnOnce(Voice),
+    ) -> Result<Arc<ReferenceVoice>, TtsError> {
+        if let Some(voice) = cached() {
+            return Ok(voice);
+        }
+
+        let key = (refer_wav_path.to_string(), prompt_text.to_string());
+        let lock = Arc::clone(self.in_flight.lock().unwrap().entry(key.clone()).or_default());
+        let result = {
+            let _building = lock.lock().unwrap();
+            match cached() {
+                Some(voice) => Ok(voice),
+                None => self.build_voice(refer_wav_path, prompt_text).map(|voice| {
+                    let voice = Arc::new(voice);
+                    insert(Voice {
+                        refer_wav_path: refer_wav_path.to_string(),
+                        prompt_text: prompt_text.to_string(),
+                        voice: Arc::clone(&voice),
+                    });
+                    voice
+                }),
+            }
+        };
+
+        // 没有别人在等了就删掉
+        let mut in_flight = self.in_flight.lock().unwrap();
+        drop(lock);
+        if in_flight.get(&key).map_or(false, |lock| Arc::strong_count(lock) == 1) {
+            in_flight.remove(&key);
+        }
+        result
+    }
+
+    /// 参考音色也走 pool，受 ssl 的并发限制
+    fn build_voice(&self, refer_wav_path: &str, prompt_text: &str) -> Result<ReferenceVoice, TtsError> {
+        let (refer_wav_path, prompt_text) = (refer_wav_path.to_string(), prompt_text.to_string());
+        self.pool.submit(move |engine, _| engine.reference_voice(&refer_wav_path, &prompt_text))?.wait()
+    }
+
+    fn voice(&self, name: &str) -> Option<Arc<ReferenceVoice>> {
+        self.voices.lock().unwrap().get(name).map(|v| Arc::clone(&v.voice))
+    }
+
+    /// 换了 vq_model_latent 之后所有音色都要重新生成；用还没换上的 sovits 生成，只拿读锁，不走 pool
+    fn rebuild_voices(&self, engine: &TtsEngine, sovits: &SovitsWeights) -> Result<HashMap<String, Voice>, TtsError> {
+        let voices: Vec<(String, String, String)> = self.voices.lock().unwrap().iter()
+            .map(|(name, v)| (name.clone(), v.refer_wav_path.clone(), v.prompt_text.clone()))
+            .collect();
+        voices.into_iter()
+            .map(|(name, refer_wav_path, prompt_text)| {
+                let voice = Arc::new(engine.reference_voice_with(sovits, &refer_wav_path, &prompt_text)?);
+                Ok((name, Voice { refer_wav_path, prompt_text, voice }))
+            })
+            .collect()
+    }
+
+    /// GPT、SoVITS 先都加载、检查好，音色也用新的 SoVITS 生成好，这时只拿读锁，合成请求照常跑；
+    /// 最后拿写锁（等正在合成的请求结束）只做替换，任何一步失败模型都不变
+    fn set_model(&self, gpt: Option<&str>, sovits: Option<&str>) -> Result<(), TtsError> {
+        let (gpt, sovits) = {
+            let engine = self.pool.engine().read().unwrap();
+            let gpt = gpt.map(|model_dir| engine.load_gpt_weights(model_dir)).transpose()?;
+            let sovits = match sovits {
+                Some(model_dir) => {
+                    let sovits = engine.load_sovits_weights(model_dir)?;
+                    let rebuilt = self.rebuild_voices(&engine, &sovits)?;
+                    Some((sovits, rebuilt))
+                }
+                None => None,
+            };
+            (gpt, sovits)
+        };
+
+        let mut engine = self.pool.engine().write().unwrap();
+        if let Some((sovits, mut rebuilt)) = sovits {
+            let old = engine.swap_sovits_weights(sovits);
+            let mut voices = self.voices.lock().unwrap();
+            // 生成期间新注册或改过的音色没有生成，拿着写锁补上
+            let mut missed = vec![];
+            for (name, v) in voices.iter() {
+                let fresh = rebuilt.get(name).map_or(false, |new| new.refer_wav_path == v.refer_wav_path && new.prompt_text == v.prompt_text);
+                if !fresh {
+                    match engine.reference_voice(&v.refer_wav_path, &v.prompt_text) {
+                        Ok(voice) => missed.push((name.clone(), Arc::new(voice))),
+                        Err(e) => {
+                            engine.swap_sovits_weights(old);
+                            return Err(e);
+                        }
+                    }
+                }
+            }
+            for (name, v) in voices.iter_mut() {
+                if let Some(new) = rebuilt.remove(name) {
+                    v.voice = new.voice;
+                }
+            }
+            for (name, voice) in missed {
+                if let Some(v) = voices.get_mut(&name) {
+                    v.voice = voice;
+                }
+            }
+            self.request_voices.lock().unwrap().clear();
+        }
+        if let Some(gpt) = gpt {
+            engine.swap_gpt_weights(gpt);
+        }
+        Ok(())
+    }
+
+    fn route(&self, path: &str, params: &HashMap<String, String>) -> Result<Reply, ApiError> {
+        match path {
+            "/" | "/tts" => self.tts(params),
+            "/change_refer" | "/set_refer_audio" => {
+                let refer_wav_path = param(params, &["refer_wav_path", "refer_audio_path", "ref_audio_path"])
+                    .ok_or_else(|| ApiError::bad_request("refer_wav_path is required"))?;
+                let prompt_text = param(params, &["prompt_text"]).unwrap_or("");
+                self.register_voice(refer_wav_path, refer_wav_path, prompt_text)?;
+                *self.default_voice.lock().unwrap() = Some(refer_wav_path.to_string());
+                Ok(success())
+            }
+            "/voices" => {
+                // 有 name 就是注册，否则列出所有音色
+                match param(params, &["name"]) {
+                    Some(name) => {
+                        let refer_wav_path = param(params, &["refer_wav_path", "ref_audio_path"])
+                            .ok_or_else(|| ApiError::bad_request("refer_wav_path is required"))?;
+                        let prompt_text = param(params, &["prompt_text"]).unwrap_or("");
+                        self.register_voice(name, refer_wav_path, prompt_text)?;
+                        self.default_voice.lock().unwrap().get_or_insert_with(|| name.to_string());
+                        Ok(success())
+                    }
+                    None => {
+                        let voices: Vec<Value> = self.voices.lock().unwrap().iter().map(|(name, v)| json!({
+                            "name": name,
+                            "refer_wav_path": v.refer_wav_path,
+                            "prompt_text": v.prompt_text,
+                            "warnings": v.voice.warnings,
+                        })).collect();
+                        let default_voice = self.default_voice.lock().unwrap().clone();
+                        Ok(Reply::Json(json!({"default": default_voice, "voices": voices})))
+                    }
+                }
+            }
+            "/voices/switch" => {
+                let name = param(params, &["name"]).ok_or_else(|| ApiError::bad_request("name is required"))?;
+                if self.voice(name).is_none() {
+                    return Err(ApiError::bad_request(format!("voice {} not registered", name)));
+                }
+                *self.default_voice.lock().unwrap() = Some(name.to_string());
+                Ok(success())
+            }
+            "/set_model" => {
+                let gpt = param(params, &["gpt_model_path"]);
+                let sovits = param(params, &["sovits_model_path"]);
+                if gpt.is_none() && sovits.is_none() {
+                    return Err(ApiError::bad_request("gpt_model_path or sovits_model_path is required"));
+                }
+                self.set_model(gpt, sovits)?;
+                Ok(success())
+            }
+            "/set_gpt_weights" => {
+                let weights_path = param(params, &["weights_path"]).ok_or_else(|| ApiError::bad_request("weights_path is required"))?;
+                self.set_model(Some(weights_path), None)?;
+                Ok(success())
+            }
+            "/set_sovits_weights" => {
+                let weights_path = param(params, &["weights_path"]).ok_or_else(|| ApiError::bad_request("weights_path is required"))?;
+                self.set_model(None, Some(weights_path))?;
+                Ok(success())
+            }
+            _ => Err(ApiError { status: 404, message: format!("{} not found", path) }),
+        }
+    }
+
+    fn tts(&self, params: &HashMap<String, String>) -> Result<Reply, ApiError> {
+        let text = param(params, &["text"]).ok_or_else(|| ApiError::bad_request("text is required"))?;
+        // 语种由 LangSegment 自动切分，text_language / prompt_language 只接收不使用
+        let voice = match param(params, &["refer_wav_path", "ref_audio_path"]) {
+            Some(refer_wav_path) => {
+                let prompt_text = param(params, &["prompt_text"]).unwrap_or("");
+                self.request_voice(refer_wav_path, prompt_text)?
+            }
+            None => {
+                let name = match param(params, &["voice"]) {
+                    Some(name) => name.to_string(),
+                    None => self.default_voice.lock().unwrap().clone()
+                        .ok_or_else(|| ApiError::bad_request("no reference voice, set refer_wav_path or call /change_refer"))?,
+                };
+                self.voice(&name).ok_or_else(|| ApiError::bad_request(format!("voice {} not registered", name)))?
+            }
+        };
+
+        let mut synthesis_params = SynthesisParams::default();
+        if let Some(top_k) = parse_param(params, &["top_k"])? {
+            synthesis_params.top_k = top_k;
+        }
+        if let Some(top_p) = parse_param(params, &["top_p"])? {
+            synthesis_params.top_p = top_p;
+        }
+        if let Some(temperature) = parse_param(params, &["temperature"])? {
+            synthesis_params.temperature = temperature;
+        }
+        if let Some(repetition_penalty) = parse_param(params, &["repetition_penalty"])? {
+            synthesis_params.repetition_penalty = repetition_penalty;
+        }
+        synthesis_params.seed = parse_param::<i64>(params, &["seed"])?.filter(|&seed| seed >= 0).map(|seed| seed as u64);
+        if let Some(lufs) = parse_param(params, &["loudness_lufs"])? {
+            synthesis_params.post_process.loudness = Some(Loudness::Lufs(lufs));
+        }
+        synthesis_params.post_process.trim_silence_db = parse_param(params, &["trim_silence_db"])?;
+        if let Some(fade_ms) = parse_param(params, &["fade_ms"])? {
+            synthesis_params.post_process.fade_ms = fade_ms;
+        }
+        if let Some(speed) = parse_param(params, &["speed", "speed_factor"])? {
+            synthesis_params.speed = speed;
+        }
+        if let Some(pitch_semitones) = parse_param(params, &["pitch", "pitch_semitones"])? {
+            synthesis_params.pitch_semitones = pitch_semitones;
+        }
+        if let Some(volume_db) = parse_param(params, &["volume_db"])? {
+            synthesis_params.volume_db = volume_db;
+        }
+        if let Some(paragraph) = parse_param(params, &["pause_paragraph"])? {
+            synthesis_params.pauses.paragraph = paragraph;
+        }
+        if let Some(sentence) = parse_param(params, &["pause_sentence"])? {
+            synthesis_params.pauses.sentence = sentence;
+        }
+        if let Some(comma) = parse_param(params, &["pause_comma"])? {
+            synthesis_params.pauses.comma = comma;
+        }
+        synthesis_params.ref_free = param(params, &["ref_free"])
+            .map(|v| v == "true" || v == "1" || v == "True")
+            .unwrap_or(false);
+        // 秒，超时返回 504
+        if let Some(timeout) = parse_param::<f64>(params, &["timeout"])?.filter(|&t| t > 0.0) {
+            // 太大的值 from_secs_f64 会 panic
+            let timeout = Duration::try_from_secs_f64(timeout).map_err(|_| ApiError::bad_request(format!("invalid timeout: {}", timeout)))?;
+            synthesis_params = synthesis_params.with_timeout(timeout);
+        }
+
+        let streaming = param(params, &["streaming_mode", "stream"])
+            .map(|v| v == "true" || v == "1" || v == "True")
+            .unwrap_or(false);
+        if streaming {
+            synthesis_params.token_streaming = Some(TokenStreamConfig::default());
+            return Ok(Reply::Stream { text: text.to_string(), voice, params: synthesis_params });
+        }
+
+        let audio = self.pool.synthesize(text.to_string(), voice, synthesis_params)?.wait()?;
+        Ok(Reply::Wav(audio.to_wav()))
+    }
+
+    /// 分块返回：先发 wav 头，每解码一块音频发一块；客户端断开就取消
+    fn stream(&self, request: Request, text: String, voice: Arc<ReferenceVoice>, params: SynthesisParams) -> std::io::Result<()> {
+        let (tx, rx) = channel::<Vec<u8>>();
+        let _ = tx.send(wav_header(self.sample_rate(), None));
+        let job = self.pool.submit(move |engine, cancel| {
+            let params = SynthesisParams { cancel: Some(cancel.clone()), ..params };
+            engine.synthesize_with_callback(&text, &voice, &params, &mut |pcm| {
+                // 响应已经结束，没人收了
+                if tx.send(pcm16_to_bytes(pcm)).is_err() {
+                    cancel.cancel();
+                }
+            })
+        });
+        let job = match job {
+            Ok(job) => job,
+            Err(e) => return respond_error(request, e.into()),
+        };
+
+        // job 结束后 tx 被 drop，响应才结束
+        let response = Response::new(
+            StatusCode(200),
+            vec![content_type("audio/wav")],
+            ChannelReader { rx, buf: vec![], pos: 0 },
+            None,
+            None,
+        );
+        if let Err(e) = request.respond(response) {
+            warn!("client disconnected: {}", e);
+            job.cancel();
+            return Ok(());
+        }
+        if let Err(e) = job.wait() {
+            // 头已经发出去了，只能提前结束
+            error!("streaming synthesis failed: {}", e);
+        }
+        Ok(())
+    }
+
+    fn handle(&self, mut request: Request) {
+        let reply = request_params(&mut request).and_then(|(path, params)| {
+            info!("{} {}", request.method(), path);
+            self.route(&path, &params)
+        });
+
+        let result = match reply {
+            Ok(Reply::Json(value)) => request.respond(
+                Response::from_string(value.to_string()).with_header(content_type("application/json")),
+            ),
+            Ok(Reply::Wav(bytes)) => request.respond(
+                Response::from_data(bytes).with_header(content_type("audio/wav")),
+            ),
+            Ok(Reply::Stream { text, voice, params }) => self.stream(request, text, voice, params),
+            Err(e) => respond_error(request, e),
+        };
+        if let Err(e) = result {
+            warn!("respond failed: {}", e);
+        }
+    }
+}
+
+fn respond_error(request: Request, e: ApiError) -> std::io::Result<()> {
+    request.respond(
+        Response::from_string(json!({"code": e.status, "message": e.message}).to_string())
+            .with_status_code(e.status)
+            .with_header(content_type("application/json")),
+    )
+}
+
+/// 把 channel 收到的块当作 Read，发送端 drop 后结束
+struct ChannelReader {
+    rx: Receiver<Vec<u8>>,
+    buf: Vec<u8>,
+    pos: usize,
+}
+
+impl Read for ChannelReader {
+    fn read(&mut self, out: &mut [u8]) -> std::io::Result<usize> {
+        while self.pos >= self.buf.len() {
+            match self.rx.recv() {
+                Ok(buf) => {
+                    self.buf = buf;
+                    self.pos = 0;
+                }
+                Err(_) => return Ok(0),
+            }
+        }
+        let n = out.len().min(self.buf.len() - self.pos);
+        out[..n].copy_from_slice(&self.buf[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+fn success() -> Reply {
+    Reply::Json(json!({"code": 0, "message": "Success"}))
+}
+
+fn content_type(value: &str) -> Header {
+    Header::from_bytes(&b"Content-Type"[..], value.as_bytes()).unwrap()
+}
+
+/// 第一个不为空的参数，names 里是同一个参数在 api.py / api_v2.py 里的不同名字
+fn param<'a>(params: &'a HashMap<String, String>, names: &[&str]) -> Option<&'a str> {
+    names.iter()
+        .filter_map(|name| params.get(*name))
+        .map(|v| v.as_str())
+        .find(|v| !v.is_empty())
+}
+
+/// 数值参数：浮点数不接受 NaN、inf
+trait ParamValue: FromStr {
+    fn is_valid(&self) -> bool {
+        true
+    }
+}
+
+impl ParamValue for i64 {}
+
+impl ParamValue for f32 {
+    fn is_valid(&self) -> bool {
+        self.is_finite()
+    }
+}
+
+impl ParamValue for f64 {
+    fn is_valid(&self) -> bool {
+        self.is_finite()
+    }
+}
+
+fn parse_param<T: ParamValue>(params: &HashMap<String, String>, names: &[&str]) -> Result<Option<T>, ApiError> {
+    match param(params, names) {
+        Some(v) => v.parse::<T>().ok().filter(|v| v.is_valid()).map(Some)
+            .ok_or_else(|| ApiError::bad_request(format!("invalid {}: {}", names[0], v))),
+        None => Ok(None),
+    }
+}
+
+/// query string 和 POST 的 json body 合并成一个表，body 优先
+fn request_params(request: &mut Request) -> Result<(String, HashMap<String, String>), ApiError> {
+    let url = request.url().to_string();
+    let (path, query) = url.split_once('?').unwrap_or((url.as_str(), ""));
+    let mut params: HashMap<String, String> = url::form_urlencoded::parse(query.as_bytes())
+        .map(|(k, v)| (k.into_owned(), v.into_owned()))
+        .collect();
+
+    if *request.method() == Method::Post {
+        // 先看 Content-Length，chunked 的没有长度，最多读 MAX_BODY_BYTES + 1 字节判断
+        if request.body_length().map_or(false, |len| len > MAX_BODY_BYTES) {
+            return Err(ApiError::body_too_large());
+        }
+        let mut body = String::new();
+        request.as_reader().take(MAX_BODY_BYTES as u64 + 1).read_to_string(&mut body).map_err(ApiError::bad_request)?;
+        if body.len() > MAX_BODY_BYTES {
+            return Err(ApiError::body_too_large());
+        }
+        if !body.trim().is_empty() {
+            let value: Value = serde_json::from_str(&body).map_err(ApiError::bad_request)?;
+            let Value::Object(map) = value else {
+                return Err(ApiError::bad_request("body must be a json object"));
+            };
+            for (k, v) in map {
+                let v = match v {
+                    Value::String(s) => s,
+                    Value::Null => continue,
+                    other => other.to_string(),
+                };
+                params.insert(k, v);
+            }
+        }
+    }
+    Ok((path.to_string(), params))
+}
+
+fn main() {
+    let args = Args::parse();
+
+    let engine = match &args.config {
+        Some(config_path) => TtsConfig::from_file(config_path).and_then(TtsEngine::init),
+        None => TtsEngine::from_model_dir(&args.model_dir),
+    };
+    let engine = match engine {
+        Ok(engine) => engine,
+        Err(e) => {
+            eprintln!("load models failed: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let pool_config = PoolConfig { workers: args.workers, queue_size: args.queue_size };
+    let state = ServerState {
+        pool: WorkerPool::new(engine, &pool_config),
+        voices: Mutex::new(HashMap::new()),
+        request_voices: Mutex::new(VoiceCache::new(args.voice_cache_size)),
+        in_flight: Mutex::new(HashMap::new()),
+        default_voice: Mutex::new(None),
+    };
+
+    if let Some(refer_path) = &args.default_refer_path {
+        let prompt_text = args.default_refer_text.clone().unwrap_or_default();
+        if let Err(e) = state.register_voice(refer_path, refer_path, &prompt_text) {
+            eprintln!("load default reference {} failed: {}", refer_path, e);
+            std::process::exit(1);
+        }
+        *state.default_voice.lock().unwrap() = Some(refer_path.clone());
+    }
+
+    let addr = format!("{}:{}", args.bind_addr, args.port);
+    let server = match Server::http(&addr) {
+        Ok(server) => server,
+        Err(e) => {
+            eprintln!("listen on {} failed: {}", addr, e);
+            std::process::exit(1);
+        }
+    };
+    println!("tts-server listening on http://{} ({} workers, queue {})", addr, pool_config.workers, pool_config.queue_size);
+
+    // 模型只有一份，多个 http 线程把合成交给 pool，并发由 --workers 和 TtsConfig.concurrency 控制
+    std::thread::scope(|scope| {
+        for _ in 0..args.http_threads.max(1) {
+            scope.spawn(|| {
+                for request in server.incoming_requests() {
+                    state.handle(request);
+                }
+            });
+        }
+    });
+}
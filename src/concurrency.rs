@@ -0,0 +1,119 @@
+use std::sync::{Arc, Condvar, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use serde::{Deserialize, Serialize};
+
+/// 每个阶段最多同时跑几个请求，None: 不限制
+///
+/// 多个请求共用一套模型时，显存、cpu 核数不够就按阶段限制
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct StageConcurrency {
+    pub bert: Option<usize>,
+    /// 生成参考音色：ssl_model + vq_model_latent
+    pub ssl: Option<usize>,
+    /// t2s_first_stage_decoder + t2s_stage_decoder
+    pub t2s: Option<usize>,
+    pub vocoder: Option<usize>,
+}
+
+/// 计数信号量
+pub struct Semaphore {
+    // None: 不限制
+    permits: Option<(Mutex<usize>, Condvar)>,
+}
+
+/// drop 时归还
+pub struct Permit<'a> {
+    semaphore: &'a Semaphore,
+}
+
+impl Semaphore {
+    pub fn new(max: Option<usize>) -> Self {
+        Semaphore { permits: max.map(|max| (Mutex::new(max.max(1)), Condvar::new())) }
+    }
+
+    /// 没有空位就阻塞
+    pub fn acquire(&self) -> Permit<'_> {
+        if let Some((count, available)) = &self.permits {
+            let mut count = count.lock().unwrap();
+            while *count == 0 {
+                count = available.wait(count).unwrap();
+            }
+            *count -= 1;
+        }
+        Permit { semaphore: self }
+    }
+}
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        if let Some((count, available)) = &self.semaphore.permits {
+            *count.lock().unwrap() += 1;
+            available.notify_one();
+        }
+    }
+}
+
+/// TtsEngine 里每个阶段的信号量
+pub struct StageLimits {
+    pub bert: Semaphore,
+    pub ssl: Semaphore,
+    pub t2s: Semaphore,
+    pub vocoder: Semaphore,
+}
+
+impl StageLimits {
+    pub fn new(config: &StageConcurrency) -> Self {
+        StageLimits {
+            bert: Semaphore::new(config.bert),
+            ssl: Semaphore::new(config.ssl),
+            t2s: Semaphore::new(config.t2s),
+            vocoder: Semaphore::new(config.vocoder),
+        }
+    }
+}
+
+/// 取消请求：clone 出去的都指向同一个标志
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancelToken {
+    pub fn new() -> Self {
+        CancelToken::default()
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+}
+
+#[test]
+fn test_semaphore() {
+    use std::sync::atomic::AtomicUsize;
+
+    let semaphore = Semaphore::new(Some(2));
+    let running = AtomicUsize::new(0);
+    let max_running = AtomicUsize::new(0);
+    std::thread::scope(|scope| {
+        for _ in 0..6 {
+            scope.spawn(|| {
+                let _permit = semaphore.acquire();
+                let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                max_running.fetch_max(now, Ordering::SeqCst);
+                std::thread::sleep(std::time::Duration::from_millis(10));
+                running.fetch_sub(1, Ordering::SeqCst);
+            });
+        }
+    });
+    assert!(max_running.load(Ordering::SeqCst) <= 2);
+
+    let token = CancelToken::new();
+    token.clone().cancel();
+    assert!(token.is_cancelled());
+}
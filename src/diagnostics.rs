@@ -0,0 +1,150 @@
+use std::collections::HashMap;
+use crate::tts_engine::SynthesisParams;
+
+/// 重复检测用的 n-gram 长度
+const NGRAM: usize = 4;
+/// 只数这么多个 token 以内的重复，长句子里隔得远的重复是正常的（约 3 秒）
+const NGRAM_WINDOW: usize = 150;
+
+/// 一段 t2s 生成的情况，用来判断有没有跑飞
+#[derive(Debug, Clone)]
+pub struct GenerationDiagnostics {
+    /// 生成的 semantic token 数
+    pub tokens: usize,
+    /// 这段文字的音素数
+    pub phones: usize,
+    /// 是否生成了结束符 1024
+    pub eos_reached: bool,
+    /// 是否因为复读被 early_stop 截断
+    pub early_stopped: bool,
+    /// NGRAM_WINDOW 个 token 以内，出现次数最多的 4-gram 出现了几次
+    pub max_ngram_repeats: usize,
+    /// 第几次尝试的结果，从 1 开始
+    pub attempts: usize,
+    /// 采样实际用的种子，没指定时是随机取的
+    pub seed: u64,
+    pub temperature: f32,
+}
+
+/// 检查不通过时换种子、降温度重新生成
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// 最多重试次数，0: 不重试只记录
+    pub max_retries: usize,
+    /// 每次重试温度乘以这个系数
+    pub temperature_decay: f32,
+    pub min_tokens_per_phone: f32,
+    pub max_tokens_per_phone: f32,
+    pub max_ngram_repeats: usize,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_retries: 2,
+            temperature_decay: 0.8,
+            min_tokens_per_phone: 0.8,
+            max_tokens_per_phone: 6.0,
+            max_ngram_repeats: 8,
+        }
+    }
+}
+
+impl GenerationDiagnostics {
+    pub fn new(tokens: &[i64], phones: usize, eos_reached: bool, early_stopped: bool, seed: u64, params: &SynthesisParams) -> Self {
+        GenerationDiagnostics {
+            tokens: tokens.len(),
+            phones,
+            eos_reached,
+            early_stopped,
+            max_ngram_repeats: max_ngram_repeats(tokens, NGRAM, NGRAM_WINDOW),
+            attempts: 1,
+            seed,
+            temperature: params.temperature,
+        }
+    }
+
+    pub fn tokens_per_phone(&self) -> f32 {
+        self.tokens as f32 / self.phones.max(1) as f32
+    }
+}
+
+impl RetryPolicy {
+    /// 不通过时返回原因
+    pub fn check(&self, diagnostics: &GenerationDiagnostics) -> Result<(), String> {
+        if diagnostics.tokens == 0 {
+            return Err("no semantic tokens".to_string());
+        }
+        // early_stop 已经把复读的部分截掉了，不算没结束
+        if !diagnostics.eos_reached && !diagnostics.early_stopped {
+            return Err(format!("EOS not reached after {} tokens", diagnostics.tokens));
+        }
+        let ratio = diagnostics.tokens_per_phone();
+        if ratio < self.min_tokens_per_phone || ratio > self.max_tokens_per_phone {
+            return Err(format!("{} tokens for {} phones", diagnostics.tokens, diagnostics.phones));
+        }
+        if diagnostics.max_ngram_repeats > self.max_ngram_repeats {
+            return Err(format!("{}-gram repeated {} times", NGRAM, diagnostics.max_ngram_repeats));
+        }
+        Ok(())
+    }
+
+    /// 第 attempt 次重试（从 1 开始）的参数：从第一次实际用的种子 first_seed 往后挪，重试可以复现；温度降低
+    pub fn params_for_retry(&self, params: &SynthesisParams, first_seed: u64, attempt: usize) -> SynthesisParams {
+        let mut params = params.clone();
+        params.seed = Some(first_seed.wrapping_add(attempt as u64));
+        params.temperature *= self.temperature_decay.powi(attempt as i32);
+        params
+    }
+}
+
+/// 滑动窗口：每 window 个 token 里出现最多的 n-gram 的次数，取最大
+fn max_ngram_repeats(tokens: &[i64], n: usize, window: usize) -> usize {
+    let grams: Vec<&[i64]> = tokens.windows(n).collect();
+    // 窗口里有几个 n-gram
+    let span = window.saturating_sub(n) + 1;
+    let mut counts: HashMap<&[i64], usize> = HashMap::new();
+    let mut max = 0;
+    for (i, &gram) in grams.iter().enumerate() {
+        let count = counts.entry(gram).or_insert(0);
+        *count += 1;
+        max = max.max(*count);
+        if i + 1 >= span {
+            if let Some(count) = counts.get_mut(grams[i + 1 - span]) {
+                *count -= 1;
+            }
+        }
+    }
+    max
+}
+
+#[test]
+fn test_retry_check() {
+    let policy = RetryPolicy::default();
+    let params = SynthesisParams::default();
+
+    let tokens: Vec<i64> = (0..30).collect();
+    let ok = GenerationDiagnostics::new(&tokens, 12, true, false, 0, &params);
+    assert!(policy.check(&ok).is_ok());
+
+    let empty = GenerationDiagnostics::new(&[], 12, false, false, 0, &params);
+    assert!(policy.check(&empty).is_err());
+
+    let looping: Vec<i64> = [1, 2, 3, 4].iter().cycle().take(40).copied().collect();
+    let looping = GenerationDiagnostics::new(&looping, 12, true, false, 0, &params);
+    assert_eq!(looping.max_ngram_repeats, 10);
+    assert!(policy.check(&looping).is_err());
+
+    // 隔得远的重复不算
+    let spread: Vec<i64> = (0..10).flat_map(|k| [1, 2, 3, 4].into_iter().chain((0..200).map(move |j| 1000 + k * 200 + j))).collect();
+    assert_eq!(max_ngram_repeats(&spread, NGRAM, NGRAM_WINDOW), 1);
+
+    let truncated = GenerationDiagnostics::new(&tokens, 12, false, true, 0, &params);
+    assert!(policy.check(&truncated).is_ok());
+
+    let retry = policy.params_for_retry(&SynthesisParams { seed: Some(7), ..params }, 7, 2);
+    assert_eq!(retry.seed, Some(9));
+    assert!((retry.temperature - 0.8 * 0.64).abs() < 1e-6);
+    // 没指定种子的，接着第一次随机到的种子
+    assert_eq!(policy.params_for_retry(&SynthesisParams::default(), 12345, 1).seed, Some(12346));
+}
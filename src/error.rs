@@ -0,0 +1,103 @@
+use std::fmt;
+
+/// 推理流程的错误：模型、字典、音频、文本前端
+#[derive(Debug)]
+pub enum TtsError {
+    /// onnx 模型文件读取或创建 Session 失败
+    ModelLoad { path: String, message: String },
+    /// Session 跑失败
+    Inference(String),
+    /// 模型输出里没有这个名字：通常是导出方式不一样
+    MissingTensor { name: String },
+    /// tensor 的类型、维度和预期不一致
+    ShapeMismatch { name: String, message: String },
+    AudioDecode(String),
+    AudioEncode(String),
+    /// tokenizer.json、eng_dict.json、rep_map.json、拼音字典等
+    DictionaryLoad { path: String, message: String },
+    TextFrontend(String),
+    Io(std::io::Error),
+    /// 参考音色文件格式不对
+    VoiceFile(String),
+    /// 参考音频不能用：太短、太长、没有声音、削波严重
+    InvalidReference(String),
+    /// 配置文件格式不对
+    Config { path: String, message: String },
+    /// onnx 的输入输出和推理代码用的不一致：导出方式不一样
+    ModelSignature { model: String, message: String },
+    /// WorkerPool 的队列满了
+    QueueFull { capacity: usize },
+    /// 请求被取消或超过 deadline，partial: 已经合成出来的 pcm16
+    Cancelled { partial: Vec<i16> },
+}
+
+pub type Result<T> = std::result::Result<T, TtsError>;
+
+impl fmt::Display for TtsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TtsError::ModelLoad { path, message } => write!(f, "load model {} failed: {}", path, message),
+            TtsError::Inference(message) => write!(f, "inference failed: {}", message),
+            TtsError::MissingTensor { name } => write!(f, "model output `{}` not found", name),
+            TtsError::ShapeMismatch { name, message } => write!(f, "tensor `{}` shape mismatch: {}", name, message),
+            TtsError::AudioDecode(message) => write!(f, "audio decode failed: {}", message),
+            TtsError::AudioEncode(message) => write!(f, "audio encode failed: {}", message),
+            TtsError::DictionaryLoad { path, message } => write!(f, "load dictionary {} failed: {}", path, message),
+            TtsError::TextFrontend(message) => write!(f, "text frontend error: {}", message),
+            TtsError::Io(e) => write!(f, "io error: {}", e),
+            TtsError::VoiceFile(message) => write!(f, "invalid reference voice file: {}", message),
+            TtsError::InvalidReference(message) => write!(f, "reference audio rejected: {}", message),
+            TtsError::Config { path, message } => write!(f, "invalid config {}: {}", path, message),
+            TtsError::ModelSignature { model, message } => write!(f, "model {} does not match: {}", model, message),
+            TtsError::QueueFull { capacity } => write!(f, "too many requests: queue of {} is full", capacity),
+            TtsError::Cancelled { partial } => write!(f, "request cancelled after {} samples", partial.len()),
+        }
+    }
+}
+
+impl std::error::Error for TtsError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            TtsError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<ort::Error> for TtsError {
+    fn from(e: ort::Error) -> Self {
+        TtsError::Inference(e.to_string())
+    }
+}
+
+impl From<std::io::Error> for TtsError {
+    fn from(e: std::io::Error) -> Self {
+        TtsError::Io(e)
+    }
+}
+
+impl TtsError {
+    pub(crate) fn dictionary(path: &str, e: impl fmt::Display) -> Self {
+        TtsError::DictionaryLoad { path: path.to_string(), message: e.to_string() }
+    }
+
+    pub(crate) fn shape(name: &str, e: impl fmt::Display) -> Self {
+        TtsError::ShapeMismatch { name: name.to_string(), message: e.to_string() }
+    }
+}
+
+/// 取出 Session 输出的 tensor 视图：名字不存在、类型或维度不对都返回错误，不再 panic
+///
+/// `extract_tensor!(outputs, "y", i64, Ix2)` -> `ArrayView2<i64>`
+macro_rules! extract_tensor {
+    ($outputs:expr, $name:expr, $t:ty, $dim:ty) => {
+        $outputs.get($name)
+            .ok_or_else(|| $crate::error::TtsError::MissingTensor { name: $name.to_string() })?
+            .try_extract_tensor::<$t>()
+            .map_err(|e| $crate::error::TtsError::shape($name, e))?
+            .into_dimensionality::<$dim>()
+            .map_err(|e| $crate::error::TtsError::shape($name, e))?
+    };
+}
+
+pub(crate) use extract_tensor;
@@ -0,0 +1,153 @@
+use ort::{AllocationDevice, AllocatorType, CPUExecutionProvider, ExecutionProviderDispatch, GraphOptimizationLevel, MemoryInfo, MemoryType, SessionBuilder};
+#[cfg(feature = "cuda")]
+use ort::{CUDAExecutionProvider, ExecutionProvider};
+use serde::{Deserialize, Serialize};
+
+/// 推理设备，按顺序尝试
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Provider {
+    /// 没开 cuda feature、或者 onnxruntime 不带 cuda 时跳过
+    Cuda {
+        #[serde(default)]
+        device_id: i32,
+        /// 显存上限：字节
+        #[serde(default)]
+        memory_limit: Option<usize>,
+    },
+    Cpu,
+}
+
+impl Provider {
+    /// 这个 provider 的设备内存：t2s 的 k/v cache 绑定在这里，每步不用拷回 cpu
+    pub fn memory_info(&self) -> ort::Result<MemoryInfo> {
+        match self {
+            Provider::Cuda { device_id, .. } => MemoryInfo::new(AllocationDevice::CUDA, *device_id, AllocatorType::Device, MemoryType::Default),
+            Provider::Cpu => MemoryInfo::new(AllocationDevice::CPU, 0, AllocatorType::Device, MemoryType::Default),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OptimizationLevel {
+    Disable,
+    Level1,
+    Level2,
+    Level3,
+}
+
+/// 每个 onnx Session 的运行配置
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ExecutionConfig {
+    /// 按优先级排列，前面的创建失败就用后面的
+    pub providers: Vec<Provider>,
+    /// 单个算子内部的线程数，None: onnxruntime 默认（物理核数）
+    pub intra_threads: Option<usize>,
+    /// 算子之间并行的线程数，只在 parallel_execution 时有用
+    pub inter_threads: Option<usize>,
+    pub parallel_execution: bool,
+    pub optimization_level: OptimizationLevel,
+    /// cpu 内存池
+    pub cpu_arena: bool,
+    /// 输入形状固定时预先规划内存，t2s 每步形状都在变，可以关掉
+    pub memory_pattern: bool,
+}
+
+impl Default for ExecutionConfig {
+    fn default() -> Self {
+        let mut providers = vec![];
+        if cfg!(feature = "cuda") {
+            providers.push(Provider::Cuda { device_id: 0, memory_limit: None });
+        }
+        providers.push(Provider::Cpu);
+
+        ExecutionConfig {
+            providers,
+            intra_threads: None,
+            inter_threads: None,
+            parallel_execution: false,
+            optimization_level: OptimizationLevel::Level3,
+            cpu_arena: true,
+            memory_pattern: true,
+        }
+    }
+}
+
+impl From<OptimizationLevel> for GraphOptimizationLevel {
+    fn from(level: OptimizationLevel) -> Self {
+        match level {
+            OptimizationLevel::Disable => GraphOptimizationLevel::Disable,
+            OptimizationLevel::Level1 => GraphOptimizationLevel::Level1,
+            OptimizationLevel::Level2 => GraphOptimizationLevel::Level2,
+            OptimizationLevel::Level3 => GraphOptimizationLevel::Level3,
+        }
+    }
+}
+
+impl ExecutionConfig {
+    /// 只用 cpu，线程数固定，结果可复现
+    pub fn cpu(threads: usize) -> Self {
+        ExecutionConfig {
+            providers: vec![Provider::Cpu],
+            intra_threads: Some(threads),
+            inter_threads: Some(1),
+            ..ExecutionConfig::default()
+        }
+    }
+
+    /// 按 providers 的顺序，每个 provider 单独建一个 SessionBuilder
+    pub fn session_builders(&self) -> Vec<(Provider, ort::Result<SessionBuilder>)> {
+        self.providers.iter()
+            .filter_map(|provider| self.dispatch(provider).map(|dispatch| (provider.clone(), dispatch)))
+            .map(|(provider, dispatch)| (provider, self.session_builder(dispatch)))
+            .collect()
+    }
+
+    fn session_builder(&self, dispatch: ExecutionProviderDispatch) -> ort::Result<SessionBuilder> {
+        let mut builder = SessionBuilder::new()?
+            .with_optimization_level(self.optimization_level.into())?
+            .with_parallel_execution(self.parallel_execution)?
+            .with_memory_pattern(self.memory_pattern)?
+            .with_execution_providers([dispatch])?;
+        if let Some(intra_threads) = self.intra_threads {
+            builder = builder.with_intra_threads(intra_threads)?;
+        }
+        if let Some(inter_threads) = self.inter_threads {
+            builder = builder.with_inter_threads(inter_threads)?;
+        }
+        Ok(builder)
+    }
+
+    fn dispatch(&self, provider: &Provider) -> Option<ExecutionProviderDispatch> {
+        match provider {
+            Provider::Cpu => {
+                let cpu = CPUExecutionProvider::default();
+                let cpu = if self.cpu_arena { cpu.with_arena_allocator() } else { cpu };
+                Some(cpu.build())
+            }
+            #[cfg(feature = "cuda")]
+            Provider::Cuda { device_id, memory_limit } => {
+                let cuda = CUDAExecutionProvider::default().with_device_id(*device_id);
+                let cuda = match memory_limit {
+                    Some(limit) => cuda.with_memory_limit(*limit),
+                    None => cuda,
+                };
+                // 没有 gpu 时 onnxruntime 默认悄悄退回 cpu，load_model_on 返回的 provider 就不对了；让它直接失败，换下一个 provider
+                let cuda = cuda.build().error_on_failure();
+                // 链接的 onnxruntime 不带 cuda 时直接跳过
+                if !cuda.is_available().unwrap_or(false) {
+                    log::warn!("cuda provider not available in onnxruntime, skip cuda device {}", device_id);
+                    return None;
+                }
+                Some(cuda)
+            }
+            #[cfg(not(feature = "cuda"))]
+            Provider::Cuda { .. } => {
+                log::warn!("built without cuda, skip cuda provider");
+                None
+            }
+        }
+    }
+}
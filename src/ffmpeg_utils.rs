@@ -1,33 +1,25 @@
-use std::cmp::max;
 use anyhow::{bail, Context, Result};
 use rsmpeg::{
-    avcodec::{AVCodecContext, AVCodecParserContext, AVPacket},
+    avcodec::AVCodecContext,
     avformat::AVFormatContextInput,
-    avutil::{
-        get_bytes_per_sample, get_packed_sample_fmt, get_sample_fmt_name, is_planar, AVFrame,
-        AVSampleFormat,
-    },
+    avutil::AVFrame,
     error::RsmpegError,
     ffi,
 };
-use std::ffi::{c_char, c_int, CStr, CString};
-use std::fs::{self, File};
-use std::io::Write;
-use std::path::Path;
-use std::{ptr, slice};
-use std::ptr::{NonNull, null};
+use std::ffi::{c_char, CString};
+use std::slice;
 use std::slice::from_raw_parts;
-use dasp::{ring_buffer, signal, Signal};
+use dasp::{signal, Signal};
 use dasp::interpolate::linear::Linear;
-use dasp::interpolate::sinc::Sinc;
-use hound::{SampleFormat, WavReader, WavSpec};
 use log::info;
 use rsmpeg::avcodec::{AVCodec, AVCodecRef};
 use rsmpeg::avformat::AVFormatContextOutput;
-use rsmpeg::avutil::{av_get_channel_layout_nb_channels, av_get_default_channel_layout, AVAudioFifo, AVSamples};
+use rsmpeg::avutil::{av_get_channel_layout_nb_channels, av_get_default_channel_layout, AVSamples};
 use rsmpeg::swresample::SwrContext;
-use rsmpeg::ffi::{AV_CH_LAYOUT_NATIVE, av_get_channel_layout, av_rescale_rnd, av_samples_copy, AVRational, AVRounding, swr_get_delay};
+use rsmpeg::ffi::{av_get_channel_layout, av_rescale_rnd, AVRational, swr_get_delay};
 use soundtouch::{Setting, SoundTouch};
+use crate::error::TtsError;
+use crate::post_process::sample_to_i16;
 
 
 pub struct FfmpegUtils {}
@@ -38,7 +30,7 @@ impl FfmpegUtils {
     pub(crate) fn init_audio_resampler(
         decode_context: &mut AVCodecContext,
         encode_context: &mut AVCodecContext,
-    ) -> Result<(bool, SwrContext), String>
+    ) -> Result<(bool, SwrContext), TtsError>
     {
         // channel_layout为0，如果此时音频数据转换的时候，需要通道布局的参数。
         // 如果直接将解码器的channel_layout做参数给swr_alloc_set_opts()肯定会出错
@@ -63,13 +55,13 @@ impl FfmpegUtils {
             av_get_default_channel_layout(decode_context.channels),
             decode_context.sample_fmt,
             decode_context.sample_rate,
-        ).context("Could not allocate resample context").unwrap();
+        ).map_err(|e| TtsError::AudioDecode(format!("Could not allocate resample context: {}", e)))?;
 
         let init_res = resample_context
             .init()
             .context("Could not open resample context");
         if init_res.is_err() {
-            return Err("Could not open resample context".to_string());
+            return Err(TtsError::AudioDecode("Could not open resample context".to_string()));
         }
         let is_same = {
             if decode_context.channels == encode_context.channels
@@ -141,7 +133,7 @@ impl FfmpegUtils {
     }
 
     fn get_audio_decoder(audio_path: &str) -> Result<(AVCodecRef, AVCodecContext, usize, AVFormatContextInput)> {
-        let audio_path = CString::new(audio_path).unwrap();
+        let audio_path = CString::new(audio_path).context("Invalid audio path.")?;
         let (decoder, mut decode_context, stream_index, mut input_format_context) = {
             let mut input_format_context = AVFormatContextInput::open(&audio_path, None, &mut None)
                 .context("Open audio file failed.")?;
@@ -178,9 +170,10 @@ impl FfmpegUtils {
     }
 
     /// pcm16 audio encode
-    pub(crate) fn get_pcm16_encode(output_format_context: Option<&AVFormatContextOutput>, sr: i32) -> AVCodecContext {
+    pub(crate) fn get_pcm16_encode(output_format_context: Option<&AVFormatContextOutput>, sr: i32) -> Result<AVCodecContext, TtsError> {
         let encode_context = unsafe {
-            let encoder = AVCodec::find_encoder(ffi::AVCodecID_AV_CODEC_ID_PCM_S16LE).unwrap();
+            let encoder = AVCodec::find_encoder(ffi::AVCodecID_AV_CODEC_ID_PCM_S16LE)
+                .ok_or_else(|| TtsError::AudioEncode("pcm_s16le encoder not found".to_string()))?;
             let mut encode_context = AVCodecContext::new(&encoder);
 
             if output_format_context.is_some() {
@@ -203,10 +196,10 @@ impl FfmpegUtils {
             let cls = av_get_channel_layout_nb_channels(encode_context.channel_layout);
             encode_context.set_channels(cls);
 
-            encode_context.open(None).unwrap();
+            encode_context.open(None).map_err(|e| TtsError::AudioEncode(format!("Open pcm_s16le encoder failed: {}", e)))?;
             encode_context
         };
-        encode_context
+        Ok(encode_context)
     }
 
 
@@ -215,12 +208,21 @@ impl FfmpegUtils {
                                out_file_path: &str,
                                sample_rate: i32,
                                nb_samples: i32,
+    ) -> Result<(), TtsError> {
+        FfmpegUtils::_decode_data_to_path(audio_data, out_file_path, sample_rate, nb_samples)
+            .map_err(|e| TtsError::AudioEncode(format!("{}: {:#}", out_file_path, e)))
+    }
+
+    fn _decode_data_to_path(audio_data: &Vec<i16>,
+                            out_file_path: &str,
+                            sample_rate: i32,
+                            nb_samples: i32,
     ) -> Result<()> {
-        let out_file_path = CString::new(out_file_path).unwrap();
+        let out_file_path = CString::new(out_file_path).context("Invalid output path.")?;
 
         let mut output_format_context = AVFormatContextOutput::create(&out_file_path, None).context("Open audio file failed.")?;
 
-        let mut encode_context = FfmpegUtils::get_pcm16_encode(Some(&output_format_context), sample_rate);
+        let mut encode_context = FfmpegUtils::get_pcm16_encode(Some(&output_format_context), sample_rate)?;
         {
             let mut out_stream = output_format_context.new_stream();
             out_stream.set_codecpar(encode_context.extract_codecpar());
@@ -237,7 +239,7 @@ impl FfmpegUtils {
         frame.set_channel_layout(encode_context.channel_layout);
         frame.set_sample_rate(encode_context.sample_rate);
         frame.set_nb_samples(nb_samples);
-        frame.alloc_buffer().unwrap();
+        frame.alloc_buffer().context("Alloc frame buffer failed.")?;
 
         for (i, sample) in audio_data.chunks(nb_samples as usize).enumerate() {
             let data_ptr = frame.data;
@@ -278,25 +280,25 @@ impl FfmpegUtils {
         }
 
 
-        FfmpegUtils::flush_encoder(&mut encode_context, &mut output_format_context, 0).unwrap();
-        output_format_context.write_trailer().unwrap();
+        FfmpegUtils::flush_encoder(&mut encode_context, &mut output_format_context, 0)?;
+        output_format_context.write_trailer().context("Write trailer failed.")?;
 
         Ok(())
     }
 
 
     /// 读取音频为一维数组 any audio file -> 1 channel sr pcm16 vec
-    pub fn decode_path_to_datas(audio_path: &str, sr_to: i32) -> Result<Vec<i16>, String> {
+    pub fn decode_path_to_datas(audio_path: &str, sr_to: i32) -> Result<Vec<i16>, TtsError> {
         let oepn_ok = FfmpegUtils::get_audio_decoder(audio_path);
         if oepn_ok.is_err() {
-            return Err(oepn_ok.err().unwrap().to_string());
+            return Err(TtsError::AudioDecode(format!("{}: {:#}", audio_path, oepn_ok.err().unwrap())));
         }
         let (_, mut decode_context, stream_index, mut input_format_context) = oepn_ok.unwrap();
 
         // 保持采样率不变：否则可能会噪音
-        let mut encode_context = FfmpegUtils::get_pcm16_encode(None, decode_context.sample_rate);
+        let mut encode_context = FfmpegUtils::get_pcm16_encode(None, decode_context.sample_rate)?;
         // 保持采样率不变，转成PCM，然后在用dasp转换采样率
-        let (is_same, mut audio_resample_context) = FfmpegUtils::init_audio_resampler(&mut decode_context, &mut encode_context).unwrap();
+        let (is_same, mut audio_resample_context) = FfmpegUtils::init_audio_resampler(&mut decode_context, &mut encode_context)?;
 
         let mut audio_datas: Vec<i16> = vec![];
         let mut sr_audio_datas: Vec<i16> = vec![];
@@ -312,7 +314,8 @@ impl FfmpegUtils {
                 continue;
             }
 
-            decode_context.send_packet(Some(&packet)).unwrap();
+            decode_context.send_packet(Some(&packet))
+                .map_err(|e| TtsError::AudioDecode(format!("{}: {}", audio_path, e)))?;
 
             loop {
                 let frame = match decode_context.receive_frame() {
@@ -341,14 +344,14 @@ impl FfmpegUtils {
                         encode_context.sample_fmt,
                         0,
                     )
-                        .context("Create samples buffer failed.").unwrap();
+                        .map_err(|e| TtsError::AudioDecode(format!("Create samples buffer failed: {}", e)))?;
 
                     unsafe {
                         audio_resample_context.convert(
                             &mut output_samples,
                             _frame.extended_data as *const _,
                             _frame.nb_samples,
-                        ).unwrap();
+                        ).map_err(|e| TtsError::AudioDecode(format!("{}: {}", audio_path, e)))?;
                     }
 
                     let data_ptr = output_samples.audio_data[0];
@@ -400,54 +403,116 @@ impl FfmpegUtils {
         wavs
     }
 
-    /// 变速0.5 - 2.0
-    fn _sound_touch(data_i16: &Vec<i16>, sr: u32, atempo: f64) -> Vec<f32> {
+    /// 变速0.5 - 2.0，pitch_semitones: 变调的半音数，0: 不变调
+    fn _sound_touch(data_i16: &Vec<i16>, sr: u32, atempo: f64, pitch_semitones: f64) -> Vec<f32> {
         let wavs: Vec<f32> = data_i16.iter().map(|&x| x as f32 / 32768.0).collect();
-        if atempo == 1.0 {
+        if atempo == 1.0 && pitch_semitones == 0.0 {
             return wavs;
         } else {
-            let mut soundtouch = SoundTouch::new();
-            soundtouch.set_sample_rate(sr as u32)
-                .set_pitch_semitones(1)
-                .set_tempo(atempo)
-                .set_channels(1);
-
-            soundtouch.set_setting(Setting::UseQuickseek, 0);
-            soundtouch.set_setting(Setting::UseAaFilter, 1);
-            soundtouch.set_setting(Setting::SequenceMs, 30);
-            soundtouch.set_setting(Setting::SeekwindowMs, 15);
-            soundtouch.set_setting(Setting::OverlapMs, 8);
+            let mut soundtouch = FfmpegUtils::new_sound_touch(sr, atempo, pitch_semitones);
             let output_samples = soundtouch.generate_audio(&wavs);
             return output_samples;
         }
     }
 
-    /// 变速0.3 - 2.0
-    pub fn sound_touch(data_i16: &Vec<i16>, sr: u32, atempo: f64) -> Vec<f32> {
+    fn new_sound_touch(sr: u32, atempo: f64, pitch_semitones: f64) -> SoundTouch {
+        let mut soundtouch = SoundTouch::new();
+        soundtouch.set_sample_rate(sr as u32)
+            .set_pitch(2f64.powf(pitch_semitones / 12.0))
+            .set_tempo(atempo)
+            .set_channels(1);
+
+        soundtouch.set_setting(Setting::UseQuickseek, 0);
+        soundtouch.set_setting(Setting::UseAaFilter, 1);
+        soundtouch.set_setting(Setting::SequenceMs, 30);
+        soundtouch.set_setting(Setting::SeekwindowMs, 15);
+        soundtouch.set_setting(Setting::OverlapMs, 8);
+        soundtouch
+    }
+
+    /// 变速0.3 - 2.0，变调和速度无关
+    pub fn sound_touch(data_i16: &Vec<i16>, sr: u32, atempo: f64, pitch_semitones: f64) -> Vec<f32> {
+        // NaN 和 0.5 比较总是 false，会一直递归下去
+        if !atempo.is_finite() || atempo <= 0.0 || !pitch_semitones.is_finite() {
+            log::warn!("invalid atempo {} / pitch {}, audio unchanged", atempo, pitch_semitones);
+            return FfmpegUtils::_sound_touch(data_i16, sr, 1.0, 0.0);
+        }
         if atempo >= 0.5 {
-            let wavs: Vec<f32> = FfmpegUtils::_sound_touch(data_i16, sr, atempo);
+            let wavs: Vec<f32> = FfmpegUtils::_sound_touch(data_i16, sr, atempo, pitch_semitones);
             return wavs;
         } else {
-            // 0.3-> 0.66666 + 0.5
+            // 0.3-> 0.66666 + 0.5，只在第一次变调
             let atempo = atempo * 2.0;
-            let data_x2 = FfmpegUtils::sound_touch(&data_i16, sr, atempo);
-            let wavs: Vec<i16> = data_x2.iter().map(|&x| (x * 32768.0) as i16).collect();
-            let data_x5 = FfmpegUtils::sound_touch(&wavs, sr, 0.5);
+            let data_x2 = FfmpegUtils::sound_touch(&data_i16, sr, atempo, pitch_semitones);
+            let wavs: Vec<i16> = data_x2.iter().map(|&x| sample_to_i16(x)).collect();
+            let data_x5 = FfmpegUtils::sound_touch(&wavs, sr, 0.5, 0.0);
             return data_x5;
         }
     }
 }
 
+/// 分几次送进来的同一段音频用同一个 SoundTouch 变速变调，窗口之间连续；
+/// 同 sound_touch，0.5 以下分两级，第二级 0.5
+pub struct SoundTouchStream {
+    stages: Vec<SoundTouch>,
+}
+
+impl SoundTouchStream {
+    /// atempo、pitch_semitones 要是有限值
+    pub fn new(sr: u32, atempo: f64, pitch_semitones: f64) -> Self {
+        let stages = if atempo >= 0.5 {
+            vec![FfmpegUtils::new_sound_touch(sr, atempo, pitch_semitones)]
+        } else {
+            vec![FfmpegUtils::new_sound_touch(sr, atempo * 2.0, pitch_semitones), FfmpegUtils::new_sound_touch(sr, 0.5, 0.0)]
+        };
+        SoundTouchStream { stages }
+    }
+
+    /// 送进一块，返回已经处理好的部分
+    pub fn put(&mut self, wavs: &[f32]) -> Vec<f32> {
+        self.run(wavs, false)
+    }
+
+    /// 这一段结束：取出剩下的
+    pub fn flush(&mut self) -> Vec<f32> {
+        self.run(&[], true)
+    }
+
+    fn run(&mut self, wavs: &[f32], flush: bool) -> Vec<f32> {
+        let mut samples = wavs.to_vec();
+        for soundtouch in self.stages.iter_mut() {
+            if !samples.is_empty() {
+                soundtouch.put_samples(&samples, samples.len());
+            }
+            if flush {
+                soundtouch.flush();
+            }
+            samples.clear();
+            let mut buf = vec![0f32; 4096];
+            loop {
+                let n = soundtouch.receive_samples(&mut buf, buf.len());
+                if n == 0 {
+                    break;
+                }
+                samples.extend_from_slice(&buf[..n as usize]);
+            }
+        }
+        samples
+    }
+}
+
 
 #[test]
 fn test_datas() {
-    // let ref_wav_path = "/Users/jxinfa/PycharmProjects/sovits_infer/data/leilei.wav";
-    let ref_wav_path = "/Users/jxinfa/Downloads/tmp49dvo0e1.wav";
-    // let wav32k: Vec<i16> = FfmpegUtils::decode_audio_to_datas(ref_wav_path, 32000).unwrap();
-    let sr_to = 16000;
-    let wav16k: Vec<i16> = FfmpegUtils::decode_path_to_datas(ref_wav_path, sr_to).unwrap();
-
-    println!("wav16k:{}", wav16k.len() as f32 / sr_to as f32);
-    // FfmpegUtils::decode_data_to_path(&wav32k, "/Users/jxinfa/PycharmProjects/sovits_infer/data/leilei_make_32k.wav", 32000, 1024).unwrap();
-    FfmpegUtils::decode_data_to_path(&wav16k, "/Users/jxinfa/PycharmProjects/sovits_infer/data/leilei_make_16k.wav", sr_to, 1024).unwrap();
+    // 1 秒 440Hz 正弦写成 wav 再读回来，时长不变
+    let sr = 16000;
+    let wav16k: Vec<i16> = (0..sr)
+        .map(|i| ((i as f32 * 440.0 * 2.0 * std::f32::consts::PI / sr as f32).sin() * 8000.0) as i16)
+        .collect();
+    let path = std::env::temp_dir().join(format!("ffmpeg_utils_test_datas_{}.wav", std::process::id()));
+    let path = path.to_str().unwrap();
+    FfmpegUtils::decode_data_to_path(&wav16k, path, sr, 1024).unwrap();
+    let decoded = FfmpegUtils::decode_path_to_datas(path, sr).unwrap();
+    let _ = std::fs::remove_file(path);
+    assert!((decoded.len() as i64 - wav16k.len() as i64).abs() < 1024);
 }
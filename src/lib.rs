@@ -0,0 +1,21 @@
+pub mod error;
+pub mod text_utils;
+pub mod text;
+pub mod bert_utils;
+pub mod ffmpeg_utils;
+pub mod reference_voice;
+pub mod reference_check;
+pub mod transcriber;
+pub mod tts_engine;
+pub mod token_stream;
+pub mod post_process;
+pub mod pause;
+pub mod ssml;
+pub mod sampling;
+pub mod diagnostics;
+pub mod wav;
+pub mod model_bundle;
+pub mod execution;
+pub mod metrics;
+pub mod concurrency;
+pub mod worker_pool;
@@ -1,11 +1,4 @@
-mod text_utils;
-mod text;
-mod bert_utils;
-mod ffmpeg_utils;
-
-use english_numbers;
-use crate::bert_utils::{infer};
-use num_traits::sign::Signed;
+use rs_tokenizer::bert_utils::{infer};
 
 fn main() {
     infer();
@@ -0,0 +1,133 @@
+use std::fmt;
+use std::time::Instant;
+
+/// 单步耗时直方图的分桶上界：ms，最后一个桶是 > 500ms
+const LATENCY_BUCKETS_MS: [f64; 9] = [1.0, 2.0, 5.0, 10.0, 20.0, 50.0, 100.0, 200.0, 500.0];
+
+/// t2s stage decoder 每个 token 的耗时分布
+#[derive(Debug, Clone, Default)]
+pub struct LatencyHistogram {
+    /// counts[i]: 落在 (LATENCY_BUCKETS_MS[i-1], LATENCY_BUCKETS_MS[i]] 的次数
+    pub counts: [usize; LATENCY_BUCKETS_MS.len() + 1],
+    pub count: usize,
+    pub sum_ms: f64,
+    pub max_ms: f64,
+}
+
+impl LatencyHistogram {
+    pub fn record(&mut self, ms: f64) {
+        let bucket = LATENCY_BUCKETS_MS.iter().position(|&b| ms <= b).unwrap_or(LATENCY_BUCKETS_MS.len());
+        self.counts[bucket] += 1;
+        self.count += 1;
+        self.sum_ms += ms;
+        if ms > self.max_ms {
+            self.max_ms = ms;
+        }
+    }
+
+    pub fn mean_ms(&self) -> f64 {
+        if self.count == 0 { 0.0 } else { self.sum_ms / self.count as f64 }
+    }
+
+    /// 按桶估计的分位数：返回所在桶的上界
+    pub fn percentile_ms(&self, p: f64) -> f64 {
+        if self.count == 0 {
+            return 0.0;
+        }
+        let target = (p * self.count as f64).ceil().max(1.0) as usize;
+        let mut seen = 0;
+        for (i, &c) in self.counts.iter().enumerate() {
+            seen += c;
+            if seen >= target {
+                return LATENCY_BUCKETS_MS.get(i).copied().unwrap_or(self.max_ms).min(self.max_ms);
+            }
+        }
+        self.max_ms
+    }
+
+    pub fn merge(&mut self, other: &LatencyHistogram) {
+        for (a, b) in self.counts.iter_mut().zip(other.counts.iter()) {
+            *a += b;
+        }
+        self.count += other.count;
+        self.sum_ms += other.sum_ms;
+        self.max_ms = self.max_ms.max(other.max_ms);
+    }
+}
+
+/// 一次合成各阶段的耗时，和音频一起返回
+#[derive(Debug, Clone, Default)]
+pub struct SynthesisMetrics {
+    /// 文本规整、分词、g2p
+    pub text_frontend_ms: f64,
+    pub bert_ms: f64,
+    /// 参考音频 ssl_model + vq_model_latent：参考音色生成时的耗时，复用参考音色时不会再花，不算进 total_ms
+    pub ssl_ms: f64,
+    pub first_stage_ms: f64,
+    /// stage decoder 总耗时，每个 token 的分布见 token_latency
+    pub decoder_ms: f64,
+    pub token_latency: LatencyHistogram,
+    /// vq_model
+    pub vocoder_ms: f64,
+    pub tokens: usize,
+    pub segments: usize,
+    pub audio_seconds: f64,
+    pub total_ms: f64,
+}
+
+impl SynthesisMetrics {
+    /// 实时率：合成耗时 / 音频时长，小于 1 比实时快
+    pub fn rtf(&self) -> f64 {
+        if self.audio_seconds > 0.0 { self.total_ms / 1000.0 / self.audio_seconds } else { 0.0 }
+    }
+
+    /// 累加一段的耗时
+    pub fn merge(&mut self, other: &SynthesisMetrics) {
+        self.text_frontend_ms += other.text_frontend_ms;
+        self.bert_ms += other.bert_ms;
+        self.first_stage_ms += other.first_stage_ms;
+        self.decoder_ms += other.decoder_ms;
+        self.token_latency.merge(&other.token_latency);
+        self.vocoder_ms += other.vocoder_ms;
+        self.tokens += other.tokens;
+        self.segments += other.segments;
+        self.audio_seconds += other.audio_seconds;
+        self.total_ms += other.total_ms;
+    }
+
+    /// 通过 log 输出，target: sovits::metrics，需要时打开这个 target 的 info 级别
+    pub fn log(&self) {
+        log::info!(target: "sovits::metrics", "{}", self);
+    }
+}
+
+impl fmt::Display for SynthesisMetrics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f,
+               "segments={} tokens={} audio={:.2}s total={:.1}ms rtf={:.3} frontend={:.1}ms bert={:.1}ms ssl={:.1}ms first_stage={:.1}ms decoder={:.1}ms token_mean={:.2}ms token_p50={:.0}ms token_p95={:.0}ms token_max={:.1}ms vocoder={:.1}ms",
+               self.segments, self.tokens, self.audio_seconds, self.total_ms, self.rtf(),
+               self.text_frontend_ms, self.bert_ms, self.ssl_ms, self.first_stage_ms, self.decoder_ms,
+               self.token_latency.mean_ms(), self.token_latency.percentile_ms(0.5), self.token_latency.percentile_ms(0.95),
+               self.token_latency.max_ms, self.vocoder_ms)
+    }
+}
+
+/// 从 start 到现在的毫秒数
+pub fn elapsed_ms(start: Instant) -> f64 {
+    start.elapsed().as_secs_f64() * 1000.0
+}
+
+#[test]
+fn test_latency_histogram() {
+    let mut h = LatencyHistogram::default();
+    for ms in [0.5, 3.0, 4.0, 8.0, 700.0] {
+        h.record(ms);
+    }
+    assert_eq!(h.count, 5);
+    assert_eq!(h.counts[0], 1);
+    assert_eq!(h.counts[2], 2);
+    assert_eq!(h.counts[LATENCY_BUCKETS_MS.len()], 1);
+    assert_eq!(h.percentile_ms(0.5), 5.0);
+    assert_eq!(h.percentile_ms(1.0), 700.0);
+    assert!((h.mean_ms() - 143.1).abs() < 1e-6);
+}
@@ -0,0 +1,246 @@
+use std::path::Path;
+use ort::{Session, ValueType};
+use serde::{Deserialize, Serialize};
+use crate::error::TtsError;
+use crate::sampling::{EOS_TOKEN, exposes_logits};
+use crate::text::symbols::SYMBOLS;
+
+/// vq_model 输出、refer 输入的采样率；ssl_model 的输入固定 16k
+pub const SAMPLE_RATE: i32 = 32000;
+/// vq_model 的帧移、窗长
+pub const HOP_LENGTH: usize = 640;
+pub const WIN_LENGTH: usize = 2048;
+/// 中文 bert 的 hidden size，英文用同样维度的 0
+pub const BERT_DIM: usize = 1024;
+/// symbols.rs 的版本：322 个符号
+pub const SYMBOL_TABLE_VERSION: &str = "v1";
+
+/// 模型目录下的 model.json：描述 export_onnx.py 导出的一套模型
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ModelManifest {
+    pub files: ModelFiles,
+    pub sample_rate: i32,
+    pub hop_length: usize,
+    pub win_length: usize,
+    pub eos_token: i64,
+    pub bert_dim: usize,
+    pub symbol_table_version: String,
+}
+
+/// 相对 model.json 所在目录的文件名
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ModelFiles {
+    pub tokenizer: String,
+    pub bert_model: String,
+    pub ssl_model: String,
+    pub vq_model_latent: String,
+    pub vq_model: String,
+    pub t2s_first_stage_decoder: String,
+    pub t2s_stage_decoder: String,
+}
+
+impl Default for ModelFiles {
+    fn default() -> Self {
+        ModelFiles {
+            tokenizer: "tokenizer.json".to_string(),
+            bert_model: "bert_model.onnx".to_string(),
+            ssl_model: "ssl_model.onnx".to_string(),
+            vq_model_latent: "vq_model_latent.onnx".to_string(),
+            vq_model: "vq_model.onnx".to_string(),
+            t2s_first_stage_decoder: "t2s_first_stage_decoder.onnx".to_string(),
+            t2s_stage_decoder: "t2s_stage_decoder.onnx".to_string(),
+        }
+    }
+}
+
+impl Default for ModelManifest {
+    fn default() -> Self {
+        ModelManifest {
+            files: ModelFiles::default(),
+            sample_rate: SAMPLE_RATE,
+            hop_length: HOP_LENGTH,
+            win_length: WIN_LENGTH,
+            eos_token: EOS_TOKEN,
+            bert_dim: BERT_DIM,
+            symbol_table_version: SYMBOL_TABLE_VERSION.to_string(),
+        }
+    }
+}
+
+impl ModelManifest {
+    /// 读 model_dir/model.json，没有这个文件就按默认文件名
+    pub fn load(model_dir: &str) -> Result<Self, TtsError> {
+        let manifest_path = Path::new(model_dir).join("model.json");
+        if !manifest_path.exists() {
+            return Ok(ModelManifest::default());
+        }
+        let path = manifest_path.to_string_lossy().to_string();
+        let content = std::fs::read_to_string(&manifest_path)?;
+        let manifest: ModelManifest = serde_json::from_str(&content)
+            .map_err(|e| TtsError::Config { path: path.clone(), message: e.to_string() })?;
+        manifest.check_constants()
+            .map_err(|message| TtsError::Config { path, message })?;
+        Ok(manifest)
+    }
+
+    /// 推理代码里写死的常量和 manifest 不一致就不能用
+    pub fn check_constants(&self) -> Result<(), String> {
+        let mut problems = vec![];
+        if self.sample_rate != SAMPLE_RATE {
+            problems.push(format!("sample_rate {} (supported: {})", self.sample_rate, SAMPLE_RATE));
+        }
+        if self.hop_length != HOP_LENGTH {
+            problems.push(format!("hop_length {} (supported: {})", self.hop_length, HOP_LENGTH));
+        }
+        if self.win_length != WIN_LENGTH {
+            problems.push(format!("win_length {} (supported: {})", self.win_length, WIN_LENGTH));
+        }
+        if self.eos_token != EOS_TOKEN {
+            problems.push(format!("eos_token {} (supported: {})", self.eos_token, EOS_TOKEN));
+        }
+        if self.bert_dim != BERT_DIM {
+            problems.push(format!("bert_dim {} (supported: {})", self.bert_dim, BERT_DIM));
+        }
+        if self.symbol_table_version != SYMBOL_TABLE_VERSION {
+            problems.push(format!("symbol_table_version {} (supported: {}, {} symbols)", self.symbol_table_version, SYMBOL_TABLE_VERSION, SYMBOLS.len()));
+        }
+        if problems.is_empty() { Ok(()) } else { Err(problems.join("; ")) }
+    }
+}
+
+/// 检查 Session 的输入输出和推理代码用的是否一致，所有问题一起报
+pub struct SessionCheck<'a> {
+    model: &'a str,
+    session: &'a Session,
+    problems: Vec<String>,
+}
+
+impl<'a> SessionCheck<'a> {
+    pub fn new(model: &'a str, session: &'a Session) -> Self {
+        SessionCheck { model, session, problems: vec![] }
+    }
+
+    pub fn inputs(mut self, names: &[&str]) -> Self {
+        let actual: Vec<&str> = self.session.inputs.iter().map(|i| i.name.as_str()).collect();
+        for name in missing(names, &actual) {
+            self.problems.push(format!("missing input `{}` (has: {})", name, actual.join(", ")));
+        }
+        self
+    }
+
+    /// 按位置传参的模型只检查个数
+    pub fn input_count(mut self, count: usize) -> Self {
+        if self.session.inputs.len() != count {
+            self.problems.push(format!("expected {} inputs, found {}", count, self.session.inputs.len()));
+        }
+        self
+    }
+
+    pub fn outputs(mut self, names: &[&str]) -> Self {
+        let actual: Vec<&str> = self.session.outputs.iter().map(|o| o.name.as_str()).collect();
+        for name in missing(names, &actual) {
+            self.problems.push(format!("missing output `{}` (has: {})", name, actual.join(", ")));
+        }
+        self
+    }
+
+    /// 维度是动态的（-1）不检查
+    pub fn input_dim(mut self, name: &str, axis: usize, expected: usize) -> Self {
+        let dims = self.session.inputs.iter().find(|i| i.name == name).and_then(|i| dims(&i.input_type));
+        self.check_dim("input", name, dims, axis, expected);
+        self
+    }
+
+    pub fn output_dim(mut self, name: &str, axis: usize, expected: usize) -> Self {
+        let dims = self.session.outputs.iter().find(|o| o.name == name).and_then(|o| dims(&o.output_type));
+        self.check_dim("output", name, dims, axis, expected);
+        self
+    }
+
+    fn check_dim(&mut self, kind: &str, name: &str, dims: Option<Vec<i64>>, axis: usize, expected: usize) {
+        if let Some(dim) = dims.and_then(|d| d.get(axis).copied()) {
+            if dim >= 0 && dim as usize != expected {
+                self.problems.push(format!("{} `{}` axis {} is {}, expected {}", kind, name, axis, dim, expected));
+            }
+        }
+    }
+
+    pub fn finish(self) -> Result<(), TtsError> {
+        if self.problems.is_empty() {
+            Ok(())
+        } else {
+            Err(TtsError::ModelSignature { model: self.model.to_string(), message: self.problems.join("; ") })
+        }
+    }
+}
+
+/// bert_model 按位置传 input_ids, attention_mask, token_type_ids
+pub fn check_bert(bert_model: &Session) -> Result<(), TtsError> {
+    SessionCheck::new("bert_model", bert_model)
+        .input_count(3)
+        .outputs(&["hidden_states"])
+        .output_dim("hidden_states", 2, BERT_DIM)
+        .finish()
+}
+
+pub fn check_ssl(ssl_model: &Session) -> Result<(), TtsError> {
+    SessionCheck::new("ssl_model", ssl_model)
+        .input_count(1)
+        .outputs(&["output"])
+        .finish()
+}
+
+/// GPT 权重：t2s_first_stage_decoder、t2s_stage_decoder
+pub fn check_gpt(t2s_first_stage_decoder: &Session, t2s_stage_decoder: &Session) -> Result<(), TtsError> {
+    SessionCheck::new("t2s_first_stage_decoder", t2s_first_stage_decoder)
+        .inputs(&["all_phoneme_ids", "bert", "prompt", "top_k", "temperature"])
+        .outputs(&["y", "k", "v", "y_emb"])
+        .input_dim("bert", 1, BERT_DIM)
+        .finish()?;
+
+    let stage = SessionCheck::new("t2s_stage_decoder", t2s_stage_decoder)
+        .inputs(&["y", "k", "v", "y_emb", "xy_attn_mask", "top_k", "temperature"])
+        .outputs(&["o_k", "o_v", "o_y_emb", "logits"]);
+    // float logits 在 Rust 这边采样，不需要 samples
+    let stage = if exposes_logits(t2s_stage_decoder) { stage } else { stage.outputs(&["samples"]) };
+    stage.finish()
+}
+
+/// SoVITS 权重：vq_model_latent、vq_model
+pub fn check_sovits(vq_model_latent: &Session, vq_model: &Session) -> Result<(), TtsError> {
+    SessionCheck::new("vq_model_latent", vq_model_latent)
+        .input_count(1)
+        .outputs(&["output"])
+        .finish()?;
+    SessionCheck::new("vq_model", vq_model)
+        .inputs(&["pred_semantic", "text", "org_audio", "hann_window", "refer_mask", "y_lengths", "text_lengths"])
+        .outputs(&["audio"])
+        .input_dim("hann_window", 0, WIN_LENGTH)
+        .finish()
+}
+
+fn dims(value_type: &ValueType) -> Option<Vec<i64>> {
+    match value_type {
+        ValueType::Tensor { dimensions, .. } => Some(dimensions.clone()),
+        _ => None,
+    }
+}
+
+fn missing<'a>(expected: &[&'a str], actual: &[&str]) -> Vec<&'a str> {
+    expected.iter().filter(|name| !actual.contains(name)).copied().collect()
+}
+
+#[test]
+fn test_manifest_check() {
+    let manifest: ModelManifest = serde_json::from_str(r#"{"sample_rate": 32000, "hop_length": 512}"#).unwrap();
+    assert_eq!(manifest.files.vq_model, "vq_model.onnx");
+    let message = manifest.check_constants().unwrap_err();
+    assert!(message.contains("hop_length 512"));
+    let manifest: ModelManifest = serde_json::from_str(r#"{"sample_rate": 48000}"#).unwrap();
+    assert!(manifest.check_constants().unwrap_err().contains("sample_rate 48000"));
+    assert!(ModelManifest::default().check_constants().is_ok());
+
+    assert_eq!(missing(&["y", "k", "o_y_emb"], &["y", "k"]), vec!["o_y_emb"]);
+}
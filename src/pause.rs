@@ -0,0 +1,181 @@
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use crate::ssml::is_placeholder;
+use crate::text_utils::LangSegment;
+
+/// 分段结尾是什么边界，决定后面插多长的停顿
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Boundary {
+    /// 换行
+    Paragraph,
+    /// 。！？
+    Sentence,
+    /// ，、 以及没有标点的切分
+    Comma,
+    /// 文本里写的 [pause=500ms]：秒
+    Markup(f32),
+}
+
+/// 每种边界的停顿：秒
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PauseConfig {
+    pub paragraph: f32,
+    pub sentence: f32,
+    pub comma: f32,
+}
+
+/// 一个停顿最长：秒，标记、SSML 和配置里更长的都截到这里
+pub const MAX_PAUSE_SECONDS: f32 = 10.0;
+
+/// 截到 0 ~ MAX_PAUSE_SECONDS，NaN 当作 0
+fn clamp_pause(seconds: f32) -> f32 {
+    if seconds.is_nan() { 0.0 } else { seconds.clamp(0.0, MAX_PAUSE_SECONDS) }
+}
+
+impl Default for PauseConfig {
+    fn default() -> Self {
+        PauseConfig { paragraph: 0.6, sentence: 0.3, comma: 0.15 }
+    }
+}
+
+impl PauseConfig {
+    /// 所有边界一样长
+    pub fn uniform(seconds: f32) -> Self {
+        PauseConfig { paragraph: seconds, sentence: seconds, comma: seconds }
+    }
+
+    pub fn seconds(&self, boundary: Boundary) -> f32 {
+        let seconds = match boundary {
+            Boundary::Paragraph => self.paragraph,
+            Boundary::Sentence => self.sentence,
+            Boundary::Comma => self.comma,
+            Boundary::Markup(seconds) => seconds,
+        };
+        clamp_pause(seconds)
+    }
+}
+
+const SENTENCE_MARKS: &[char] = &['。', '！', '？', '.', '!', '?', '…', '；', ';'];
+
+/// 两段之间被切掉的那部分文字 -> 边界类型
+fn classify_gap(gap: &str) -> Boundary {
+    if gap.contains('\n') {
+        Boundary::Paragraph
+    } else if gap.contains(SENTENCE_MARKS) {
+        Boundary::Sentence
+    } else {
+        Boundary::Comma
+    }
+}
+
+/// 文字末尾的标点 -> 边界类型，没有标点按逗号
+pub(crate) fn trailing_boundary(text: &str) -> Boundary {
+    let content_end = text.rfind(|c: char| is_content(c)).map(|i| i + text[i..].chars().next().unwrap().len_utf8()).unwrap_or(0);
+    classify_gap(&text[content_end..].replace('\n', ""))
+}
+
+/// 两个边界挨在一起：标记的停顿相加，有标记用标记，否则取停顿长的
+pub(crate) fn merge_boundary(a: Boundary, b: Boundary) -> Boundary {
+    let rank = |boundary: Boundary| match boundary {
+        Boundary::Comma => 0,
+        Boundary::Sentence => 1,
+        _ => 2,
+    };
+    match (a, b) {
+        (Boundary::Markup(x), Boundary::Markup(y)) => Boundary::Markup(clamp_pause(x + y)),
+        (Boundary::Markup(_), _) => a,
+        (_, Boundary::Markup(_)) => b,
+        _ if rank(b) > rank(a) => b,
+        _ => a,
+    }
+}
+
+// 文字、数字，以及 SSML 的占位符
+fn is_content(c: char) -> bool {
+    c.is_alphanumeric() || is_placeholder(c)
+}
+
+/// "500ms" / "1.5s" / "1.5" -> 秒，最长 MAX_PAUSE_SECONDS；inf、NaN 当作写错
+pub(crate) fn parse_duration(value: &str) -> Option<f32> {
+    let value = value.trim();
+    let seconds = match value.strip_suffix("ms") {
+        Some(ms) => ms.trim().parse::<f32>().ok()? / 1000.0,
+        None => value.strip_suffix('s').unwrap_or(value).trim().parse::<f32>().ok()?,
+    };
+    seconds.is_finite().then(|| clamp_pause(seconds))
+}
+
+/// 按 [pause=500ms] 标记拆开：(文字, 后面的停顿)
+fn split_markup(text: &str) -> Vec<(String, Option<f32>)> {
+    let pattern = Regex::new(r"\[pause=([^\]]*)\]").unwrap();
+    let mut parts = vec![];
+    let mut last = 0;
+    for caps in pattern.captures_iter(text) {
+        let m = caps.get(0).unwrap();
+        // 写错的时长按没有标记处理，只切分
+        parts.push((text[last..m.start()].to_string(), Some(parse_duration(&caps[1]).unwrap_or(0.0))));
+        last = m.end();
+    }
+    parts.push((text[last..].to_string(), None));
+    parts
+}
+
+/// 按行切段落，段落内按 cut_texts 切分，每段标上结尾的边界；去掉空段
+///
+/// 切分只会去掉标点和换行，用文字、数字对齐回原文找两段之间的标点
+pub fn cut_texts_with_boundaries(lang_seg: &LangSegment, text: &str, max_num: usize) -> Vec<(String, Boundary)> {
+    let mut segments: Vec<(String, Boundary)> = vec![];
+    for (part, pause) in split_markup(text) {
+        for paragraph in part.split('\n').map(|p| p.trim()).filter(|p| !p.is_empty()) {
+            let chars: Vec<char> = paragraph.chars().collect();
+            let content: Vec<usize> = (0..chars.len()).filter(|&i| is_content(chars[i])).collect();
+            // 只有标点的行 cut_texts 处理不了，也没有可以发音的内容
+            if content.is_empty() {
+                continue;
+            }
+            let mut consumed = 0;
+            for segment in lang_seg.cut_texts(&paragraph.to_string(), max_num) {
+                consumed += segment.chars().filter(|&c| is_content(c)).count();
+                if segment.trim().is_empty() {
+                    continue;
+                }
+                let boundary = match (consumed.checked_sub(1).and_then(|i| content.get(i)), content.get(consumed)) {
+                    (Some(&end), Some(&next)) => classify_gap(&chars[end + 1..next].iter().collect::<String>()),
+                    _ => Boundary::Paragraph,
+                };
+                segments.push((segment, boundary));
+            }
+        }
+        if let (Some(pause), Some(last)) = (pause, segments.last_mut()) {
+            // 连着的几个标记加在一起
+            last.1 = match last.1 {
+                Boundary::Markup(_) => merge_boundary(last.1, Boundary::Markup(pause)),
+                _ => Boundary::Markup(pause),
+            };
+        }
+    }
+    segments
+}
+
+#[test]
+fn test_pause_markup() {
+    assert_eq!(classify_gap("，"), Boundary::Comma);
+    assert_eq!(classify_gap("！”"), Boundary::Sentence);
+    assert_eq!(classify_gap("。\n"), Boundary::Paragraph);
+    assert_eq!(parse_duration("500ms"), Some(0.5));
+    assert_eq!(parse_duration("1.5s"), Some(1.5));
+    assert_eq!(parse_duration("abc"), None);
+    assert_eq!(parse_duration("inf"), None);
+    assert_eq!(parse_duration("1e12s"), Some(MAX_PAUSE_SECONDS));
+    assert_eq!(PauseConfig::uniform(f32::INFINITY).seconds(Boundary::Comma), MAX_PAUSE_SECONDS);
+    assert_eq!(trailing_boundary("你好。"), Boundary::Sentence);
+    assert_eq!(merge_boundary(Boundary::Comma, Boundary::Paragraph), Boundary::Paragraph);
+
+    let parts = split_markup("你好[pause=1s]世界[pause=200ms]");
+    assert_eq!(parts, vec![
+        ("你好".to_string(), Some(1.0)),
+        ("世界".to_string(), Some(0.2)),
+        ("".to_string(), None),
+    ]);
+}
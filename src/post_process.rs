@@ -0,0 +1,268 @@
+use std::f32::consts::PI;
+use serde::{Deserialize, Serialize};
+
+/// 响度目标：dB
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Loudness {
+    /// ITU-R BS.1770 积分响度，广播常用 -23，播客常用 -16
+    Lufs(f32),
+    /// 整段的 RMS：dBFS
+    Rms(f32),
+}
+
+/// 每段音频转成 pcm16 之前的后处理，顺序：去直流 -> 裁首尾静音 -> 响度归一化 -> 淡入淡出 -> 转 pcm16
+///
+/// 默认都不做，只保证转换不溢出
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PostProcessConfig {
+    /// 高通去掉直流偏置
+    pub remove_dc: bool,
+    /// 首尾低于这个电平（dBFS）的部分裁掉，None: 不裁
+    pub trim_silence_db: Option<f32>,
+    /// 裁剪后首尾保留的静音：毫秒
+    pub trim_keep_ms: f32,
+    /// None: 不做响度归一化
+    pub loudness: Option<Loudness>,
+    /// 响度归一化后峰值不超过：dBFS
+    pub peak_db: f32,
+    /// 每段首尾的淡入淡出：毫秒，0: 不做
+    pub fade_ms: f32,
+}
+
+impl Default for PostProcessConfig {
+    fn default() -> Self {
+        PostProcessConfig {
+            remove_dc: false,
+            trim_silence_db: None,
+            trim_keep_ms: 20.0,
+            loudness: None,
+            peak_db: -1.0,
+            fade_ms: 0.0,
+        }
+    }
+}
+
+impl PostProcessConfig {
+    /// vq_model 输出的一段 -> pcm16
+    pub fn process(&self, mut audio: Vec<f32>, sample_rate: i32) -> Vec<i16> {
+        if self.remove_dc {
+            remove_dc(&mut audio);
+        }
+        if let Some(threshold_db) = self.trim_silence_db {
+            let keep = ms_to_samples(self.trim_keep_ms, sample_rate);
+            let (start, end) = voiced_range(&audio, sample_rate, threshold_db);
+            audio.truncate((end + keep).min(audio.len()));
+            audio.drain(..start.saturating_sub(keep));
+        }
+        if let Some(loudness) = self.loudness {
+            normalize_loudness(&mut audio, sample_rate, loudness, db_to_gain(self.peak_db));
+        }
+        if self.fade_ms > 0.0 {
+            fade(&mut audio, ms_to_samples(self.fade_ms, sample_rate));
+        }
+        audio_to_pcm16(&audio)
+    }
+}
+
+/// [-1, 1] -> pcm16：峰值超过 1.0 按峰值归一化，否则四舍五入并截断到 i16 范围
+pub fn audio_to_pcm16(audio: &[f32]) -> Vec<i16> {
+    let peak = audio.iter().fold(0.0f32, |max, &x| max.max(x.abs()));
+    let scale = if peak > 1.0 { 1.0 / peak } else { 1.0 };
+    audio.iter().map(|&x| sample_to_i16(x * scale)).collect()
+}
+
+/// 单个采样点，超出 [-1, 1] 的截断
+pub fn sample_to_i16(x: f32) -> i16 {
+    (x * 32767.0).round().clamp(-32768.0, 32767.0) as i16
+}
+
+pub fn db_to_gain(db: f32) -> f32 {
+    10f32.powf(db / 20.0)
+}
+
+fn ms_to_samples(ms: f32, sample_rate: i32) -> usize {
+    (ms * sample_rate as f32 / 1000.0) as usize
+}
+
+/// 二阶 IIR 滤波器，系数按 RBJ Audio EQ Cookbook
+#[derive(Debug, Clone)]
+pub struct Biquad {
+    b: [f32; 3],
+    a: [f32; 2],
+    x: [f32; 2],
+    y: [f32; 2],
+}
+
+impl Biquad {
+    fn init(b: [f32; 3], a: [f32; 3]) -> Self {
+        Biquad {
+            b: [b[0] / a[0], b[1] / a[0], b[2] / a[0]],
+            a: [a[1] / a[0], a[2] / a[0]],
+            x: [0.0; 2],
+            y: [0.0; 2],
+        }
+    }
+
+    pub fn high_pass(sample_rate: i32, cutoff: f32, q: f32) -> Self {
+        let w0 = 2.0 * PI * cutoff / sample_rate as f32;
+        let (cos, alpha) = (w0.cos(), w0.sin() / (2.0 * q));
+        Biquad::init(
+            [(1.0 + cos) / 2.0, -(1.0 + cos), (1.0 + cos) / 2.0],
+            [1.0 + alpha, -2.0 * cos, 1.0 - alpha],
+        )
+    }
+
+    pub fn high_shelf(sample_rate: i32, cutoff: f32, q: f32, gain_db: f32) -> Self {
+        let a = 10f32.powf(gain_db / 40.0);
+        let w0 = 2.0 * PI * cutoff / sample_rate as f32;
+        let (cos, alpha) = (w0.cos(), w0.sin() / (2.0 * q));
+        let sqrt_a_alpha = 2.0 * a.sqrt() * alpha;
+        Biquad::init(
+            [
+                a * ((a + 1.0) + (a - 1.0) * cos + sqrt_a_alpha),
+                -2.0 * a * ((a - 1.0) + (a + 1.0) * cos),
+                a * ((a + 1.0) + (a - 1.0) * cos - sqrt_a_alpha),
+            ],
+            [
+                (a + 1.0) - (a - 1.0) * cos + sqrt_a_alpha,
+                2.0 * ((a - 1.0) - (a + 1.0) * cos),
+                (a + 1.0) - (a - 1.0) * cos - sqrt_a_alpha,
+            ],
+        )
+    }
+
+    pub fn process(&mut self, x: f32) -> f32 {
+        let y = self.b[0] * x + self.b[1] * self.x[0] + self.b[2] * self.x[1]
+            - self.a[0] * self.y[0] - self.a[1] * self.y[1];
+        self.x = [x, self.x[0]];
+        self.y = [y, self.y[0]];
+        y
+    }
+}
+
+/// 一阶 dc blocker：y[n] = x[n] - x[n-1] + R * y[n-1]
+fn remove_dc(audio: &mut [f32]) {
+    const R: f32 = 0.995;
+    let (mut prev_x, mut prev_y) = (0.0, 0.0);
+    for x in audio.iter_mut() {
+        let y = *x - prev_x + R * prev_y;
+        prev_x = *x;
+        prev_y = y;
+        *x = y;
+    }
+}
+
+/// 按 10ms 一帧的 RMS 找第一个和最后一个超过阈值的帧，返回采样点范围；全都低于阈值时不裁
+pub(crate) fn voiced_range(audio: &[f32], sample_rate: i32, threshold_db: f32) -> (usize, usize) {
+    let frame = ms_to_samples(10.0, sample_rate).max(1);
+    let threshold = db_to_gain(threshold_db);
+    let voiced: Vec<usize> = audio.chunks(frame).enumerate()
+        .filter(|(_, chunk)| rms(chunk) >= threshold)
+        .map(|(i, _)| i)
+        .collect();
+    match (voiced.first(), voiced.last()) {
+        (Some(&first), Some(&last)) => (first * frame, ((last + 1) * frame).min(audio.len())),
+        _ => (0, audio.len()),
+    }
+}
+
+pub(crate) fn rms(audio: &[f32]) -> f32 {
+    if audio.is_empty() {
+        return 0.0;
+    }
+    (audio.iter().map(|&x| x * x).sum::<f32>() / audio.len() as f32).sqrt()
+}
+
+/// BS.1770 积分响度：K 加权，400ms 一块、重叠 75%，-70 LUFS 绝对门限和 -10 LU 相对门限；不到 400ms 的按整段算
+pub fn integrated_lufs(audio: &[f32], sample_rate: i32) -> Option<f32> {
+    let mut shelf = Biquad::high_shelf(sample_rate, 1500.0, 1.0 / 2f32.sqrt(), 4.0);
+    let mut high_pass = Biquad::high_pass(sample_rate, 38.0, 0.5);
+    let weighted: Vec<f32> = audio.iter().map(|&x| high_pass.process(shelf.process(x))).collect();
+
+    let block = ms_to_samples(400.0, sample_rate).max(1).min(weighted.len().max(1));
+    let hop = (block / 4).max(1);
+    let mut powers = vec![];
+    let mut start = 0;
+    while start + block <= weighted.len() {
+        let chunk = &weighted[start..start + block];
+        powers.push(chunk.iter().map(|&x| x * x).sum::<f32>() / block as f32);
+        start += hop;
+    }
+
+    let loudness = |power: f32| -0.691 + 10.0 * power.log10();
+    let mean = |powers: &[f32]| if powers.is_empty() { None } else { Some(powers.iter().sum::<f32>() / powers.len() as f32) };
+    // 相对门限只在过了绝对门限 -70 的块里算（BS.1770）
+    let powers: Vec<f32> = powers.into_iter().filter(|&p| loudness(p) > -70.0).collect();
+    let threshold = loudness(mean(&powers)?) - 10.0;
+    let gated: Vec<f32> = powers.into_iter().filter(|&p| loudness(p) > threshold).collect();
+    Some(loudness(mean(&gated)?))
+}
+
+/// 按目标响度整体调增益，峰值不超过 peak
+fn normalize_loudness(audio: &mut [f32], sample_rate: i32, target: Loudness, peak: f32) {
+    let (measured, target_db) = match target {
+        Loudness::Lufs(target) => (integrated_lufs(audio, sample_rate), target),
+        Loudness::Rms(target) => {
+            let rms = rms(audio);
+            (if rms > 0.0 { Some(20.0 * rms.log10()) } else { None }, target)
+        }
+    };
+    // 静音段不放大
+    let measured = match measured {
+        Some(measured) => measured,
+        None => return,
+    };
+    let max = audio.iter().fold(0.0f32, |max, &x| max.max(x.abs()));
+    let mut gain = db_to_gain(target_db - measured);
+    if max * gain > peak {
+        gain = peak / max;
+    }
+    for x in audio.iter_mut() {
+        *x *= gain;
+    }
+}
+
+/// 首尾线性淡入淡出，段太短时各占一半
+fn fade(audio: &mut [f32], samples: usize) {
+    let n = samples.min(audio.len() / 2);
+    let len = audio.len();
+    for i in 0..n {
+        let w = i as f32 / n as f32;
+        audio[i] *= w;
+        audio[len - 1 - i] *= w;
+    }
+}
+
+#[test]
+fn test_post_process() {
+    assert_eq!(audio_to_pcm16(&[1.0, -1.0, 0.0]), vec![32767, -32767, 0]);
+    assert_eq!(audio_to_pcm16(&[2.0, -1.0]), vec![32767, -16384]);
+
+    // 200ms 静音 + 1s 440Hz + 200ms 静音
+    let sample_rate = 32000;
+    let mut audio = vec![0.0; 6400];
+    audio.extend((0..32000).map(|i| 0.1 * (2.0 * PI * 440.0 * i as f32 / sample_rate as f32).sin()));
+    audio.extend(vec![0.0; 6400]);
+
+    let config = PostProcessConfig {
+        trim_silence_db: Some(-50.0),
+        trim_keep_ms: 0.0,
+        loudness: Some(Loudness::Rms(-20.0)),
+        fade_ms: 5.0,
+        ..PostProcessConfig::default()
+    };
+    let pcm = config.process(audio, sample_rate);
+    assert_eq!(pcm.len(), 32000);
+    assert_eq!(pcm[0], 0);
+    let rms_db = 20.0 * rms(&pcm.iter().map(|&x| x as f32 / 32767.0).collect::<Vec<f32>>()).log10();
+    assert!((rms_db + 20.0).abs() < 0.1);
+
+    // 很小声的时候，低于 -70 LUFS 的块不能因为过了相对门限又算进来
+    let tone = |amplitude: f32, seconds: usize| (0..sample_rate as usize * seconds).map(move |i| amplitude * (2.0 * PI * 440.0 * i as f32 / sample_rate as f32).sin());
+    let loud: Vec<f32> = tone(1.2e-3, 1).collect();
+    let mixed: Vec<f32> = tone(1.2e-3, 1).chain(tone(4e-4, 2)).collect();
+    let (loud, mixed) = (integrated_lufs(&loud, sample_rate).unwrap(), integrated_lufs(&mixed, sample_rate).unwrap());
+    assert!((loud - mixed).abs() < 1.0, "{} {}", loud, mixed);
+}
@@ -0,0 +1,162 @@
+use serde::{Deserialize, Serialize};
+use crate::error::TtsError;
+use crate::post_process::{rms, sample_to_i16, voiced_range, Biquad};
+
+/// 参考音频的检查和预处理：高通去低频噪声 -> 能量 VAD 裁首尾静音 -> 检查时长、削波、音量
+///
+/// 上游 GPT-SoVITS 要求参考音频 3~10 秒
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ReferenceCheck {
+    /// false: 原样使用，不检查也不处理
+    pub enabled: bool,
+    /// 裁掉首尾静音后的时长：秒，超出范围报错；多段混合时只检查作为 prompt 的段
+    pub min_seconds: f32,
+    pub max_seconds: f32,
+    /// 首尾低于这个电平（dBFS）的部分裁掉
+    pub trim_db: f32,
+    /// 裁剪后首尾保留的静音：毫秒
+    pub trim_keep_ms: f32,
+    /// 高通截止频率：Hz，0: 不滤
+    pub high_pass_hz: f32,
+    /// RMS 低于这个电平（dBFS）当作没有声音，报错
+    pub silent_db: f32,
+    /// RMS 低于这个电平给警告
+    pub quiet_db: f32,
+    /// 削波的采样点占比超过这个值给警告
+    pub clip_warn_ratio: f32,
+    /// 超过这个值报错
+    pub clip_error_ratio: f32,
+}
+
+impl Default for ReferenceCheck {
+    fn default() -> Self {
+        ReferenceCheck {
+            enabled: true,
+            min_seconds: 3.0,
+            max_seconds: 10.0,
+            trim_db: -40.0,
+            trim_keep_ms: 50.0,
+            high_pass_hz: 60.0,
+            silent_db: -50.0,
+            quiet_db: -35.0,
+            clip_warn_ratio: 0.001,
+            clip_error_ratio: 0.01,
+        }
+    }
+}
+
+/// 检查结果：裁剪范围和警告
+#[derive(Debug, Clone, Default)]
+pub struct ReferenceReport {
+    /// 保留的范围：秒
+    pub start: f32,
+    pub end: f32,
+    /// 削波的采样点占比
+    pub clipped_ratio: f32,
+    /// 保留部分的 RMS：dBFS
+    pub rms_db: f32,
+    pub warnings: Vec<String>,
+}
+
+impl ReferenceReport {
+    pub fn duration(&self) -> f32 {
+        self.end - self.start
+    }
+}
+
+// 差不多到满幅就算削波
+const CLIP_LEVEL: i16 = 32700;
+
+impl ReferenceCheck {
+    /// 检查参考音频，返回要保留的范围和警告；不能用的返回 InvalidReference
+    pub fn check(&self, wav: &[i16], sample_rate: i32) -> Result<ReferenceReport, TtsError> {
+        let report = self.check_timbre(wav, sample_rate)?;
+        let duration = report.duration();
+        if duration < self.min_seconds || duration > self.max_seconds {
+            return Err(TtsError::InvalidReference(format!("{:.2}s of speech, expected {}~{}s", duration, self.min_seconds, self.max_seconds)));
+        }
+        Ok(report)
+    }
+
+    /// 同 check，但不限时长：多段混合时只提供音色的段
+    pub fn check_timbre(&self, wav: &[i16], sample_rate: i32) -> Result<ReferenceReport, TtsError> {
+        let invalid = |message: String| Err(TtsError::InvalidReference(message));
+        if wav.is_empty() {
+            return invalid("empty audio".to_string());
+        }
+        let audio = self.high_pass(wav, sample_rate);
+
+        let clipped = wav.iter().filter(|&&x| x.saturating_abs() >= CLIP_LEVEL).count();
+        let clipped_ratio = clipped as f32 / wav.len() as f32;
+        if clipped_ratio > self.clip_error_ratio {
+            return invalid(format!("{:.1}% of samples are clipped", clipped_ratio * 100.0));
+        }
+
+        let (start, end) = voiced_range(&audio, sample_rate, self.trim_db);
+        let keep = (self.trim_keep_ms * sample_rate as f32 / 1000.0) as usize;
+        let (start, end) = (start.saturating_sub(keep), (end + keep).min(audio.len()));
+
+        let level = rms(&audio[start..end]);
+        let rms_db = if level > 0.0 { 20.0 * level.log10() } else { f32::NEG_INFINITY };
+        if rms_db < self.silent_db {
+            return invalid(format!("audio is nearly silent: {:.1} dBFS", rms_db));
+        }
+
+        let seconds = |samples: usize| samples as f32 / sample_rate as f32;
+
+        let mut warnings = vec![];
+        if clipped_ratio > self.clip_warn_ratio {
+            warnings.push(format!("{:.2}% of samples are clipped", clipped_ratio * 100.0));
+        }
+        if rms_db < self.quiet_db {
+            warnings.push(format!("audio is quiet: {:.1} dBFS", rms_db));
+        }
+        Ok(ReferenceReport { start: seconds(start), end: seconds(end), clipped_ratio, rms_db, warnings })
+    }
+
+    /// 按 check 的结果裁剪、高通；wav 可以是别的采样率
+    pub fn apply(&self, wav: &[i16], sample_rate: i32, report: &ReferenceReport) -> Vec<i16> {
+        let sample = |seconds: f32| ((seconds * sample_rate as f32).round() as usize).min(wav.len());
+        let (start, end) = (sample(report.start), sample(report.end));
+        self.high_pass(&wav[start..end.max(start)], sample_rate).iter().map(|&x| sample_to_i16(x)).collect()
+    }
+
+    fn high_pass(&self, wav: &[i16], sample_rate: i32) -> Vec<f32> {
+        let audio = wav.iter().map(|&x| x as f32 / 32768.0);
+        if self.high_pass_hz <= 0.0 {
+            return audio.collect();
+        }
+        let mut filter = Biquad::high_pass(sample_rate, self.high_pass_hz, 1.0 / 2f32.sqrt());
+        audio.map(|x| filter.process(x)).collect()
+    }
+}
+
+#[test]
+fn test_reference_check() {
+    use std::f32::consts::PI;
+    let sample_rate = 16000;
+    let tone = |seconds: f32, amplitude: f32| -> Vec<i16> {
+        (0..(seconds * sample_rate as f32) as usize)
+            .map(|i| sample_to_i16(amplitude * (2.0 * PI * 300.0 * i as f32 / sample_rate as f32).sin()))
+            .collect()
+    };
+    let check = ReferenceCheck::default();
+
+    // 1s 静音 + 4s 语音 + 1s 静音
+    let mut wav = vec![0; 16000];
+    wav.extend(tone(4.0, 0.3));
+    wav.extend(vec![0; 16000]);
+    let report = check.check(&wav, sample_rate).unwrap();
+    assert!((report.duration() - 4.1).abs() < 0.02);
+    assert!(report.warnings.is_empty());
+    assert_eq!(check.apply(&wav, sample_rate, &report).len(), (report.duration() * 16000.0).round() as usize);
+
+    let mut short = vec![0; 48000];
+    short.extend(tone(2.0, 0.3));
+    assert!(matches!(check.check(&short, sample_rate), Err(TtsError::InvalidReference(_))));
+    assert!((check.check_timbre(&short, sample_rate).unwrap().duration() - 2.05).abs() < 0.02);
+    assert!(check.check(&vec![0; 64000], sample_rate).is_err());
+    assert!(check.check(&tone(4.0, 2.0), sample_rate).is_err());
+    assert_eq!(check.check(&tone(4.0, 0.01), sample_rate).unwrap().warnings.len(), 1);
+}
@@ -0,0 +1,350 @@
+use std::borrow::Cow;
+use std::fs;
+use std::time::Instant;
+use log::{info, warn};
+use ndarray::{Array1, Array2, Axis};
+use ort::Session;
+use crate::bert_utils::get_prompt_semantic;
+use crate::error::TtsError;
+use crate::ffmpeg_utils::FfmpegUtils;
+use crate::metrics::elapsed_ms;
+use crate::model_bundle::{BERT_DIM, SAMPLE_RATE};
+use crate::tts_engine::TtsEngine;
+
+const VOICE_MAGIC: &[u8; 8] = b"SOVITSRV";
+const VOICE_VERSION: u32 = 1;
+/// 没有参考文字时按这个字数切分
+const REF_FREE_CUT_LEN: usize = 50;
+
+/// 多段参考音频里的一段
+#[derive(Debug, Clone)]
+pub struct ReferenceClip {
+    pub wav_path: String,
+    /// 作为 t2s prompt 的那一段需要参考文字，只提供音色的可以没有
+    pub prompt_text: Option<String>,
+    /// 相对权重
+    pub weight: f32,
+}
+
+/// t2s 的 prompt：参考文字的 phones、bert 和参考音频的 semantic codes
+pub struct Prompt<'a> {
+    pub semantic: Cow<'a, Array2<i64>>,
+    pub bert: Cow<'a, Array2<f32>>,
+    pub phones: Cow<'a, Vec<usize>>,
+}
+
+/// 参考音色：只跟参考音频、参考文字有关的部分，计算一次，合成多次
+pub struct ReferenceVoice {
+    pub prompt_text: String,
+    /// vq_model_latent 输出的 prompt semantic codes [1, T]
+    pub prompt_semantic: Array2<i64>,
+    /// [1, n] 32k，给 vq_model 的 org_audio (refer spectrogram)
+    pub wav32k_arr: Array2<f32>,
+    /// 参考文字的 phones
+    pub prompt_phones: Vec<usize>,
+    /// 参考文字的 bert features [1024, prompt_phones.len()]
+    pub prompt_bert: Array2<f32>,
+    /// 生成时 ssl_model + vq_model_latent 的耗时：ms，不保存，load 的是 0
+    pub ssl_ms: f64,
+    /// 参考音频检查的警告，不保存
+    pub warnings: Vec<String>,
+}
+
+impl ReferenceVoice {
+    /// ref_wav_path: 参考音色音频文件，prompt_text: 参考音色音频对应的文字
+    ///
+    /// 按 engine.config.reference_check 检查、预处理，不能用的返回 InvalidReference
+    pub fn from_file(engine: &TtsEngine, ref_wav_path: &str, prompt_text: &str) -> Result<Self, TtsError> {
+        ReferenceVoice::from_file_with(engine, &engine.vq_model_latent, ref_wav_path, prompt_text)
+    }
+
+    /// from_file，prompt semantic 用指定的 vq_model_latent，不用 engine 上的
+    pub(crate) fn from_file_with(engine: &TtsEngine, vq_model_latent: &Session, ref_wav_path: &str, prompt_text: &str) -> Result<Self, TtsError> {
+        let (wav16k, wav32k, warnings) = decode_reference(engine, ref_wav_path, true)?;
+        let voice = ReferenceVoice::from_pcm_with(engine, vq_model_latent, &wav16k, &wav32k, prompt_text)?;
+        Ok(ReferenceVoice { warnings, ..voice })
+    }
+
+    /// 多段参考音频合成一个音色
+    ///
+    /// vq_model 的 refer 是整段 org_audio 过 ref encoder 再在时间上取平均，所以把所有段拼起来，每段按权重伸缩时长；
+    /// t2s 的 prompt（参考文字 + semantic codes）只能来自一段，用有参考文字的段里权重最大的；
+    /// 都没有参考文字时用权重最大的一段，参考文字按 from_pcm 识别
+    pub fn from_clips(engine: &TtsEngine, clips: &[ReferenceClip]) -> Result<Self, TtsError> {
+        if clips.is_empty() {
+            return Err(TtsError::VoiceFile("no reference clip".to_string()));
+        }
+        if clips.iter().any(|clip| !(clip.weight > 0.0)) {
+            return Err(TtsError::VoiceFile("reference clip weight must be > 0".to_string()));
+        }
+        let heaviest = |a: &(usize, &str), b: &(usize, &str)| clips[a.0].weight.total_cmp(&clips[b.0].weight);
+        let (prompt_idx, prompt_text) = clips.iter().enumerate()
+            .filter_map(|(i, clip)| clip.prompt_text.as_deref().map(|text| (i, text)))
+            .max_by(heaviest)
+            .or_else(|| clips.iter().enumerate().map(|(i, _)| (i, "")).max_by(heaviest))
+            .unwrap();
+
+        let mut wav16ks = vec![];
+        let mut wav32ks = vec![];
+        let mut warnings = vec![];
+        for (i, clip) in clips.iter().enumerate() {
+            // 只有 prompt 那段要求 3~10 秒，只提供音色的段可以很短
+            let (wav16k, wav32k, clip_warnings) = decode_reference(engine, &clip.wav_path, i == prompt_idx)?;
+            wav16ks.push(wav16k);
+            wav32ks.push(wav32k);
+            warnings.extend(clip_warnings.into_iter().map(|w| format!("{}: {}", clip.wav_path, w)));
+        }
+        let mut voice = ReferenceVoice::from_pcm(engine, &wav16ks[prompt_idx], &wav32ks[prompt_idx], prompt_text)?;
+        voice.warnings = warnings;
+
+        let refers: Vec<(Vec<f32>, f32)> = wav32ks.iter().zip(clips)
+            .map(|(wav32k, clip)| (wav32k.iter().map(|&x| x as f32 / 32768.0).collect(), clip.weight))
+            .collect();
+        voice.wav32k_arr = Array1::from_vec(blend_refer(&refers)).insert_axis(Axis(0));
+        Ok(voice)
+    }
+
+    /// wav16k、wav32k: 已经重采样好的单声道 pcm16
+    ///
+    /// prompt_text 为空时用 engine.transcriber 识别，没有 transcriber 就只能按 ref_free 合成
+    pub fn from_pcm(engine: &TtsEngine, wav16k: &Vec<i16>, wav32k: &Vec<i16>, prompt_text: &str) -> Result<Self, TtsError> {
+        ReferenceVoice::from_pcm_with(engine, &engine.vq_model_latent, wav16k, wav32k, prompt_text)
+    }
+
+    fn from_pcm_with(engine: &TtsEngine, vq_model_latent: &Session, wav16k: &Vec<i16>, wav32k: &Vec<i16>, prompt_text: &str) -> Result<Self, TtsError> {
+        let prompt_text = match &engine.transcriber {
+            Some(transcriber) if prompt_text.trim().is_empty() => {
+                let text = transcriber.transcribe(wav16k)?;
+                info!("transcribed reference: {}", text);
+                text
+            }
+            _ => prompt_text.to_string(),
+        };
+        let prompt_text = prompt_text.as_str();
+        let wav16k: Vec<f32> = wav16k.iter().map(|&x| x as f32 / 32768.0).collect();
+        let wav32k: Vec<f32> = wav32k.iter().map(|&x| x as f32 / 32768.0).collect();
+
+        let zero_sampling_len = (engine.config.sampling_rate as f32 * 0.3) as usize;
+        let zero_wav: Array1<f32> = Array1::zeros((zero_sampling_len, ));
+
+        let wav16k_arr: Array1<f32> = Array1::from_vec(wav16k);
+        let wav32k_arr: Array1<f32> = Array1::from_vec(wav32k);
+
+        let wav16k_arr: Array2<f32> = ndarray::concatenate(Axis(0), &[wav16k_arr.view(), zero_wav.view()]).unwrap().insert_axis(Axis(0));
+        let wav32k_arr: Array2<f32> = wav32k_arr.insert_axis(Axis(0));
+
+        let (prompt_semantic, ssl_ms) = {
+            let _ssl = engine.limits.ssl.acquire();
+            let start_ssl = Instant::now();
+            let prompt_semantic = get_prompt_semantic(&engine.ssl_model, vq_model_latent, &wav16k_arr)?;
+            (prompt_semantic, elapsed_ms(start_ssl))
+        };
+        // 没有参考文字，也没识别出来，只能用 ref_free 合成
+        let (prompt_bert, prompt_phones) = if prompt_text.trim().is_empty() {
+            (Array2::zeros((BERT_DIM, 0)), vec![])
+        } else {
+            let (prompt_bert, prompt_phones, _) = engine.text_features(prompt_text)?;
+            (prompt_bert, prompt_phones)
+        };
+
+        Ok(ReferenceVoice { prompt_text: prompt_text.to_string(), prompt_semantic, wav32k_arr, prompt_phones, prompt_bert, ssl_ms, warnings: vec![] })
+    }
+
+    /// ref_free 或者没有参考文字时 prompt 是空的：t2s 只看目标文字，音色只来自 vq_model 的 refer
+    pub fn prompt(&self, ref_free: bool) -> Prompt<'_> {
+        if self.empty_prompt(ref_free) {
+            return Prompt {
+                semantic: Cow::Owned(Array2::zeros((1, 0))),
+                bert: Cow::Owned(Array2::zeros((self.prompt_bert.nrows(), 0))),
+                phones: Cow::Owned(vec![]),
+            };
+        }
+        Prompt { semantic: Cow::Borrowed(&self.prompt_semantic), bert: Cow::Borrowed(&self.prompt_bert), phones: Cow::Borrowed(&self.prompt_phones) }
+    }
+
+    pub fn empty_prompt(&self, ref_free: bool) -> bool {
+        ref_free || self.prompt_phones.is_empty()
+    }
+
+    /// 切分长度：参考文字的字数，没有参考文字时按 REF_FREE_CUT_LEN
+    pub fn cut_len(&self) -> usize {
+        match self.prompt_text.chars().count() {
+            0 => REF_FREE_CUT_LEN,
+            len => len,
+        }
+    }
+
+    /// 保存到文件，之后用 `load` 加载不需要原始音频和 ssl_model
+    pub fn save(&self, path: &str) -> Result<(), TtsError> {
+        let mut buf: Vec<u8> = vec![];
+
+        buf.extend_from_slice(VOICE_MAGIC);
+        buf.extend_from_slice(&VOICE_VERSION.to_le_bytes());
+
+        let prompt_text = self.prompt_text.as_bytes();
+        buf.extend_from_slice(&(prompt_text.len() as u64).to_le_bytes());
+        buf.extend_from_slice(prompt_text);
+
+        buf.extend_from_slice(&(self.prompt_phones.len() as u64).to_le_bytes());
+        for &p in &self.prompt_phones {
+            buf.extend_from_slice(&(p as u64).to_le_bytes());
+        }
+
+        write_shape(&mut buf, self.prompt_bert.shape());
+        for &v in self.prompt_bert.iter() {
+            buf.extend_from_slice(&v.to_le_bytes());
+        }
+        write_shape(&mut buf, self.prompt_semantic.shape());
+        for &v in self.prompt_semantic.iter() {
+            buf.extend_from_slice(&v.to_le_bytes());
+        }
+        write_shape(&mut buf, self.wav32k_arr.shape());
+        for &v in self.wav32k_arr.iter() {
+            buf.extend_from_slice(&v.to_le_bytes());
+        }
+
+        fs::write(path, &buf)?;
+        Ok(())
+    }
+
+    pub fn load(path: &str) -> Result<Self, TtsError> {
+        let buf = fs::read(path)?;
+
+        let mut r = ByteReader { buf: &buf, pos: 0 };
+        if r.take(8)? != VOICE_MAGIC {
+            return Err(TtsError::VoiceFile(format!("{}: bad magic", path)));
+        }
+        let version = u32::from_le_bytes(r.take(4)?.try_into().unwrap());
+        if version != VOICE_VERSION {
+            return Err(TtsError::VoiceFile(format!("{}: unsupported version {}", path, version)));
+        }
+
+        let text_len = r.read_u64()? as usize;
+        let prompt_text = String::from_utf8(r.take(text_len)?.to_vec()).map_err(|e| TtsError::VoiceFile(e.to_string()))?;
+
+        let phones_len = r.read_u64()? as usize;
+        let mut prompt_phones = vec![];
+        for _ in 0..phones_len {
+            prompt_phones.push(r.read_u64()? as usize);
+        }
+
+        let shape = r.read_shape()?;
+        let data = r.take_matrix(shape, 4)?.chunks(4).map(|b| f32::from_le_bytes(b.try_into().unwrap())).collect();
+        let prompt_bert = Array2::from_shape_vec(shape, data).map_err(|e| TtsError::VoiceFile(e.to_string()))?;
+
+        let shape = r.read_shape()?;
+        let data = r.take_matrix(shape, 8)?.chunks(8).map(|b| i64::from_le_bytes(b.try_into().unwrap())).collect();
+        let prompt_semantic = Array2::from_shape_vec(shape, data).map_err(|e| TtsError::VoiceFile(e.to_string()))?;
+
+        let shape = r.read_shape()?;
+        let data = r.take_matrix(shape, 4)?.chunks(4).map(|b| f32::from_le_bytes(b.try_into().unwrap())).collect();
+        let wav32k_arr = Array2::from_shape_vec(shape, data).map_err(|e| TtsError::VoiceFile(e.to_string()))?;
+
+        Ok(ReferenceVoice { prompt_text, prompt_semantic, wav32k_arr, prompt_phones, prompt_bert, ssl_ms: 0.0, warnings: vec![] })
+    }
+}
+
+// 解码成 16k、32k，按 engine.config.reference_check 检查、裁剪、高通；裁剪范围按 32k 算，两个采样率一起裁
+// prompt: 作为 t2s prompt 的段检查时长，其它段不限
+fn decode_reference(engine: &TtsEngine, wav_path: &str, prompt: bool) -> Result<(Vec<i16>, Vec<i16>, Vec<String>), TtsError> {
+    let wav16k: Vec<i16> = FfmpegUtils::decode_path_to_datas(wav_path, 16000)?;
+    let wav32k: Vec<i16> = FfmpegUtils::decode_path_to_datas(wav_path, SAMPLE_RATE)?;
+    let check = &engine.config.reference_check;
+    if !check.enabled {
+        return Ok((wav16k, wav32k, vec![]));
+    }
+    let report = if prompt { check.check(&wav32k, SAMPLE_RATE)? } else { check.check_timbre(&wav32k, SAMPLE_RATE)? };
+    for warning in &report.warnings {
+        warn!("reference {}: {}", wav_path, warning);
+    }
+    Ok((check.apply(&wav16k, 16000, &report), check.apply(&wav32k, SAMPLE_RATE, &report), report.warnings))
+}
+
+/// 多段 refer 拼接：总时长不变，每段占的时长和权重成正比，不够的循环补齐，多的截掉
+fn blend_refer(refers: &[(Vec<f32>, f32)]) -> Vec<f32> {
+    let total: usize = refers.iter().map(|(wav, _)| wav.len()).sum();
+    let weight_sum: f32 = refers.iter().filter(|(wav, _)| !wav.is_empty()).map(|(_, weight)| weight).sum();
+    let mut out = Vec::with_capacity(total);
+    for (wav, weight) in refers.iter().filter(|(wav, _)| !wav.is_empty()) {
+        let len = (total as f64 * (*weight / weight_sum) as f64).round() as usize;
+        out.extend(wav.iter().cycle().take(len));
+    }
+    out
+}
+
+fn write_shape(buf: &mut Vec<u8>, shape: &[usize]) {
+    buf.extend_from_slice(&(shape[0] as u64).to_le_bytes());
+    buf.extend_from_slice(&(shape[1] as u64).to_le_bytes());
+}
+
+struct ByteReader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> ByteReader<'a> {
+    // 长度都是从文件里读的，溢出当作文件损坏
+    fn take(&mut self, n: usize) -> Result<&'a [u8], TtsError> {
+        let end = self.pos.checked_add(n).filter(|&end| end <= self.buf.len())
+            .ok_or_else(|| TtsError::VoiceFile("truncated".to_string()))?;
+        let v = &self.buf[self.pos..end];
+        self.pos = end;
+        Ok(v)
+    }
+
+    /// shape 的矩阵，每个元素 elem_size 字节
+    fn take_matrix(&mut self, shape: (usize, usize), elem_size: usize) -> Result<&'a [u8], TtsError> {
+        let n = shape.0.checked_mul(shape.1).and_then(|n| n.checked_mul(elem_size))
+            .ok_or_else(|| TtsError::VoiceFile(format!("bad shape {:?}", shape)))?;
+        self.take(n)
+    }
+
+    fn read_u64(&mut self) -> Result<u64, TtsError> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn read_shape(&mut self) -> Result<(usize, usize), TtsError> {
+        let rows = self.read_u64()? as usize;
+        let cols = self.read_u64()? as usize;
+        Ok((rows, cols))
+    }
+}
+
+
+#[test]
+fn test_save_load() {
+    let voice = ReferenceVoice {
+        prompt_text: "我注意到了".to_string(),
+        prompt_semantic: Array2::from_shape_vec((1, 3), vec![1, 2, 1023]).unwrap(),
+        wav32k_arr: Array2::from_shape_vec((1, 4), vec![0.0, 0.5, -0.5, 1.0]).unwrap(),
+        prompt_phones: vec![3, 4, 5],
+        prompt_bert: Array2::ones((1024, 3)),
+        ssl_ms: 0.0,
+        warnings: vec![],
+    };
+    let path = std::env::temp_dir().join("test_reference_voice.bin");
+    let path = path.to_str().unwrap();
+    voice.save(path).unwrap();
+
+    let voice2 = ReferenceVoice::load(path).unwrap();
+    assert_eq!(voice.prompt_text, voice2.prompt_text);
+    assert_eq!(voice.prompt_semantic, voice2.prompt_semantic);
+    assert_eq!(voice.wav32k_arr, voice2.wav32k_arr);
+    assert_eq!(voice.prompt_phones, voice2.prompt_phones);
+    assert_eq!(voice.prompt_bert, voice2.prompt_bert);
+
+    let mut r = ByteReader { buf: &[0; 16], pos: 8 };
+    assert!(r.take(usize::MAX).is_err());
+    assert!(r.take_matrix((usize::MAX, 2), 4).is_err());
+}
+
+#[test]
+fn test_blend_refer() {
+    let blended = blend_refer(&[(vec![1.0; 100], 3.0), (vec![2.0; 300], 1.0)]);
+    assert_eq!(blended.len(), 400);
+    assert_eq!(blended.iter().filter(|&&x| x == 1.0).count(), 300);
+    assert_eq!(blended.iter().filter(|&&x| x == 2.0).count(), 100);
+
+    let single = vec![0.1, 0.2, 0.3];
+    assert_eq!(blend_refer(&[(single.clone(), 0.5)]), single);
+}
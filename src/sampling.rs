@@ -0,0 +1,158 @@
+use ort::{Session, TensorElementType, ValueType};
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+
+/// semantic token 的结束符
+pub const EOS_TOKEN: i64 = 1024;
+
+/// 生成长度上限：t2s 最多跑这么多步
+pub const MAX_SEMANTIC_TOKENS: usize = 1500;
+
+/// 复读检测：末尾同一个片段（长度 1..=max_period）连续出现 min_repeats 次以上，
+/// 并且覆盖了 min_span 个 token，就提前结束
+#[derive(Debug, Clone)]
+pub struct EarlyStop {
+    pub max_period: usize,
+    pub min_repeats: usize,
+    pub min_span: usize,
+}
+
+impl Default for EarlyStop {
+    fn default() -> Self {
+        // 50 个 token = 2 秒
+        EarlyStop { max_period: 8, min_repeats: 3, min_span: 50 }
+    }
+}
+
+impl EarlyStop {
+    /// 返回复读开始前应该保留的 token 数（只留一遍片段），None: 没有复读
+    pub fn check(&self, tokens: &[i64]) -> Option<usize> {
+        for period in 1..=self.max_period {
+            if tokens.len() < period * 2 {
+                break;
+            }
+            let pattern = &tokens[tokens.len() - period..];
+            let mut repeats = 1;
+            while tokens.len() >= period * (repeats + 1) {
+                let start = tokens.len() - period * (repeats + 1);
+                if &tokens[start..start + period] != pattern {
+                    break;
+                }
+                repeats += 1;
+            }
+            if repeats >= self.min_repeats && period * repeats >= self.min_span {
+                return Some(tokens.len() - period * (repeats - 1));
+            }
+        }
+        None
+    }
+}
+
+/// 导出的 stage decoder 有 float 的 logits 输出时，在 Rust 这边采样
+pub fn exposes_logits(session: &Session) -> bool {
+    session.outputs.iter().any(|o| {
+        o.name == "logits" && matches!(o.output_type, ValueType::Tensor { ty: TensorElementType::Float32, .. })
+    })
+}
+
+/// top_k / top_p / temperature / 重复惩罚 采样，seed 相同结果就相同
+pub struct Sampler {
+    rng: ChaCha8Rng,
+    seed: u64,
+    top_k: usize,
+    top_p: f32,
+    temperature: f32,
+    repetition_penalty: f32,
+}
+
+impl Sampler {
+    /// seed 为 None 时随机取一个，用 seed() 拿到
+    pub fn init(top_k: i64, top_p: f32, temperature: f32, repetition_penalty: f32, seed: Option<u64>) -> Self {
+        let seed = seed.unwrap_or_else(rand::random);
+        let rng = ChaCha8Rng::seed_from_u64(seed);
+        Sampler { rng, seed, top_k: top_k.max(0) as usize, top_p, temperature, repetition_penalty }
+    }
+
+    /// 实际用的种子
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// logits: 一步的 [vocab]，history: 已经生成的 token
+    pub fn sample(&mut self, logits: &[f32], history: &[i64]) -> i64 {
+        let mut scores = logits.to_vec();
+
+        if self.repetition_penalty != 1.0 {
+            let mut seen = vec![false; scores.len()];
+            for &t in history {
+                if t >= 0 && (t as usize) < scores.len() && !seen[t as usize] {
+                    seen[t as usize] = true;
+                    let s = &mut scores[t as usize];
+                    *s = if *s < 0.0 { *s * self.repetition_penalty } else { *s / self.repetition_penalty };
+                }
+            }
+        }
+
+        let temperature = self.temperature.max(1e-5);
+        let mut candidates: Vec<(usize, f32)> = scores.iter().map(|&s| s / temperature).enumerate().collect();
+        candidates.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal));
+        if self.top_k > 0 && self.top_k < candidates.len() {
+            candidates.truncate(self.top_k);
+        }
+
+        // softmax
+        let max = candidates.first().map(|c| c.1).unwrap_or(0.0);
+        let mut total = 0.0;
+        for c in candidates.iter_mut() {
+            c.1 = (c.1 - max).exp();
+            total += c.1;
+        }
+        for c in candidates.iter_mut() {
+            c.1 /= total;
+        }
+
+        // top_p: 保留累计概率达到 top_p 的最少的 token
+        if self.top_p < 1.0 {
+            let mut cum = 0.0;
+            let mut keep = candidates.len();
+            for (i, c) in candidates.iter().enumerate() {
+                cum += c.1;
+                if cum >= self.top_p {
+                    keep = i + 1;
+                    break;
+                }
+            }
+            candidates.truncate(keep);
+        }
+
+        let total: f32 = candidates.iter().map(|c| c.1).sum();
+        let mut r = self.rng.gen::<f32>() * total;
+        for c in &candidates {
+            if r < c.1 {
+                return c.0 as i64;
+            }
+            r -= c.1;
+        }
+        candidates.last().map(|c| c.0 as i64).unwrap_or(EOS_TOKEN)
+    }
+}
+
+#[test]
+fn test_sampler() {
+    let logits: Vec<f32> = (0..32).map(|i| (i as f32 * 0.37).sin()).collect();
+    let history = vec![3, 5, 7];
+
+    let mut a = Sampler::init(10, 0.9, 0.8, 1.3, Some(42));
+    let mut b = Sampler::init(10, 0.9, 0.8, 1.3, Some(42));
+    let ta: Vec<i64> = (0..20).map(|_| a.sample(&logits, &history)).collect();
+    let tb: Vec<i64> = (0..20).map(|_| b.sample(&logits, &history)).collect();
+    assert_eq!(ta, tb);
+
+    // top_k = 1 就是 argmax
+    let mut greedy = Sampler::init(1, 1.0, 1.0, 1.0, None);
+    assert_eq!(greedy.sample(&[0.1, 2.0, 0.5, -1.0], &[]), 1);
+
+    let early_stop = EarlyStop { max_period: 4, min_repeats: 3, min_span: 6 };
+    assert_eq!(early_stop.check(&[9, 1, 2, 1, 2, 1, 2]), Some(3));
+    assert_eq!(early_stop.check(&[1, 2, 3, 4, 5, 6]), None);
+}
@@ -0,0 +1,450 @@
+use std::collections::HashMap;
+use log::warn;
+use crate::error::TtsError;
+use crate::pause::{cut_texts_with_boundaries, merge_boundary, parse_duration, trailing_boundary, Boundary};
+use crate::text_utils::{CHINESE_LANG, ENGLISH_LANG, JAPANESE_LANG, TextUtils};
+use crate::tts_engine::SynthesisParams;
+
+/// 支持的子集：speak、p、s、break、prosody、say-as、phoneme、lang，其它标签只保留文字
+pub fn is_ssml(text: &str) -> bool {
+    text.trim_start().starts_with("<speak")
+}
+
+/// <prosody> 叠加到 SynthesisParams 上的部分，嵌套时 rate 相乘，pitch、volume 相加
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Prosody {
+    pub rate: f32,
+    /// 半音
+    pub pitch: f32,
+    /// dB
+    pub volume: f32,
+}
+
+impl Default for Prosody {
+    fn default() -> Self {
+        Prosody { rate: 1.0, pitch: 0.0, volume: 0.0 }
+    }
+}
+
+impl Prosody {
+    pub fn apply(&self, params: &SynthesisParams) -> SynthesisParams {
+        SynthesisParams {
+            speed: params.speed * self.rate,
+            pitch_semitones: params.pitch_semitones + self.pitch,
+            volume_db: params.volume_db + self.volume,
+            ..params.clone()
+        }
+    }
+
+    fn nested(&self, attrs: &HashMap<String, String>) -> Self {
+        let mut prosody = *self;
+        if let Some(rate) = attrs.get("rate").and_then(|v| parse_rate(v)) {
+            prosody.rate *= rate;
+        }
+        if let Some(pitch) = attrs.get("pitch").and_then(|v| parse_pitch(v)) {
+            prosody.pitch += pitch;
+        }
+        if let Some(volume) = attrs.get("volume").and_then(|v| parse_volume(v)) {
+            prosody.volume += volume;
+        }
+        prosody
+    }
+}
+
+/// 一段里的一部分文字
+#[derive(Debug, Clone, PartialEq)]
+pub enum SsmlSpan {
+    /// 自动分语言
+    Text(String),
+    /// <lang xml:lang>：不做语言检测
+    Lang { text: String, lang: String },
+    /// <phoneme>：音素已经转成 SYMBOLS 的 id
+    Phonemes { text: String, lang: String, phones: Vec<usize>, word2ph: Vec<usize> },
+}
+
+/// 合成时的一段：对应纯文本的 cut_texts 切出来的一段
+#[derive(Debug, Clone)]
+pub struct SsmlSegment {
+    pub spans: Vec<SsmlSpan>,
+    pub prosody: Prosody,
+    /// 结尾的停顿
+    pub boundary: Boundary,
+}
+
+impl SsmlSegment {
+    /// 日志用
+    pub fn text(&self) -> String {
+        self.spans.iter().map(|span| match span {
+            SsmlSpan::Text(text) | SsmlSpan::Lang { text, .. } | SsmlSpan::Phonemes { text, .. } => text.as_str(),
+        }).collect()
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Node {
+    Open(String, HashMap<String, String>),
+    Close(String),
+    Text(String),
+}
+
+fn ssml_error(message: impl Into<String>) -> TtsError {
+    TtsError::TextFrontend(format!("invalid ssml: {}", message.into()))
+}
+
+fn unescape(text: &str) -> String {
+    text.replace("&lt;", "<").replace("&gt;", ">").replace("&quot;", "\"").replace("&apos;", "'").replace("&amp;", "&")
+}
+
+/// 文字里的私用区字符去掉：SsmlBuilder 用它们做占位符
+fn text_node(raw: &str) -> Node {
+    Node::Text(unescape(raw).chars().filter(|&c| !is_placeholder(c)).collect())
+}
+
+/// 拆成标签和文字，自闭合标签拆成 Open + Close；跳过 <?xml?> 和注释
+fn tokenize(ssml: &str) -> Result<Vec<Node>, TtsError> {
+    let mut nodes = vec![];
+    let mut rest = ssml;
+    while !rest.is_empty() {
+        let start = match rest.find('<') {
+            Some(start) => start,
+            None => {
+                nodes.push(text_node(rest));
+                break;
+            }
+        };
+        if start > 0 {
+            nodes.push(text_node(&rest[..start]));
+        }
+        rest = &rest[start..];
+        if rest.starts_with("<!--") {
+            let end = rest.find("-->").ok_or_else(|| ssml_error("unclosed comment"))?;
+            rest = &rest[end + 3..];
+            continue;
+        }
+        let end = rest.find('>').ok_or_else(|| ssml_error("unclosed tag"))?;
+        let tag = &rest[1..end];
+        rest = &rest[end + 1..];
+        if tag.starts_with('?') || tag.starts_with('!') {
+            continue;
+        }
+        if let Some(name) = tag.strip_prefix('/') {
+            nodes.push(Node::Close(name.trim().to_string()));
+            continue;
+        }
+        let self_closing = tag.ends_with('/');
+        let tag = tag.trim_end_matches('/').trim();
+        let (name, attrs) = tag.split_once(char::is_whitespace).unwrap_or((tag, ""));
+        nodes.push(Node::Open(name.to_string(), parse_attrs(attrs)?));
+        if self_closing {
+            nodes.push(Node::Close(name.to_string()));
+        }
+    }
+    Ok(nodes)
+}
+
+/// a="1" b='2'
+fn parse_attrs(attrs: &str) -> Result<HashMap<String, String>, TtsError> {
+    let mut map = HashMap::new();
+    let mut rest = attrs.trim();
+    while !rest.is_empty() {
+        let eq = rest.find('=').ok_or_else(|| ssml_error(format!("bad attribute {}", rest)))?;
+        let name = rest[..eq].trim().to_string();
+        let value = rest[eq + 1..].trim_start();
+        let quote = value.chars().next().filter(|&c| c == '"' || c == '\'')
+            .ok_or_else(|| ssml_error(format!("attribute {} is not quoted", name)))?;
+        let end = value[1..].find(quote).ok_or_else(|| ssml_error(format!("attribute {} is not closed", name)))?;
+        map.insert(name, unescape(&value[1..end + 1]));
+        rest = value[end + 2..].trim_start();
+    }
+    Ok(map)
+}
+
+/// "120%" / "+20%" / "1.2" / "fast" -> 倍数
+fn parse_rate(value: &str) -> Option<f32> {
+    let value = value.trim();
+    let rate = match value {
+        "x-slow" => 0.5,
+        "slow" => 0.75,
+        "medium" | "default" => 1.0,
+        "fast" => 1.25,
+        "x-fast" => 1.5,
+        _ => match value.strip_suffix('%') {
+            Some(percent) if percent.starts_with('+') || percent.starts_with('-') => 1.0 + percent.parse::<f32>().ok()? / 100.0,
+            Some(percent) => percent.parse::<f32>().ok()? / 100.0,
+            None => value.parse::<f32>().ok()?,
+        },
+    };
+    Some(rate).filter(|&rate| rate > 0.0)
+}
+
+/// "+2st" / "-10%" / "high" -> 半音
+fn parse_pitch(value: &str) -> Option<f32> {
+    let value = value.trim();
+    match value {
+        "x-low" => Some(-6.0),
+        "low" => Some(-3.0),
+        "medium" | "default" => Some(0.0),
+        "high" => Some(3.0),
+        "x-high" => Some(6.0),
+        _ => match (value.strip_suffix("st"), value.strip_suffix('%')) {
+            (Some(st), _) => st.parse::<f32>().ok(),
+            (_, Some(percent)) => {
+                let ratio = 1.0 + percent.parse::<f32>().ok()? / 100.0;
+                Some(12.0 * ratio.max(0.01).log2())
+            }
+            _ => None,
+        },
+    }
+}
+
+/// "+6dB" / "loud" -> dB
+fn parse_volume(value: &str) -> Option<f32> {
+    let value = value.trim();
+    match value {
+        "silent" => Some(-96.0),
+        "x-soft" => Some(-12.0),
+        "soft" => Some(-6.0),
+        "medium" | "default" => Some(0.0),
+        "loud" => Some(6.0),
+        "x-loud" => Some(12.0),
+        _ => value.strip_suffix("dB").and_then(|db| db.parse::<f32>().ok()),
+    }
+}
+
+/// <break>：time 优先，没有按 strength，都没有按句号
+fn break_boundary(attrs: &HashMap<String, String>) -> Boundary {
+    if let Some(seconds) = attrs.get("time").and_then(|time| parse_duration(time)) {
+        return Boundary::Markup(seconds);
+    }
+    match attrs.get("strength").map(|s| s.as_str()) {
+        Some("none") => Boundary::Markup(0.0),
+        Some("x-weak") | Some("weak") => Boundary::Comma,
+        Some("strong") | Some("x-strong") => Boundary::Paragraph,
+        _ => Boundary::Sentence,
+    }
+}
+
+/// zh-CN / en-US -> 前端的语言名
+fn lang_name(xml_lang: &str) -> Result<&'static str, TtsError> {
+    match xml_lang.split(|c| c == '-' || c == '_').next().unwrap_or("").to_lowercase().as_str() {
+        "zh" | "cmn" => Ok(CHINESE_LANG),
+        "en" => Ok(ENGLISH_LANG),
+        "ja" | "jp" => Ok(JAPANESE_LANG),
+        _ => Err(ssml_error(format!("unsupported xml:lang {}", xml_lang))),
+    }
+}
+
+// 私用区字符占位：<lang>、<phoneme> 在切分时不能被拆开
+const PLACEHOLDER_START: u32 = 0xE000;
+const PLACEHOLDER_END: u32 = 0xF8FF;
+
+pub(crate) fn is_placeholder(c: char) -> bool {
+    (PLACEHOLDER_START..=PLACEHOLDER_END).contains(&(c as u32))
+}
+
+/// 同一个 prosody 下、两个停顿之间的文字，切分后变成若干段
+struct SsmlBuilder<'a> {
+    text_util: &'a TextUtils,
+    max_num: usize,
+    segments: Vec<SsmlSegment>,
+    text: String,
+    atoms: Vec<SsmlSpan>,
+    prosody: Prosody,
+}
+
+impl<'a> SsmlBuilder<'a> {
+    fn push_text(&mut self, text: &str, lang: Option<&str>) {
+        match lang {
+            Some(lang) => self.push_atom(SsmlSpan::Lang { text: text.to_string(), lang: lang.to_string() }),
+            None => self.text.push_str(text),
+        }
+    }
+
+    fn push_atom(&mut self, atom: SsmlSpan) {
+        // 用完了就先切一块
+        if self.atoms.len() as u32 > PLACEHOLDER_END - PLACEHOLDER_START {
+            self.flush(None);
+        }
+        self.text.push(char::from_u32(PLACEHOLDER_START + self.atoms.len() as u32).unwrap());
+        self.atoms.push(atom);
+    }
+
+    /// 当前块切分成段，end: 块结尾的停顿，None: 按结尾的标点
+    fn flush(&mut self, end: Option<Boundary>) {
+        let text = std::mem::take(&mut self.text);
+        let atoms = std::mem::take(&mut self.atoms);
+        let mut cut = cut_texts_with_boundaries(&self.text_util.lang_seg, &text, self.max_num);
+        if let Some(last) = cut.last_mut() {
+            last.1 = end.unwrap_or_else(|| trailing_boundary(&text));
+        } else if let (Some(end), Some(last)) = (end, self.segments.last_mut()) {
+            // 空块的停顿并到上一段
+            last.boundary = merge_boundary(last.boundary, end);
+        }
+        for (segment, boundary) in cut {
+            let mut spans = vec![];
+            let mut plain = String::new();
+            for c in segment.chars() {
+                if is_placeholder(c) {
+                    if !plain.trim().is_empty() {
+                        spans.push(SsmlSpan::Text(std::mem::take(&mut plain)));
+                    }
+                    match atoms.get((c as u32 - PLACEHOLDER_START) as usize) {
+                        Some(atom) => spans.push(atom.clone()),
+                        None => warn!("unknown ssml placeholder {:?}", c),
+                    }
+                } else {
+                    plain.push(c);
+                }
+            }
+            if !plain.trim().is_empty() {
+                spans.push(SsmlSpan::Text(plain));
+            }
+            self.segments.push(SsmlSegment { spans, prosody: self.prosody, boundary });
+        }
+    }
+
+    /// <say-as>：中文用 zh_normalization 的转换，英文、日文按字符读
+    fn say_as(&self, interpret_as: &str, text: &str, lang: Option<&str>) -> String {
+        let normalizer = &self.text_util.lang_chinese.text_normalizer;
+        let num_util = &normalizer.chronology.num_util;
+        let spell = |text: &str| text.chars().filter(|c| !c.is_whitespace()).map(|c| c.to_string()).collect::<Vec<String>>().join(" ");
+        // 日文的数字由日文前端读
+        let english = lang == Some(ENGLISH_LANG) || lang == Some(JAPANESE_LANG);
+        match interpret_as {
+            "digits" | "number_digits" if english => spell(text),
+            "digits" | "number_digits" => num_util.verbalize_digit(text.to_string(), false),
+            "telephone" if english => spell(text),
+            "telephone" => normalizer.phonecode.phone2str(text.trim().to_string(), text.trim_start().starts_with('+')),
+            "date" if english => text.to_string(),
+            "date" => normalizer.chronology.replace_date(normalizer.chronology.replace_date2(text.to_string())),
+            "characters" | "spell-out" if english => spell(text),
+            "characters" | "spell-out" => num_util.verbalize_digit(spell(text), false),
+            _ => {
+                warn!("say-as interpret-as={} not supported, read as text", interpret_as);
+                text.to_string()
+            }
+        }
+    }
+
+    /// <phoneme alphabet="pinyin|arpabet" ph>
+    fn phoneme(&mut self, alphabet: &str, ph: &str, text: &str) -> Result<(), TtsError> {
+        let atom = match alphabet {
+            "pinyin" => {
+                let (phones, word2ph) = self.text_util.pinyin_to_phones(ph)?;
+                let chars = text.chars().filter(|c| !c.is_whitespace()).count();
+                // bert 按字对齐
+                if chars != word2ph.len() {
+                    return Err(ssml_error(format!("phoneme {:?} has {} syllables but text {:?} has {} characters", ph, word2ph.len(), text, chars)));
+                }
+                SsmlSpan::Phonemes { text: text.to_string(), lang: CHINESE_LANG.to_string(), phones, word2ph }
+            }
+            "arpabet" | "x-arpabet" => {
+                let phones = self.text_util.arpabet_to_phones(ph)?;
+                SsmlSpan::Phonemes { text: text.to_string(), lang: ENGLISH_LANG.to_string(), phones, word2ph: vec![] }
+            }
+            _ => return Err(ssml_error(format!("unsupported phoneme alphabet {}", alphabet))),
+        };
+        self.push_atom(atom);
+        Ok(())
+    }
+}
+
+/// 元素栈上的一层
+struct Frame {
+    name: String,
+    attrs: HashMap<String, String>,
+    prosody: Prosody,
+    lang: Option<&'static str>,
+    // say-as、phoneme 里的文字攒到结束标签再处理
+    inner: String,
+}
+
+/// SSML -> 切分好的段，max_num: 和纯文本一样按参考文字长度切分
+pub fn parse_ssml(text_util: &TextUtils, ssml: &str, max_num: usize) -> Result<Vec<SsmlSegment>, TtsError> {
+    let mut builder = SsmlBuilder { text_util, max_num, segments: vec![], text: String::new(), atoms: vec![], prosody: Prosody::default() };
+    let mut stack: Vec<Frame> = vec![];
+
+    for node in tokenize(ssml)? {
+        match node {
+            Node::Text(text) => {
+                // xml 里的换行、缩进只是格式
+                let text = text.split_whitespace().collect::<Vec<&str>>().join(" ");
+                match stack.last_mut() {
+                    Some(frame) if frame.name == "say-as" || frame.name == "phoneme" => frame.inner.push_str(&text),
+                    frame => {
+                        let lang = frame.and_then(|frame| frame.lang);
+                        builder.push_text(&text, lang);
+                    }
+                }
+            }
+            Node::Open(name, attrs) => {
+                let parent_prosody = stack.last().map(|frame| frame.prosody).unwrap_or_default();
+                let mut lang = stack.last().and_then(|frame| frame.lang);
+                let prosody = if name == "prosody" { parent_prosody.nested(&attrs) } else { parent_prosody };
+                if name == "lang" || (attrs.contains_key("xml:lang") && name != "speak") {
+                    let xml_lang = attrs.get("xml:lang").ok_or_else(|| ssml_error("lang without xml:lang"))?;
+                    lang = Some(lang_name(xml_lang)?);
+                }
+                if prosody != builder.prosody {
+                    builder.flush(None);
+                    builder.prosody = prosody;
+                }
+                stack.push(Frame { name, attrs, prosody, lang, inner: String::new() });
+            }
+            Node::Close(name) => {
+                let frame = stack.pop().filter(|frame| frame.name == name)
+                    .ok_or_else(|| ssml_error(format!("unexpected </{}>", name)))?;
+                match frame.name.as_str() {
+                    "break" => builder.flush(Some(break_boundary(&frame.attrs))),
+                    "p" => builder.flush(Some(Boundary::Paragraph)),
+                    "s" => builder.flush(Some(Boundary::Sentence)),
+                    "say-as" => {
+                        let interpret_as = frame.attrs.get("interpret-as").map(|s| s.as_str()).unwrap_or("");
+                        let text = builder.say_as(interpret_as, &frame.inner, frame.lang);
+                        builder.push_text(&text, frame.lang);
+                    }
+                    "phoneme" => {
+                        let alphabet = frame.attrs.get("alphabet").map(|s| s.as_str()).unwrap_or("pinyin");
+                        let ph = frame.attrs.get("ph").ok_or_else(|| ssml_error("phoneme without ph"))?;
+                        builder.phoneme(alphabet, ph, &frame.inner)?;
+                    }
+                    _ => {}
+                }
+                let prosody = stack.last().map(|frame| frame.prosody).unwrap_or_default();
+                if prosody != builder.prosody {
+                    builder.flush(None);
+                    builder.prosody = prosody;
+                }
+            }
+        }
+    }
+    if let Some(frame) = stack.last() {
+        return Err(ssml_error(format!("<{}> is not closed", frame.name)));
+    }
+    builder.flush(None);
+    Ok(builder.segments)
+}
+
+#[test]
+fn test_ssml_tokenize() {
+    let nodes = tokenize(r#"<?xml version="1.0"?><speak>a &amp; b<break time='500ms'/><!-- x --><prosody rate="fast">c</prosody></speak>"#).unwrap();
+    let attrs = |pairs: &[(&str, &str)]| pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect::<HashMap<String, String>>();
+    assert_eq!(nodes, vec![
+        Node::Open("speak".to_string(), attrs(&[])),
+        Node::Text("a & b".to_string()),
+        Node::Open("break".to_string(), attrs(&[("time", "500ms")])),
+        Node::Close("break".to_string()),
+        Node::Open("prosody".to_string(), attrs(&[("rate", "fast")])),
+        Node::Text("c".to_string()),
+        Node::Close("prosody".to_string()),
+        Node::Close("speak".to_string()),
+    ]);
+    assert_eq!(tokenize("a\u{e005}b").unwrap(), vec![Node::Text("ab".to_string())]);
+
+    assert_eq!(parse_rate("120%"), Some(1.2));
+    assert_eq!(parse_rate("-50%"), Some(0.5));
+    assert_eq!(parse_pitch("+2st"), Some(2.0));
+    assert_eq!(parse_volume("-6dB"), Some(-6.0));
+    assert_eq!(break_boundary(&attrs(&[("time", "1s")])), Boundary::Markup(1.0));
+    assert_eq!(break_boundary(&attrs(&[("strength", "strong")])), Boundary::Paragraph);
+}
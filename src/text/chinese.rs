@@ -1,4 +1,3 @@
-use std::cmp::min;
 use std::collections::HashMap;
 use std::fs;
 use std::iter::zip;
@@ -8,13 +7,14 @@ use jieba_rs::{Jieba, Tag};
 use fancy_regex::{Captures, Regex};
 use log::info;
 use pinyin::ToPinyin;
-use crate::tts_sovits::text::lazy_pinyin;
-use crate::tts_sovits::text::lazy_pinyin::lazy_pinyin::LazyPinyin;
-use crate::tts_sovits::text::lazy_pinyin::style::Style;
-use crate::tts_sovits::text::tone_sandhi::ToneSandhi;
+use crate::error::TtsError;
+use crate::text::lazy_pinyin;
+use crate::text::lazy_pinyin::lazy_pinyin::LazyPinyin;
+use crate::text::lazy_pinyin::style::Style;
+use crate::text::tone_sandhi::ToneSandhi;
 
-use crate::tts_sovits::text::zh_normalization::opencpop_strict::OPENCPOP_STRICT;
-use crate::tts_sovits::text::zh_normalization::text_normalization::TextNormalizer;
+use crate::text::zh_normalization::opencpop_strict::OPENCPOP_STRICT;
+use crate::text::zh_normalization::text_normalization::TextNormalizer;
 
 pub struct Chinese {
     pub rep_map: HashMap<String, String>,
@@ -42,9 +42,9 @@ impl Chinese {
     pub fn init(rep_map_json_path: &str,
                 phrases_dict_path: &str,
                 pinyin_dict_path: &str,
-    ) -> Self {
-        let file = fs::File::open(rep_map_json_path).unwrap();
-        let mut rep_map: HashMap<String, String> = serde_json::from_reader(&file).unwrap();
+    ) -> Result<Self, TtsError> {
+        let file = fs::File::open(rep_map_json_path).map_err(|e| TtsError::dictionary(rep_map_json_path, e))?;
+        let mut rep_map: HashMap<String, String> = serde_json::from_reader(&file).map_err(|e| TtsError::dictionary(rep_map_json_path, e))?;
 
 
         let _OPENCPOP_STRICT = OPENCPOP_STRICT.map(|x| (x.0.to_string(), x.1.to_string()));
@@ -80,7 +80,7 @@ impl Chinese {
         }
 
         let ps = ps.join("|");
-        let pattern = Regex::new(&ps).unwrap();
+        let pattern = Regex::new(&ps).map_err(|e| TtsError::dictionary(rep_map_json_path, e))?;
 
         // 中文和符号
         let punctuation = ["!".to_string(), "?".to_string(), "…".to_string(), ",".to_string(), ".".to_string(), "-".to_string()];
@@ -101,9 +101,9 @@ impl Chinese {
         let lazy_pinyin = LazyPinyin::init(
             phrases_dict_path,
             pinyin_dict_path,
-        ).unwrap();
+        )?;
 
-        Chinese {
+        Ok(Chinese {
             rep_map,
             pinyin_to_symbol_map,
             v_rep_map,
@@ -118,7 +118,7 @@ impl Chinese {
             jieba_util,
             tone_modifier,
             lazy_pinyin,
-        }
+        })
     }
 
     /// 符号统一替换为英文输入下的符号
@@ -317,7 +317,7 @@ fn chinese_test0() {
         "/Users/jxinfa/RustroverProjects/rs_tokenizer/data/rep_map.json",
         "/Users/jxinfa/RustroverProjects/rs_lazy_pinyin/datas/PHRASES_DICT.json",
         "/Users/jxinfa/RustroverProjects/rs_lazy_pinyin/datas/PINYIN_DICT.json",
-    );
+    ).unwrap();
     // let text = r"我的手机号是".to_string();
     //
     // println!("text:{:?}",text);
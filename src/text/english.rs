@@ -10,7 +10,8 @@ use log::info;
 use regex::{Regex, Captures};
 use fancy_regex::Regex as Regex2;
 use substring::Substring;
-use crate::tts_sovits::text::symbols::{ARPA, SYMBOLS};
+use crate::error::TtsError;
+use crate::text::symbols::{ARPA, SYMBOLS};
 
 pub struct English {
     pub eng_dict: HashMap<String, Vec<Vec<String>>>,
@@ -65,21 +66,21 @@ pub fn split_with_delimiter(re: &Regex, input: &str) -> Vec<String> {
 
 
 impl English {
-    pub fn init(eng_dict_json_path: &str, ph_model_path: &str) -> Result<Self, String> {
+    pub fn init(eng_dict_json_path: &str, ph_model_path: &str) -> Result<Self, TtsError> {
         let file_op = fs::File::open(eng_dict_json_path);
         if file_op.is_err() {
-            return Err(file_op.err().unwrap().to_string());
+            return Err(TtsError::dictionary(eng_dict_json_path, file_op.err().unwrap()));
         }
         let file = file_op.unwrap();
         let eng_dict_op = serde_json::from_reader(&file);
         if eng_dict_op.is_err() {
-            return Err(eng_dict_op.err().unwrap().to_string());
+            return Err(TtsError::dictionary(eng_dict_json_path, eng_dict_op.err().unwrap()));
         }
         let eng_dict: HashMap<String, Vec<Vec<String>>> = eng_dict_op.unwrap();
 
         let pho_model_op = Model::load_from_npz_file(Path::new(ph_model_path));
         if pho_model_op.is_err() {
-            return Err(pho_model_op.err().unwrap().to_string());
+            return Err(TtsError::dictionary(ph_model_path, pho_model_op.err().unwrap()));
         }
 
         let pho_model = pho_model_op.unwrap();
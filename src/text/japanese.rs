@@ -0,0 +1,345 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::Path;
+use regex::{Captures, Regex};
+use log::warn;
+use crate::error::TtsError;
+
+const VOWELS: [&str; 5] = ["a", "i", "u", "e", "o"];
+
+// 五十音的行：辅音 + あいうえお 段，不规则的在 EXCEPTIONS 里覆盖
+const KANA_ROWS: [(&str, &str); 14] = [
+    ("", "あいうえお"),
+    ("", "ぁぃぅぇぉ"),
+    ("k", "かきくけこ"),
+    ("g", "がぎぐげご"),
+    ("s", "さしすせそ"),
+    ("z", "ざじずぜぞ"),
+    ("t", "たちつてと"),
+    ("d", "だぢづでど"),
+    ("n", "なにぬねの"),
+    ("h", "はひふへほ"),
+    ("b", "ばびぶべぼ"),
+    ("p", "ぱぴぷぺぽ"),
+    ("m", "まみむめも"),
+    ("r", "らりるれろ"),
+];
+
+const EXCEPTIONS: [(&str, &str); 16] = [
+    ("し", "sh i"),
+    ("じ", "j i"),
+    ("ち", "ch i"),
+    ("ぢ", "j i"),
+    ("つ", "ts u"),
+    ("づ", "z u"),
+    ("ふ", "f u"),
+    ("ゔ", "v u"),
+    ("や", "y a"),
+    ("ゆ", "y u"),
+    ("よ", "y o"),
+    ("わ", "w a"),
+    ("ゎ", "w a"),
+    ("を", "o"),
+    ("ん", "N"),
+    ("っ", "cl"),
+];
+
+// 拗音：い段 + ゃゅょ
+const YOUON: [(&str, &str); 12] = [
+    ("き", "ky"), ("ぎ", "gy"), ("し", "sh"), ("じ", "j"), ("ち", "ch"), ("ぢ", "j"),
+    ("に", "ny"), ("ひ", "hy"), ("び", "by"), ("ぴ", "py"), ("み", "my"), ("り", "ry"),
+];
+
+// 外来语的写法
+const FOREIGN: [(&str, &str); 24] = [
+    ("しぇ", "sh e"), ("じぇ", "j e"), ("ちぇ", "ch e"), ("いぇ", "y e"),
+    ("ふぁ", "f a"), ("ふぃ", "f i"), ("ふぇ", "f e"), ("ふぉ", "f o"), ("ふゅ", "hy u"),
+    ("てぃ", "t i"), ("でぃ", "d i"), ("とぅ", "t u"), ("どぅ", "d u"), ("でゅ", "dy u"),
+    ("うぃ", "w i"), ("うぇ", "w e"), ("うぉ", "w o"),
+    ("ゔぁ", "v a"), ("ゔぃ", "v i"), ("ゔぇ", "v e"), ("ゔぉ", "v o"),
+    ("つぁ", "ts a"), ("つぇ", "ts e"), ("つぉ", "ts o"),
+];
+
+// 全角已经转成半角
+const PUNCTUATION: [(&str, &str); 9] = [
+    ("。", "."), ("、", ","), ("・", ","), (":", ","), (";", ","),
+    ("~", "ー"), ("〜", "ー"), ("―", "ー"), ("‥", "…"),
+];
+
+const DIGITS: [&str; 10] = ["ぜろ", "いち", "に", "さん", "よん", "ご", "ろく", "なな", "はち", "きゅう"];
+
+pub struct Japanese {
+    /// 漢字词 -> 读音（平假名或片假名），按最长匹配
+    pub dict: HashMap<String, String>,
+    pub max_word_len: usize,
+    /// 平假名 -> 音素，一到两个假名
+    pub kana_map: HashMap<String, String>,
+    pub punctuation: HashMap<String, String>,
+    pub number_pattern: Regex,
+}
+
+impl Japanese {
+    /// ja_dict.json: {"学生": "がくせい", "私": "わたし", ...}，漢字词 -> 平假名或片假名读音，格式和来源见 README；
+    /// 文件不存在时漢字都按中文读
+    pub fn init(ja_dict_path: &str) -> Result<Self, TtsError> {
+        // 没有词典时 TextUtils::init 会打 warn
+        if !Path::new(ja_dict_path).exists() {
+            return Ok(Japanese::with_dict(HashMap::new()));
+        }
+        let file = fs::File::open(ja_dict_path).map_err(|e| TtsError::dictionary(ja_dict_path, e))?;
+        let dict: HashMap<String, String> = serde_json::from_reader(&file).map_err(|e| TtsError::dictionary(ja_dict_path, e))?;
+        Ok(Japanese::with_dict(dict))
+    }
+
+    pub fn with_dict(dict: HashMap<String, String>) -> Self {
+        let dict: HashMap<String, String> = dict.into_iter().map(|(word, reading)| (word, to_hiragana(&reading))).collect();
+        let max_word_len = dict.keys().map(|word| word.chars().count()).max().unwrap_or(0);
+
+        let mut kana_map: HashMap<String, String> = HashMap::new();
+        for (consonant, kanas) in KANA_ROWS {
+            for (kana, vowel) in kanas.chars().zip(VOWELS) {
+                let phones = if consonant.is_empty() { vowel.to_string() } else { format!("{} {}", consonant, vowel) };
+                kana_map.insert(kana.to_string(), phones);
+            }
+        }
+        for (kana, phones) in EXCEPTIONS.iter().chain(FOREIGN.iter()) {
+            kana_map.insert(kana.to_string(), phones.to_string());
+        }
+        for (kana, consonant) in YOUON {
+            for (small, vowel) in [("ゃ", "a"), ("ゅ", "u"), ("ょ", "o")] {
+                kana_map.insert(format!("{}{}", kana, small), format!("{} {}", consonant, vowel));
+            }
+        }
+
+        let punctuation = PUNCTUATION.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+        let number_pattern = Regex::new(r"[0-9]+(\.[0-9]+)?").unwrap();
+        Japanese { dict, max_word_len, kana_map, punctuation, number_pattern }
+    }
+
+    /// 全角转半角、标点统一、数字读出来，去掉不能发音的字符
+    pub fn text_normalize(&self, text: String) -> String {
+        let text: String = text.chars().map(|c| match c {
+            '\u{ff01}'..='\u{ff5e}' => char::from_u32(c as u32 - 0xfee0).unwrap(),
+            '\u{3000}' => ' ',
+            _ => c,
+        }).collect();
+        let text = self.number_pattern.replace_all(&text, |caps: &Captures| read_number(&caps[0])).to_string();
+
+        let mut norm_text = String::new();
+        for c in text.chars() {
+            let s = c.to_string();
+            if let Some(p) = self.punctuation.get(&s) {
+                norm_text.push_str(p);
+            } else if is_kana(c) || is_kanji(c) || c == 'ー' || ",.!?…".contains(c) {
+                norm_text.push(c);
+            }
+        }
+        norm_text
+    }
+
+    /// 假名、漢字 -> 音素，漢字按词典读；词典里没有的先用 split_unknown_kanji 分出去，到这里还有的跳过
+    pub fn g2p(&self, norm_text: &str) -> Vec<String> {
+        let kana = self.to_kana(norm_text);
+        let chars: Vec<char> = kana.chars().collect();
+        let mut phones: Vec<String> = vec![];
+        let mut i = 0;
+        while i < chars.len() {
+            let c = chars[i];
+            if ",.!?…".contains(c) {
+                phones.push(c.to_string());
+                i += 1;
+                continue;
+            }
+            if c == 'ー' {
+                // 长音：重复前一个元音
+                if let Some(vowel) = phones.last().filter(|p| VOWELS.contains(&p.as_str())).cloned() {
+                    phones.push(vowel);
+                }
+                i += 1;
+                continue;
+            }
+            let two: String = chars[i..(i + 2).min(chars.len())].iter().collect();
+            let (phone, len) = match self.kana_map.get(&two) {
+                Some(phone) if two.chars().count() == 2 => (Some(phone), 2),
+                _ => (self.kana_map.get(&c.to_string()), 1),
+            };
+            if let Some(phone) = phone {
+                phones.extend(phone.split(' ').map(|p| p.to_string()));
+            }
+            i += len;
+        }
+        phones
+    }
+
+    /// 词典里查不到读音的漢字切出来：(有没有读音, 文字)；没有读音的交给中文前端读，不能丢掉
+    pub fn split_unknown_kanji(&self, text: &str) -> Vec<(bool, String)> {
+        let chars: Vec<char> = text.chars().collect();
+        let mut parts: Vec<(bool, String)> = vec![];
+        let mut i = 0;
+        while i < chars.len() {
+            let (known, len) = match self.match_word(&chars[i..]) {
+                Some((_, len)) => (true, len),
+                None => (!is_kanji(chars[i]), 1),
+            };
+            let word: String = chars[i..i + len].iter().collect();
+            match parts.last_mut() {
+                Some((last_known, part)) if *last_known == known => part.push_str(&word),
+                _ => parts.push((known, word)),
+            }
+            i += len;
+        }
+        let unknown: Vec<&str> = parts.iter().filter(|(known, _)| !known).map(|(_, part)| part.as_str()).collect();
+        if !unknown.is_empty() {
+            warn!("no reading for {:?} in ja_dict, read as chinese", unknown);
+        }
+        parts
+    }
+
+    // 最长匹配：(读音, 字数)
+    fn match_word(&self, chars: &[char]) -> Option<(&String, usize)> {
+        (1..=self.max_word_len.min(chars.len())).rev().find_map(|len| {
+            let word: String = chars[..len].iter().collect();
+            self.dict.get(&word).map(|reading| (reading, len))
+        })
+    }
+
+    // 漢字按词典换成读音，片假名换成平假名
+    fn to_kana(&self, text: &str) -> String {
+        let chars: Vec<char> = text.chars().collect();
+        let mut kana = String::new();
+        let mut unknown: HashSet<char> = HashSet::new();
+        let mut i = 0;
+        while i < chars.len() {
+            match self.match_word(&chars[i..]) {
+                Some((reading, len)) => {
+                    kana.push_str(reading);
+                    i += len;
+                }
+                None => {
+                    if is_kanji(chars[i]) {
+                        unknown.insert(chars[i]);
+                    } else {
+                        kana.push_str(&to_hiragana(&chars[i].to_string()));
+                    }
+                    i += 1;
+                }
+            }
+        }
+        if !unknown.is_empty() {
+            warn!("no reading for {:?} in {}", unknown, text);
+        }
+        kana
+    }
+}
+
+fn is_kana(c: char) -> bool {
+    matches!(c, '\u{3041}'..='\u{3096}' | '\u{30a1}'..='\u{30fa}')
+}
+
+fn is_kanji(c: char) -> bool {
+    matches!(c, '\u{4e00}'..='\u{9fff}' | '\u{3400}'..='\u{4dbf}' | '々')
+}
+
+/// 文字里有假名才是日文，全是漢字的按中文
+pub fn contains_kana(text: &str) -> bool {
+    text.chars().any(is_kana)
+}
+
+fn to_hiragana(text: &str) -> String {
+    text.chars().map(|c| match c {
+        '\u{30a1}'..='\u{30f6}' => char::from_u32(c as u32 - 0x60).unwrap(),
+        _ => c,
+    }).collect()
+}
+
+/// "123.5" -> "ひゃくにじゅうさんてんご"，太长或者 0 开头的按位读
+fn read_number(number: &str) -> String {
+    let (integer, decimal) = number.split_once('.').unwrap_or((number, ""));
+    let digits = |s: &str| s.chars().map(|c| DIGITS[c.to_digit(10).unwrap() as usize]).collect::<String>();
+    let mut reading = match integer.parse::<u64>() {
+        Ok(n) if integer.len() <= 16 && (integer == "0" || !integer.starts_with('0')) => read_integer(n),
+        _ => digits(integer),
+    };
+    if !decimal.is_empty() {
+        reading.push_str("てん");
+        reading.push_str(&digits(decimal));
+    }
+    reading
+}
+
+fn read_integer(n: u64) -> String {
+    if n == 0 {
+        return DIGITS[0].to_string();
+    }
+    let mut groups = vec![];
+    let mut rest = n;
+    while rest > 0 {
+        groups.push((rest % 10000) as usize);
+        rest /= 10000;
+    }
+    let mut reading = String::new();
+    for (i, &group) in groups.iter().enumerate().rev() {
+        if group == 0 {
+            continue;
+        }
+        reading.push_str(&read_group(group));
+        reading.push_str(["", "まん", "おく", "ちょう"][i]);
+    }
+    reading
+}
+
+// 0 - 9999，百、千的连浊和促音
+fn read_group(n: usize) -> String {
+    let (thousands, hundreds, tens, ones) = (n / 1000, n / 100 % 10, n / 10 % 10, n % 10);
+    let mut reading = String::new();
+    match thousands {
+        0 => {}
+        1 => reading.push_str("せん"),
+        3 => reading.push_str("さんぜん"),
+        8 => reading.push_str("はっせん"),
+        d => reading.push_str(&format!("{}せん", DIGITS[d])),
+    }
+    match hundreds {
+        0 => {}
+        1 => reading.push_str("ひゃく"),
+        3 => reading.push_str("さんびゃく"),
+        6 => reading.push_str("ろっぴゃく"),
+        8 => reading.push_str("はっぴゃく"),
+        d => reading.push_str(&format!("{}ひゃく", DIGITS[d])),
+    }
+    match tens {
+        0 => {}
+        1 => reading.push_str("じゅう"),
+        d => reading.push_str(&format!("{}じゅう", DIGITS[d])),
+    }
+    if ones > 0 {
+        reading.push_str(DIGITS[ones]);
+    }
+    reading
+}
+
+#[test]
+fn test_japanese_g2p() {
+    use crate::text::symbols::SYMBOLS;
+    let japanese = Japanese::with_dict(HashMap::from([("学生".to_string(), "ガクセイ".to_string())]));
+    for phones in japanese.kana_map.values() {
+        for phone in phones.split(' ') {
+            assert!(SYMBOLS.contains(&phone), "{} not in SYMBOLS", phone);
+        }
+    }
+
+    assert_eq!(read_number("0"), "ぜろ");
+    assert_eq!(read_number("123"), "ひゃくにじゅうさん");
+    assert_eq!(read_number("3800"), "さんぜんはっぴゃく");
+    assert_eq!(read_number("20000"), "にまん");
+    assert_eq!(read_number("1.5"), "いちてんご");
+
+    assert_eq!(japanese.text_normalize("私は学生です！２人「Ａ」：".to_string()), "私は学生です!に人,");
+    assert_eq!(japanese.g2p("がくせいです."), vec!["g", "a", "k", "u", "s", "e", "i", "d", "e", "s", "u", "."]);
+    assert_eq!(japanese.g2p("学生"), japanese.g2p("がくせい"));
+    assert_eq!(japanese.g2p("キャッシュ"), vec!["ky", "a", "cl", "sh", "u"]);
+    assert_eq!(japanese.g2p("コーヒー"), vec!["k", "o", "o", "h", "i", "i"]);
+    assert_eq!(japanese.g2p("ティーン"), vec!["t", "i", "i", "N"]);
+    assert!(contains_kana("私は") && !contains_kana("我们"));
+    assert_eq!(japanese.split_unknown_kanji("私は学生です"), vec![(false, "私".to_string()), (true, "は学生です".to_string())]);
+}
@@ -2,8 +2,9 @@ use std::collections::HashMap;
 use std::fs;
 use pinyin::ToPinyin;
 use regex::Regex;
-use crate::tts_sovits::text::lazy_pinyin::mmseg::MMSeg;
-use crate::tts_sovits::text::lazy_pinyin::style::{convert_styles, Style};
+use crate::error::TtsError;
+use crate::text::lazy_pinyin::mmseg::MMSeg;
+use crate::text::lazy_pinyin::style::{convert_styles, Style};
 
 pub struct LazyPinyin {
     neutral_tone_with_five: bool,
@@ -43,12 +44,12 @@ fn _remove_dup_and_empty(lst_list: Vec<Vec<String>>) -> Vec<Vec<String>> {
 
 
 impl LazyPinyin {
-    pub fn init(phrases_dict_path: &str, pinyin_dict_path: &str) -> Result<Self, String> {
-        let file_op = fs::File::open(phrases_dict_path).unwrap();
-        let file_op2 = fs::File::open(pinyin_dict_path).unwrap();
+    pub fn init(phrases_dict_path: &str, pinyin_dict_path: &str) -> Result<Self, TtsError> {
+        let file_op = fs::File::open(phrases_dict_path).map_err(|e| TtsError::dictionary(phrases_dict_path, e))?;
+        let file_op2 = fs::File::open(pinyin_dict_path).map_err(|e| TtsError::dictionary(pinyin_dict_path, e))?;
 
-        let phrases_dict: HashMap<String, Vec<Vec<String>>> = serde_json::from_reader(&file_op).unwrap();
-        let pinyin_dict: HashMap<String, String> = serde_json::from_reader(&file_op2).unwrap();
+        let phrases_dict: HashMap<String, Vec<Vec<String>>> = serde_json::from_reader(&file_op).map_err(|e| TtsError::dictionary(phrases_dict_path, e))?;
+        let pinyin_dict: HashMap<String, String> = serde_json::from_reader(&file_op2).map_err(|e| TtsError::dictionary(pinyin_dict_path, e))?;
 
         let mmseg = MMSeg::init(true, &phrases_dict);
 
@@ -1,6 +1,5 @@
 use std::collections::{HashMap, HashSet};
 use substring::Substring;
-use regex::Regex;
 
 pub struct MMSeg {
     _no_non_phrases: bool,
@@ -3,7 +3,7 @@ use std::fmt::format;
 use lazy_static::lazy_static;
 use regex::{Captures, Regex};
 use substring::Substring;
-use crate::tts_sovits::text::lazy_pinyin::convert::{_FINALS, convert_finals};
+use crate::text::lazy_pinyin::convert::{_FINALS, convert_finals};
 
 lazy_static! {
 
@@ -3,4 +3,5 @@ pub mod chinese;
 mod tone_sandhi;
 pub  mod symbols;
 pub mod english;
+pub mod japanese;
 pub mod lazy_pinyin;
\ No newline at end of file
@@ -1,7 +1,7 @@
 use std::ops::Add;
 // use regex::{Captures,Regex};
 use fancy_regex::{Captures, Regex};
-use crate::tts_sovits::text::zh_normalization::num::NumUtil;
+use crate::text::zh_normalization::num::NumUtil;
 
 pub struct Chronology {
     pub RE_DATE: Regex,
@@ -1,6 +1,6 @@
 // use regex::Regex;
 use fancy_regex::{Captures, Regex};
-use crate::tts_sovits::text::zh_normalization::num::NumUtil;
+use crate::text::zh_normalization::num::NumUtil;
 
 pub struct Phonecode {
     pub RE_MOBILE_PHONE: Regex,
@@ -19,7 +19,7 @@ impl Phonecode {
         Phonecode { RE_MOBILE_PHONE, RE_TELEPHONE, RE_NATIONAL_UNIFORM_NUMBER, num_util }
     }
 
-    fn phone2str(&self, phone_string: String, mobile: bool) -> String {
+    pub fn phone2str(&self, phone_string: String, mobile: bool) -> String {
         let mut results: Vec<String> = vec![];
         let sp_parts_opt = phone_string.strip_prefix("+");
         if mobile && sp_parts_opt.is_some() {
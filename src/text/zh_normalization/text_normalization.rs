@@ -2,9 +2,9 @@ use std::collections::HashMap;
 use std::ops::{Add};
 use fnv::FnvHashMap;
 use fancy_regex::{Captures, Regex};
-use crate::tts_sovits::text::zh_normalization::chronology::Chronology;
-use crate::tts_sovits::text::zh_normalization::phonecode::Phonecode;
-use crate::tts_sovits::text::zh_normalization::quantifier::Quantifier;
+use crate::text::zh_normalization::chronology::Chronology;
+use crate::text::zh_normalization::phonecode::Phonecode;
+use crate::text::zh_normalization::quantifier::Quantifier;
 
 pub struct TextNormalizer {
     pub SENTENCE_SPLITOR: Regex,
@@ -3,8 +3,10 @@ use regex::{Regex, Captures};
 use lingua::{DetectionResult, Language, LanguageDetector, LanguageDetectorBuilder};
 use lingua::Language::{Chinese, English, Japanese};
 use substring::Substring;
-use crate::tts_sovits::{text};
-use crate::tts_sovits::text::symbols::SYMBOLS;
+use crate::text;
+use crate::text::symbols::SYMBOLS;
+use crate::error::TtsError;
+use log::{debug, warn};
 
 pub(crate) const ENGLISH_LANG: &str = "English";
 pub(crate) const CHINESE_LANG: &str = "Chinese";
@@ -25,6 +27,7 @@ pub struct TextUtils {
     pub lang_seg: LangSegment,
     pub lang_chinese: text::chinese::Chinese,
     pub lang_english: text::english::English,
+    pub lang_japanese: text::japanese::Japanese,
     pub _symbol_to_id: HashMap<String, usize>,
 }
 
@@ -142,6 +145,11 @@ impl LangSegment {
         for res in &results {
             let lang = res.language();
             let text = sentence[res.start_index()..res.end_index()].to_string();
+            // 全是汉字的识别成日文时按中文
+            if lang == Japanese && !text::japanese::contains_kana(&text) {
+                out.push((CHINESE_LANG.to_string(), text));
+                continue;
+            }
             out.push((lang.to_string(), text));
         }
         // 123344 -> 纯数字、数字+标点，无法识别
@@ -262,31 +270,30 @@ impl LangSegment {
 
 impl TextUtils {
     /// 英语处理需要的文本
-    pub(crate) fn init(eng_dict_json_path: &str,
-                       rep_map_json_path: &str,
-                       ph_model_path: &str,
-                       phrases_dict_path: &str,
-                       pinyin_dict_path: &str, ) -> Result<Self, String> {
-        // let languages = vec![English, Chinese, Japanese];
-        let languages = vec![English, Chinese];
+    pub fn init(eng_dict_json_path: &str,
+                rep_map_json_path: &str,
+                ph_model_path: &str,
+                phrases_dict_path: &str,
+                pinyin_dict_path: &str,
+                ja_dict_path: &str, ) -> Result<Self, TtsError> {
+        let languages = vec![English, Chinese, Japanese];
         let lang_seg: LangSegment = LangSegment::init(languages);
-        let lang_chinese = text::chinese::Chinese::init(rep_map_json_path, phrases_dict_path, pinyin_dict_path);
-        let lang_english_op = text::english::English::init(
+        let lang_chinese = text::chinese::Chinese::init(rep_map_json_path, phrases_dict_path, pinyin_dict_path)?;
+        let lang_english = text::english::English::init(
             eng_dict_json_path, ph_model_path,
-        );
-        if lang_english_op.is_err() {
-            return Err(lang_english_op.err().unwrap());
+        )?;
+        let lang_japanese = text::japanese::Japanese::init(ja_dict_path)?;
+        if lang_japanese.dict.is_empty() {
+            warn!("ja_dict {} missing or empty: every japanese kanji will be read by the chinese frontend (mandarin readings)", ja_dict_path);
         }
 
-        let lang_english = lang_english_op.unwrap();
-
         let mut _symbol_to_id: HashMap<String, usize> = HashMap::new();
         for i in 0..SYMBOLS.len() {
             let s = SYMBOLS[i].to_string();
             _symbol_to_id.insert(s, i);
         }
 
-        Ok(TextUtils { lang_seg, lang_chinese, lang_english, _symbol_to_id })
+        Ok(TextUtils { lang_seg, lang_chinese, lang_english, lang_japanese, _symbol_to_id })
     }
 
     // 有特殊符号的处理，仅针对中文
@@ -343,9 +350,9 @@ impl TextUtils {
         let mut word2ph: Vec<usize> = vec![];
 
         if language == CHINESE_LANG {
-            println!("text:{}, len:{}", text, text.trim().chars().count());
+            debug!("text:{}, len:{}", text, text.trim().chars().count());
             norm_text = self.lang_chinese.text_normalize(text);
-            println!("norm_text:{}, len:{}", norm_text, norm_text.trim().chars().count());
+            debug!("norm_text:{}, len:{}", norm_text, norm_text.trim().chars().count());
 
             (phones, word2ph) = self.lang_chinese.g2p(&norm_text);
         } else if language == ENGLISH_LANG {
@@ -354,7 +361,9 @@ impl TextUtils {
             norm_text = self.lang_chinese.replace_symbol(text);
             phones = self.lang_english.g2p(&norm_text);
         } else if language == JAPANESE_LANG {
-            // todo
+            // 和英文一样没有 bert，不需要 word2ph
+            norm_text = self.lang_japanese.text_normalize(text);
+            phones = self.lang_japanese.g2p(&norm_text);
         }
 
         return (phones, word2ph, norm_text);
@@ -386,6 +395,15 @@ impl TextUtils {
             let (lang, text) = &seg_texts[i];
 
             let seg_texts2 = self.lang_seg.lang_seg_texts2(text, lang);
+            // ja_dict 里没有读音的漢字按中文读，不丢掉
+            let seg_texts2: Vec<(String, String)> = seg_texts2.into_iter().flat_map(|(lang2, text2)| {
+                if lang2 != JAPANESE_LANG {
+                    return vec![(lang2, text2)];
+                }
+                self.lang_japanese.split_unknown_kanji(&text2).into_iter()
+                    .map(|(known, part)| (if known { JAPANESE_LANG } else { CHINESE_LANG }.to_string(), part))
+                    .collect()
+            }).collect();
             for (ei, (lang2, text2)) in seg_texts2.iter().enumerate() {
                 if text2 == "" {
                     continue;
@@ -393,7 +411,7 @@ impl TextUtils {
                 let mut text2 = text2.clone();
                 // 添加标题
                 if ei == 0 && !text2.chars().nth(0).unwrap().is_numeric() {
-                    if lang2 == CHINESE_LANG {
+                    if lang2 == CHINESE_LANG || lang2 == JAPANESE_LANG {
                         text2 = "。".to_string() + &text2;
                     } else if lang2 == ENGLISH_LANG {
                         text2 = ". ".to_string() + &text2;
@@ -429,6 +447,61 @@ impl TextUtils {
 
         (phones_list, word2ph_list, lang_list, norm_text_list)
     }
+
+    /// 指定语言，不做语言检测：SSML 的 <lang xml:lang>；返回值同 get_cleaned_text_final，
+    /// 日文里 ja_dict 没有读音的漢字和 get_cleaned_text_final 一样切出来按中文读
+    pub fn get_cleaned_text_lang(&self, text: &str, lang: &str) -> (Vec<Vec<usize>>, Vec<Vec<usize>>, Vec<String>, Vec<String>) {
+        let parts: Vec<(String, String)> = if lang == JAPANESE_LANG {
+            self.lang_japanese.split_unknown_kanji(text).into_iter()
+                .map(|(known, part)| (if known { JAPANESE_LANG } else { CHINESE_LANG }.to_string(), part))
+                .collect()
+        } else {
+            vec![(lang.to_string(), text.to_string())]
+        };
+        let (mut phones_list, mut word2ph_list, mut lang_list, mut norm_text_list) = (vec![], vec![], vec![], vec![]);
+        for (lang, text) in parts {
+            let (phones, word2ph, norm_text) = self.clean_text_inf(&text, &lang);
+            if phones.is_empty() {
+                continue;
+            }
+            phones_list.push(self.cleaned_text_to_sequence(&phones));
+            word2ph_list.push(word2ph);
+            lang_list.push(lang);
+            norm_text_list.push(norm_text);
+        }
+        (phones_list, word2ph_list, lang_list, norm_text_list)
+    }
+
+    /// "zhong1 guo2" -> (音素 id, word2ph)，没写声调按轻声
+    pub fn pinyin_to_phones(&self, pinyin: &str) -> Result<(Vec<usize>, Vec<usize>), TtsError> {
+        let mut phones: Vec<String> = vec![];
+        let mut word2ph: Vec<usize> = vec![];
+        for syllable in pinyin.split_whitespace() {
+            let syllable = syllable.to_lowercase().replace('ü', "v").replace("u:", "v");
+            let (base, tone) = match syllable.chars().last() {
+                Some(tone) if "12345".contains(tone) => (&syllable[..syllable.len() - 1], tone.to_string()),
+                _ => (syllable.as_str(), "5".to_string()),
+            };
+            let symbols = self.lang_chinese.pinyin_to_symbol_map.get(base)
+                .ok_or_else(|| TtsError::TextFrontend(format!("unknown pinyin {}", syllable)))?;
+            let mut symbols: Vec<String> = symbols.split(" ").map(|s| s.to_string()).collect();
+            // 声调在韵母上
+            if let Some(last) = symbols.last_mut() {
+                last.push_str(&tone);
+            }
+            word2ph.push(symbols.len());
+            phones.append(&mut symbols);
+        }
+        Ok((self.cleaned_text_to_sequence(&phones), word2ph))
+    }
+
+    /// "HH AH0 L OW1" -> 音素 id，不在 SYMBOLS 里的报错
+    pub fn arpabet_to_phones(&self, arpabet: &str) -> Result<Vec<usize>, TtsError> {
+        arpabet.split_whitespace().map(|ph| {
+            self._symbol_to_id.get(&ph.to_uppercase()).cloned()
+                .ok_or_else(|| TtsError::TextFrontend(format!("unknown arpabet phone {}", ph)))
+        }).collect()
+    }
 }
 
 
@@ -441,6 +514,7 @@ pub fn chinese_test0() {
         "/Users/jxinfa/RustroverProjects/rs_tokenizer/data/model.npz",
         "/Users/jxinfa/RustroverProjects/rs_lazy_pinyin/datas/PHRASES_DICT.json",
         "/Users/jxinfa/RustroverProjects/rs_lazy_pinyin/datas/PINYIN_DICT.json",
+        "/Users/jxinfa/RustroverProjects/rs_tokenizer/data/ja_dict.json",
     ).unwrap();
 
 
@@ -0,0 +1,126 @@
+use std::time::Instant;
+use ndarray::Array2;
+use ort::Session;
+use crate::bert_utils::vq_decode;
+use crate::error::TtsError;
+use crate::metrics::elapsed_ms;
+use crate::post_process::sample_to_i16;
+
+/// token 级流式：每生成 window_tokens 个 semantic token 就跑一次 vq_model
+#[derive(Debug, Clone)]
+pub struct TokenStreamConfig {
+    /// 每个窗口的 token 数，1 个 token = 1280 个采样点（32k 下 40ms）
+    pub window_tokens: usize,
+    /// 每次往前多解码的 token 数：vq_model 需要上文，边界才不会突变
+    pub overlap_tokens: usize,
+    /// 窗口之间交叉淡化的采样点数
+    pub crossfade_samples: usize,
+}
+
+impl Default for TokenStreamConfig {
+    fn default() -> Self {
+        TokenStreamConfig { window_tokens: 25, overlap_tokens: 5, crossfade_samples: 1280 }
+    }
+}
+
+/// t2s 生成过程中按窗口解码音频，每解码出一块就调用 on_audio
+pub struct TokenStreamer<'a> {
+    vq_model: &'a Session,
+    phones: &'a Vec<usize>,
+    wav32k_arr: &'a Array2<f32>,
+    config: &'a TokenStreamConfig,
+    on_audio: &'a mut dyn FnMut(&[i16]),
+    // 已经输出过音频的 token 数
+    emitted_tokens: usize,
+    // 上一个窗口末尾留着没输出的部分，和下一个窗口交叉淡化
+    tail: Vec<f32>,
+    /// vq_model 累计耗时
+    pub vocoder_ms: f64,
+}
+
+impl<'a> TokenStreamer<'a> {
+    pub fn new(
+        vq_model: &'a Session,
+        phones: &'a Vec<usize>,
+        wav32k_arr: &'a Array2<f32>,
+        config: &'a TokenStreamConfig,
+        on_audio: &'a mut dyn FnMut(&[i16]),
+    ) -> Self {
+        TokenStreamer { vq_model, phones, wav32k_arr, config, on_audio, emitted_tokens: 0, tail: vec![], vocoder_ms: 0.0 }
+    }
+
+    /// 每生成一个 token 调用一次，攒够一个窗口才解码
+    pub fn push(&mut self, tokens: &[i64]) -> Result<(), TtsError> {
+        if self.window_ready(tokens.len()) {
+            self.decode(tokens, false)?;
+        }
+        Ok(())
+    }
+
+    /// 一共生成了 tokens 个时，push 会不会解码
+    pub fn window_ready(&self, tokens: usize) -> bool {
+        tokens >= self.emitted_tokens + self.config.window_tokens.max(1)
+    }
+
+    /// t2s 结束：tokens 是 t2s_decode 返回的 pred_semantic，输出剩下的全部音频
+    pub fn finish(&mut self, tokens: &[i64]) -> Result<(), TtsError> {
+        self.decode(tokens, true)
+    }
+
+    fn decode(&mut self, tokens: &[i64], last: bool) -> Result<(), TtsError> {
+        let end = tokens.len();
+        if end <= self.emitted_tokens {
+            if last && !self.tail.is_empty() {
+                let tail = std::mem::take(&mut self.tail);
+                (self.on_audio)(&to_pcm16(&tail));
+            }
+            return Ok(());
+        }
+
+        let start = self.emitted_tokens.saturating_sub(self.config.overlap_tokens);
+        let start_vocoder = Instant::now();
+        let audio = vq_decode(self.vq_model, &tokens[start..end], self.phones, self.wav32k_arr)?;
+        self.vocoder_ms += elapsed_ms(start_vocoder);
+
+        // overlap 部分对应的音频已经输出过
+        let skip = audio.len() * (self.emitted_tokens - start) / (end - start);
+        let fade = self.tail.len().min(skip);
+        let tail_keep = self.tail.len() - fade;
+
+        let mut out: Vec<f32> = self.tail[..tail_keep].to_vec();
+        out.extend(crossfade(&self.tail[tail_keep..], &audio[skip - fade..skip]));
+
+        let hold = if last { 0 } else { self.config.crossfade_samples.min(audio.len() - skip) };
+        out.extend_from_slice(&audio[skip..audio.len() - hold]);
+        self.tail = audio[audio.len() - hold..].to_vec();
+        self.emitted_tokens = end;
+
+        (self.on_audio)(&to_pcm16(&out));
+        Ok(())
+    }
+}
+
+/// 线性交叉淡化：from 淡出，to 淡入
+pub fn crossfade(from: &[f32], to: &[f32]) -> Vec<f32> {
+    let n = from.len().min(to.len());
+    (0..n).map(|i| {
+        let w = (i + 1) as f32 / (n + 1) as f32;
+        from[i] * (1.0 - w) + to[i] * w
+    }).collect()
+}
+
+// 窗口之间不能按各自的最大值归一化，否则音量会跳：直接截断
+fn to_pcm16(audio: &[f32]) -> Vec<i16> {
+    audio.iter().map(|&x| sample_to_i16(x)).collect()
+}
+
+#[test]
+fn test_crossfade() {
+    let from = vec![1.0; 4];
+    let to = vec![0.0; 4];
+    let out = crossfade(&from, &to);
+    assert_eq!(out.len(), 4);
+    assert!((out[0] - 0.8).abs() < 1e-6);
+    assert!((out[3] - 0.2).abs() < 1e-6);
+    assert!(out.windows(2).all(|w| w[0] > w[1]));
+}
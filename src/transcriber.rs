@@ -0,0 +1,167 @@
+use log::info;
+use ndarray::{Array1, Array2, ArrayView2, Axis, Ix3};
+use ort::{inputs, Session};
+use serde::{Deserialize, Serialize};
+use crate::bert_utils::ChBertUtils;
+use crate::error::{extract_tensor, TtsError};
+use crate::execution::ExecutionConfig;
+use crate::model_bundle::SessionCheck;
+
+/// 参考音频 -> 参考文字，ReferenceVoice 没有给参考文字时调用
+pub trait Transcriber: Send + Sync {
+    /// wav16k: 16k 单声道 pcm16
+    fn transcribe(&self, wav16k: &[i16]) -> Result<String, TtsError>;
+}
+
+/// 语音识别模型的路径，TtsConfig.asr 为 Some 时 TtsEngine::init 加载 OnnxTranscriber
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AsrConfig {
+    pub model_path: String,
+    pub tokens_path: String,
+}
+
+/// 测试用：不跑模型，总是返回同一段文字
+pub struct StubTranscriber {
+    pub text: String,
+}
+
+impl StubTranscriber {
+    pub fn init(text: &str) -> Self {
+        StubTranscriber { text: text.to_string() }
+    }
+}
+
+impl Transcriber for StubTranscriber {
+    fn transcribe(&self, _wav16k: &[i16]) -> Result<String, TtsError> {
+        Ok(self.text.clone())
+    }
+}
+
+/// onnx 导出的 CTC 语音识别模型（Paraformer、wav2vec2 等），特征提取要一起导出
+///
+/// 输入 speech [1, n] 16k 波形，有 speech_lengths 输入的也传；输出 logits [1, T, vocab]，贪心解码
+pub struct OnnxTranscriber {
+    session: Session,
+    /// tokens.txt：每行 `token` 或 `token id`
+    tokens: Vec<String>,
+    blank_id: usize,
+    with_lengths: bool,
+}
+
+impl OnnxTranscriber {
+    pub fn init(config: &AsrConfig, execution: &ExecutionConfig) -> Result<Self, TtsError> {
+        let session = ChBertUtils::load_model(&config.model_path, execution)?;
+        SessionCheck::new("asr_model", &session)
+            .inputs(&["speech"])
+            .outputs(&["logits"])
+            .finish()?;
+        let with_lengths = session.inputs.iter().any(|i| i.name == "speech_lengths");
+
+        let content = std::fs::read_to_string(&config.tokens_path).map_err(|e| TtsError::dictionary(&config.tokens_path, e))?;
+        let tokens = parse_tokens(&content);
+        let blank_id = tokens.iter().position(|t| t == "<blank>" || t == "<pad>").unwrap_or(0);
+        info!("asr model {}: {} tokens, blank {}", config.model_path, tokens.len(), blank_id);
+        Ok(OnnxTranscriber { session, tokens, blank_id, with_lengths })
+    }
+}
+
+impl Transcriber for OnnxTranscriber {
+    fn transcribe(&self, wav16k: &[i16]) -> Result<String, TtsError> {
+        let speech: Array2<f32> = Array1::from_iter(wav16k.iter().map(|&x| x as f32 / 32768.0)).insert_axis(Axis(0));
+        let speech_lengths: Array1<i32> = Array1::from_vec(vec![wav16k.len() as i32]);
+        let outputs = if self.with_lengths {
+            self.session.run(inputs!["speech" => speech.view(), "speech_lengths" => speech_lengths.view()]?)?
+        } else {
+            self.session.run(inputs!["speech" => speech.view()]?)?
+        };
+        let logits = extract_tensor!(outputs, "logits", f32, Ix3);
+        let ids = ctc_greedy(logits.index_axis(Axis(0), 0), self.blank_id);
+        let pieces: Vec<&str> = ids.iter().filter_map(|&id| self.tokens.get(id)).map(|t| t.as_str()).collect();
+        Ok(join_tokens(&pieces))
+    }
+}
+
+// 行号是 id，写了 id 的按写的
+fn parse_tokens(content: &str) -> Vec<String> {
+    let mut tokens: Vec<String> = vec![];
+    for (i, line) in content.lines().enumerate() {
+        let line = line.trim_end_matches('\r');
+        let (token, id) = match line.rsplit_once(char::is_whitespace) {
+            Some((token, id)) if !token.is_empty() && id.parse::<usize>().is_ok() => (token, id.parse::<usize>().unwrap()),
+            _ => (line, i),
+        };
+        if tokens.len() <= id {
+            tokens.resize(id + 1, String::new());
+        }
+        tokens[id] = token.to_string();
+    }
+    tokens
+}
+
+/// 每帧取最大的，合并连续重复，去掉 blank
+fn ctc_greedy(logits: ArrayView2<f32>, blank_id: usize) -> Vec<usize> {
+    let mut ids = vec![];
+    let mut prev = None;
+    for frame in logits.outer_iter() {
+        let id = frame.iter().enumerate().fold((0, f32::NEG_INFINITY), |best, (i, &x)| if x > best.1 { (i, x) } else { best }).0;
+        if Some(id) != prev && id != blank_id {
+            ids.push(id);
+        }
+        prev = Some(id);
+    }
+    ids
+}
+
+/// sentencepiece 的 ▁ 是词首，Paraformer 的 @@ 是词没完；中文直接拼，英文单词之间加空格；<unk> 之类的去掉
+fn join_tokens(pieces: &[&str]) -> String {
+    // 有 ▁ 的按 sentencepiece：没有 ▁ 的都接在上一个后面
+    let sentencepiece = pieces.iter().any(|piece| piece.starts_with('▁'));
+    let mut text = String::new();
+    let mut joined = true;
+    for &piece in pieces {
+        if piece.starts_with('<') && piece.ends_with('>') {
+            continue;
+        }
+        let (piece, word_start) = match piece.strip_prefix('▁') {
+            Some(piece) => (piece, true),
+            None => (piece, false),
+        };
+        let (piece, next_joined) = match piece.strip_suffix("@@") {
+            Some(piece) => (piece, true),
+            None => (piece, false),
+        };
+        let ascii_word = |c: Option<char>| c.map_or(false, |c| c.is_ascii_alphanumeric());
+        let new_word = if sentencepiece {
+            word_start
+        } else {
+            !joined && ascii_word(text.chars().last()) && ascii_word(piece.chars().next())
+        };
+        if !text.is_empty() && new_word {
+            text.push(' ');
+        }
+        text.push_str(piece);
+        joined = next_joined;
+    }
+    text.trim().to_string()
+}
+
+#[test]
+fn test_ctc_decode() {
+    let logits = Array2::from_shape_vec((6, 3), vec![
+        0.9, 0.1, 0.0,
+        0.1, 0.8, 0.1,
+        0.1, 0.8, 0.1,
+        0.9, 0.0, 0.1,
+        0.1, 0.8, 0.1,
+        0.0, 0.1, 0.9,
+    ]).unwrap();
+    assert_eq!(ctc_greedy(logits.view(), 0), vec![1, 1, 2]);
+
+    assert_eq!(parse_tokens("<blank> 0\n你 1\n好 2\n"), vec!["<blank>", "你", "好"]);
+    assert_eq!(join_tokens(&["<s>", "我", "注", "意", "到", "</s>"]), "我注意到");
+    assert_eq!(join_tokens(&["hel@@", "lo", "world", "你", "好"]), "hello world你好");
+    assert_eq!(join_tokens(&["▁he", "llo", "▁world"]), "hello world");
+
+    let transcriber: Box<dyn Transcriber> = Box::new(StubTranscriber::init("我注意到了"));
+    assert_eq!(transcriber.transcribe(&[0; 16000]).unwrap(), "我注意到了");
+}
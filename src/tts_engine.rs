@@ -0,0 +1,1015 @@
+use std::path::Path;
+use std::time::{Duration, Instant};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use ndarray::Array2;
+use ort::Session;
+use crate::bert_utils::{ChBertUtils, probe_batch_mask, probe_empty_prompt, t2s_decode, t2s_decode_batch, vq_decode_pcm16};
+use crate::concurrency::{CancelToken, StageConcurrency, StageLimits};
+use crate::diagnostics::{GenerationDiagnostics, RetryPolicy};
+use crate::error::TtsError;
+use crate::execution::{ExecutionConfig, Provider};
+use crate::ffmpeg_utils::{FfmpegUtils, SoundTouchStream};
+use crate::metrics::{elapsed_ms, SynthesisMetrics};
+use crate::pause::{cut_texts_with_boundaries, Boundary, PauseConfig};
+use crate::post_process::{db_to_gain, sample_to_i16, PostProcessConfig};
+use crate::model_bundle::{check_bert, check_gpt, check_ssl, check_sovits, ModelManifest, SAMPLE_RATE};
+use crate::reference_check::ReferenceCheck;
+use crate::reference_voice::{Prompt, ReferenceClip, ReferenceVoice};
+use crate::ssml::{is_ssml, parse_ssml, SsmlSpan};
+use crate::sampling::{EarlyStop, MAX_SEMANTIC_TOKENS};
+use crate::text_utils::TextUtils;
+use crate::wav::wav_bytes;
+use crate::token_stream::{TokenStreamConfig, TokenStreamer};
+use crate::transcriber::{AsrConfig, OnnxTranscriber, Transcriber};
+
+/// 模型、字典文件路径
+///
+/// 可以从 json 配置文件读取，没写的字段按 ./data 目录下的默认文件名
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TtsConfig {
+    pub tokenizer_path: String,
+    pub bert_model_path: String,
+    // cnhubert_base_path
+    pub ssl_model_path: String,
+    // sovits_path
+    pub vq_model_latent_path: String,
+    pub vq_model_path: String,
+    // gpt_path
+    pub t2s_first_stage_decoder_path: String,
+    pub t2s_stage_decoder_path: String,
+    // 文本前端
+    pub eng_dict_path: String,
+    pub rep_map_path: String,
+    pub ph_model_path: String,
+    pub phrases_dict_path: String,
+    pub pinyin_dict_path: String,
+    /// 日文漢字的读音，没有这个文件只读假名
+    pub ja_dict_path: String,
+    pub sampling_rate: i32,
+    /// 推理设备、线程数
+    pub execution: ExecutionConfig,
+    /// 多个请求共用模型时，每个阶段同时跑的请求数
+    pub concurrency: StageConcurrency,
+    /// 参考音频的检查和预处理
+    pub reference_check: ReferenceCheck,
+    /// 没有参考文字时用来识别参考音频，None: 没有参考文字就按 ref_free 合成
+    pub asr: Option<AsrConfig>,
+}
+
+/// 合成参数
+#[derive(Debug, Clone)]
+pub struct SynthesisParams {
+    pub top_k: i64,
+    /// 1.0: 不做 top_p 截断；只有 stage decoder 导出了 float logits 时生效，下同
+    pub top_p: f32,
+    pub temperature: f32,
+    /// 1.0: 不惩罚
+    pub repetition_penalty: f32,
+    /// 固定种子，同样的输入得到同样的输出
+    pub seed: Option<u64>,
+    /// 最多生成多少个 semantic token，None: 按音素数估计
+    pub max_semantic_tokens: Option<usize>,
+    /// 复读检测，None: 只靠 EOS 和 max_semantic_tokens 结束
+    pub early_stop: Option<EarlyStop>,
+    /// 生成检查和重试，None: 不检查
+    pub retry: Option<RetryPolicy>,
+    /// 分段之间按边界类型插入的停顿
+    pub pauses: PauseConfig,
+    /// Some: synthesize_with_callback 按 token 窗口输出音频，None: 按分段输出
+    pub token_streaming: Option<TokenStreamConfig>,
+    /// synthesize 一次送进 t2s 的分段数，1: 逐段合成；TtsEngine.batch_t2s 为 true 才能 batch
+    pub batch_size: usize,
+    /// 取消合成：每个 decoder step、每段开始前检查，返回 Cancelled 和已经合成的音频
+    pub cancel: Option<CancelToken>,
+    /// 到这个时间还没合成完就按取消处理
+    pub deadline: Option<Instant>,
+    /// 每段拼接前的后处理：响度、裁静音、淡入淡出；token_streaming 的窗口只做截断转换
+    pub post_process: PostProcessConfig,
+    /// 语速 0.3 - 2.0，1.0: 不变；分段之间的静音也跟着变
+    pub speed: f32,
+    /// 变调：半音，0: 不变调，和语速无关
+    pub pitch_semitones: f32,
+    /// 音量：dB，0: 不变
+    pub volume_db: f32,
+    /// 不用参考文字：t2s 只输入目标文字，不输入参考文字和 prompt semantic，音色只来自参考音频；参考文字为空时总是这样
+    pub ref_free: bool,
+}
+
+/// 合成结果：单声道 pcm16
+#[derive(Debug, Clone)]
+pub struct Audio {
+    pub samples: Vec<i16>,
+    pub sample_rate: i32,
+    /// 每一段的生成情况
+    pub diagnostics: Vec<GenerationDiagnostics>,
+    /// 各阶段耗时
+    pub metrics: SynthesisMetrics,
+}
+
+/// 流式合成的一段：对应 cut_texts 切出来的一段文字
+#[derive(Debug, Clone)]
+pub struct AudioChunk {
+    pub index: usize,
+    pub text: String,
+    /// 和上一段有声音的之间的停顿放在开头，最后一段后面不加停顿
+    pub samples: Vec<i16>,
+    pub sample_rate: i32,
+    pub diagnostics: GenerationDiagnostics,
+    pub metrics: SynthesisMetrics,
+}
+
+/// `SynthesisParams::segment_effects`：一段音频分窗口送进来，变速变调前后连续
+pub struct SegmentEffects {
+    soundtouch: Option<SoundTouchStream>,
+    gain: f32,
+}
+
+impl SegmentEffects {
+    /// 送进一个窗口，返回已经处理好的部分，变速时会滞后一点
+    pub fn process(&mut self, samples: &[i16]) -> Vec<i16> {
+        let audio: Vec<f32> = samples.iter().map(|&x| x as f32 / 32768.0).collect();
+        let audio = match &mut self.soundtouch {
+            Some(soundtouch) => soundtouch.put(&audio),
+            None => audio,
+        };
+        self.to_pcm16(audio)
+    }
+
+    /// 这一段结束，取出剩下的
+    pub fn finish(&mut self) -> Vec<i16> {
+        let audio = match &mut self.soundtouch {
+            Some(soundtouch) => soundtouch.flush(),
+            None => vec![],
+        };
+        self.to_pcm16(audio)
+    }
+
+    fn to_pcm16(&self, audio: Vec<f32>) -> Vec<i16> {
+        audio.iter().map(|&x| sample_to_i16(x * self.gain)).collect()
+    }
+}
+
+/// `TtsEngine::synthesize_stream` 返回的迭代器：每次 next 合成一段
+pub struct SynthesisStream<'a> {
+    engine: &'a TtsEngine,
+    reference: &'a ReferenceVoice,
+    params: &'a SynthesisParams,
+    texts: Vec<(String, Boundary)>,
+    idx: usize,
+    /// 上一段有声音的结尾边界，停顿只放在两段有声音的中间
+    pending: Option<Boundary>,
+}
+
+/// 加载好的全部模型 + 文本前端，可以多次合成
+///
+/// Send + Sync：多个线程可以同时合成，见 `worker_pool::WorkerPool`
+pub struct TtsEngine {
+    pub config: TtsConfig,
+    pub text_util: TextUtils,
+    pub ch_bert_util: ChBertUtils,
+    pub bert_model: Session,
+    pub ssl_model: Session,
+    pub vq_model_latent: Session,
+    pub t2s_first_stage_decoder: Session,
+    pub t2s_stage_decoder: Session,
+    /// t2s_stage_decoder 实际跑在哪个 provider 上
+    pub t2s_device: Provider,
+    /// 加载时检查过 stage decoder 按 xy_attn_mask 屏蔽 batch 补齐的位置，false: batch_size 不生效
+    pub batch_t2s: bool,
+    /// 加载时检查过 t2s 能用空的 prompt，false: ref_free、没有参考文字的音色直接报错
+    pub empty_prompt: bool,
+    pub vq_model: Session,
+    /// 按 config.concurrency 限制每个阶段的并发
+    pub limits: StageLimits,
+    /// 参考音色没有参考文字时识别参考音频
+    pub transcriber: Option<Box<dyn Transcriber>>,
+}
+
+/// load_gpt_weights 加载好、还没换上的 GPT 权重
+pub struct GptWeights {
+    t2s_first_stage_decoder: Session,
+    t2s_stage_decoder: Session,
+    t2s_device: Provider,
+    batch_t2s: bool,
+    empty_prompt: bool,
+    t2s_first_stage_decoder_path: String,
+    t2s_stage_decoder_path: String,
+}
+
+/// load_sovits_weights 加载好、还没换上的 SoVITS 权重
+pub struct SovitsWeights {
+    vq_model_latent: Session,
+    vq_model: Session,
+    vq_model_latent_path: String,
+    vq_model_path: String,
+}
+
+// 编译期检查：TtsEngine 加了不是 Send + Sync 的字段这里就报错，不用等到 WorkerPool
+fn _assert_send_sync() {
+    fn _assert<T: Send + Sync>() {}
+    _assert::<TtsEngine>();
+    _assert::<ReferenceVoice>();
+}
+
+impl TtsConfig {
+    /// 目录下按 data 目录的文件名查找：tokenizer.json、bert_model.onnx、ssl_model.onnx ...
+    pub fn from_model_dir(model_dir: &str) -> Self {
+        TtsConfig::from_manifest(model_dir, &ModelManifest::default())
+    }
+
+    /// 模型文件名、采样率按 model.json，字典还是 data 目录的文件名
+    pub fn from_manifest(model_dir: &str, manifest: &ModelManifest) -> Self {
+        let path = |name: &str| Path::new(model_dir).join(name).to_string_lossy().to_string();
+        let files = &manifest.files;
+        TtsConfig {
+            tokenizer_path: path(&files.tokenizer),
+            bert_model_path: path(&files.bert_model),
+            ssl_model_path: path(&files.ssl_model),
+            vq_model_latent_path: path(&files.vq_model_latent),
+            vq_model_path: path(&files.vq_model),
+            t2s_first_stage_decoder_path: path(&files.t2s_first_stage_decoder),
+            t2s_stage_decoder_path: path(&files.t2s_stage_decoder),
+            eng_dict_path: path("eng_dict.json"),
+            rep_map_path: path("rep_map.json"),
+            ph_model_path: path("model.npz"),
+            phrases_dict_path: path("PHRASES_DICT.json"),
+            pinyin_dict_path: path("PINYIN_DICT.json"),
+            ja_dict_path: path("ja_dict.json"),
+            sampling_rate: manifest.sample_rate,
+            execution: ExecutionConfig::default(),
+            concurrency: StageConcurrency::default(),
+            reference_check: ReferenceCheck::default(),
+            asr: None,
+        }
+    }
+
+    /// json 配置文件
+    pub fn from_file(config_path: &str) -> Result<Self, TtsError> {
+        let content = std::fs::read_to_string(config_path)?;
+        serde_json::from_str(&content).map_err(|e| TtsError::Config { path: config_path.to_string(), message: e.to_string() })
+    }
+}
+
+impl Default for TtsConfig {
+    fn default() -> Self {
+        TtsConfig::from_model_dir("./data")
+    }
+}
+
+impl Default for SynthesisParams {
+    fn default() -> Self {
+        SynthesisParams {
+            top_k: 20,
+            top_p: 1.0,
+            temperature: 0.8,
+            repetition_penalty: 1.35,
+            seed: None,
+            max_semantic_tokens: None,
+            early_stop: Some(EarlyStop::default()),
+            retry: Some(RetryPolicy::default()),
+            pauses: PauseConfig::default(),
+            token_streaming: None,
+            batch_size: 1,
+            cancel: None,
+            deadline: None,
+            post_process: PostProcessConfig::default(),
+            speed: 1.0,
+            pitch_semitones: 0.0,
+            volume_db: 0.0,
+            ref_free: false,
+        }
+    }
+}
+
+impl SynthesisParams {
+    /// 一段文字最多生成的 token 数：1 个 token 40ms，正常语速一个音素不超过 5 个 token
+    pub fn max_tokens(&self, phones_len: usize) -> usize {
+        self.max_semantic_tokens
+            .unwrap_or(phones_len * 5 + 50)
+            .min(MAX_SEMANTIC_TOKENS)
+    }
+
+    /// 从现在起最多合成 timeout
+    pub fn with_timeout(self, timeout: Duration) -> Self {
+        // 远到溢出的当作没有 deadline
+        SynthesisParams { deadline: Instant::now().checked_add(timeout), ..self }
+    }
+
+    /// 分段之后的停顿：采样点数
+    pub fn pause_samples(&self, boundary: Boundary, sample_rate: i32) -> usize {
+        (sample_rate as f32 * self.pauses.seconds(boundary) / self.speed()) as usize
+    }
+
+    fn speed(&self) -> f32 {
+        // NaN 经过 clamp 还是 NaN
+        if self.speed.is_finite() { self.speed.clamp(0.3, 2.0) } else { 1.0 }
+    }
+
+    /// NaN / inf 当作不变调、不改音量
+    fn pitch_and_volume(&self) -> (f32, f32) {
+        let finite = |value: f32| if value.is_finite() { value } else { 0.0 };
+        if !(self.speed.is_finite() && self.pitch_semitones.is_finite() && self.volume_db.is_finite()) {
+            warn!("ignore non-finite speed {} / pitch {} / volume {}", self.speed, self.pitch_semitones, self.volume_db);
+        }
+        (finite(self.pitch_semitones), finite(self.volume_db))
+    }
+
+    /// speed、pitch_semitones、volume_db 作用在一段 pcm16 上
+    pub fn apply_effects(&self, samples: Vec<i16>, sample_rate: i32) -> Vec<i16> {
+        let (pitch_semitones, volume_db) = self.pitch_and_volume();
+        let stretch = self.speed() != 1.0 || pitch_semitones != 0.0;
+        if samples.is_empty() || (!stretch && volume_db == 0.0) {
+            return samples;
+        }
+        let audio = if stretch {
+            FfmpegUtils::sound_touch(&samples, sample_rate as u32, self.speed() as f64, pitch_semitones as f64)
+        } else {
+            samples.iter().map(|&x| x as f32 / 32768.0).collect()
+        };
+        let gain = db_to_gain(volume_db);
+        audio.iter().map(|&x| sample_to_i16(x * gain)).collect()
+    }
+
+    /// token_streaming 时一段文字的效果：这一段的所有窗口共用一个 SoundTouch
+    pub fn segment_effects(&self, sample_rate: i32) -> SegmentEffects {
+        let (pitch_semitones, volume_db) = self.pitch_and_volume();
+        let stretch = self.speed() != 1.0 || pitch_semitones != 0.0;
+        SegmentEffects {
+            soundtouch: stretch.then(|| SoundTouchStream::new(sample_rate as u32, self.speed() as f64, pitch_semitones as f64)),
+            gain: db_to_gain(volume_db),
+        }
+    }
+
+    /// 取消了或超过 deadline 返回 Cancelled，partial 由调用方补上
+    pub fn check_cancelled(&self) -> Result<(), TtsError> {
+        let cancelled = self.cancel.as_ref().map_or(false, |cancel| cancel.is_cancelled())
+            || self.deadline.map_or(false, |deadline| Instant::now() >= deadline);
+        if cancelled {
+            Err(TtsError::Cancelled { partial: vec![] })
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl Audio {
+    /// 时长：秒
+    pub fn duration(&self) -> f32 {
+        self.samples.len() as f32 / self.sample_rate as f32
+    }
+
+    /// wav 文件内容，http 返回用
+    pub fn to_wav(&self) -> Vec<u8> {
+        wav_bytes(&self.samples, self.sample_rate)
+    }
+
+    /// 保存为文件，格式由后缀决定
+    pub fn save(&self, out_file_path: &str) -> Result<(), TtsError> {
+        FfmpegUtils::decode_data_to_path(&self.samples, out_file_path, self.sample_rate, 1024)
+    }
+}
+
+impl TtsEngine {
+    pub fn init(config: TtsConfig) -> Result<Self, TtsError> {
+        // 参考音频、vq_model 的输出都按 32k 处理
+        if config.sampling_rate != SAMPLE_RATE {
+            return Err(TtsError::ModelSignature { model: "vq_model".to_string(), message: format!("sampling_rate {} (supported: {})", config.sampling_rate, SAMPLE_RATE) });
+        }
+        let text_util = TextUtils::init(
+            &config.eng_dict_path,
+            &config.rep_map_path,
+            &config.ph_model_path,
+            &config.phrases_dict_path,
+            &config.pinyin_dict_path,
+            &config.ja_dict_path,
+        )?;
+        let ch_bert_util = ChBertUtils::init(&config.tokenizer_path)?;
+
+        let bert_model = ChBertUtils::load_model(&config.bert_model_path, &config.execution)?;
+        let ssl_model = ChBertUtils::load_model(&config.ssl_model_path, &config.execution)?;
+        let vq_model_latent = ChBertUtils::load_model(&config.vq_model_latent_path, &config.execution)?;
+        let t2s_first_stage_decoder = ChBertUtils::load_model(&config.t2s_first_stage_decoder_path, &config.execution)?;
+        let (t2s_stage_decoder, t2s_device) = ChBertUtils::load_model_on(&config.t2s_stage_decoder_path, &config.execution)?;
+        let vq_model = ChBertUtils::load_model(&config.vq_model_path, &config.execution)?;
+
+        // 导出方式不一样的模型在这里就报错，不等到推理
+        check_bert(&bert_model)?;
+        check_ssl(&ssl_model)?;
+        check_gpt(&t2s_first_stage_decoder, &t2s_stage_decoder)?;
+        check_sovits(&vq_model_latent, &vq_model)?;
+        let batch_t2s = check_batch_t2s(&t2s_first_stage_decoder, &t2s_stage_decoder, &t2s_device);
+        let empty_prompt = check_empty_prompt(&t2s_first_stage_decoder, &t2s_stage_decoder, &t2s_device);
+
+        let transcriber: Option<Box<dyn Transcriber>> = match &config.asr {
+            Some(asr) => Some(Box::new(OnnxTranscriber::init(asr, &config.execution)?)),
+            None => None,
+        };
+
+        let limits = StageLimits::new(&config.concurrency);
+        Ok(TtsEngine {
+            config,
+            text_util,
+            ch_bert_util,
+            bert_model,
+            ssl_model,
+            vq_model_latent,
+            t2s_first_stage_decoder,
+            t2s_stage_decoder,
+            t2s_device,
+            batch_t2s,
+            empty_prompt,
+            vq_model,
+            limits,
+            transcriber,
+        })
+    }
+
+    /// 换成别的语音识别实现，比如测试用的 StubTranscriber
+    pub fn set_transcriber(&mut self, transcriber: Box<dyn Transcriber>) {
+        self.transcriber = Some(transcriber);
+    }
+
+    /// 有 model.json 就按 model.json 加载
+    pub fn from_model_dir(model_dir: &str) -> Result<Self, TtsError> {
+        let manifest = ModelManifest::load(model_dir)?;
+        TtsEngine::init(TtsConfig::from_manifest(model_dir, &manifest))
+    }
+
+    /// 换 GPT 权重：目录下的 t2s_first_stage_decoder.onnx、t2s_stage_decoder.onnx
+    pub fn set_gpt_weights(&mut self, model_dir: &str) -> Result<(), TtsError> {
+        let weights = self.load_gpt_weights(model_dir)?;
+        self.swap_gpt_weights(weights);
+        Ok(())
+    }
+
+    /// 换 SoVITS 权重：目录下的 vq_model_latent.onnx、vq_model.onnx，之前生成的 ReferenceVoice 要重新生成
+    pub fn set_sovits_weights(&mut self, model_dir: &str) -> Result<(), TtsError> {
+        let weights = self.load_sovits_weights(model_dir)?;
+        self.swap_sovits_weights(weights);
+        Ok(())
+    }
+
+    /// 只加载、检查，不替换；GPT 和 SoVITS 一起换时两边都成功了再 swap
+    pub fn load_gpt_weights(&self, model_dir: &str) -> Result<GptWeights, TtsError> {
+        let config = TtsConfig::from_manifest(model_dir, &ModelManifest::load(model_dir)?);
+        let t2s_first_stage_decoder = ChBertUtils::load_model(&config.t2s_first_stage_decoder_path, &self.config.execution)?;
+        let (t2s_stage_decoder, t2s_device) = ChBertUtils::load_model_on(&config.t2s_stage_decoder_path, &self.config.execution)?;
+        check_gpt(&t2s_first_stage_decoder, &t2s_stage_decoder)?;
+        let batch_t2s = check_batch_t2s(&t2s_first_stage_decoder, &t2s_stage_decoder, &t2s_device);
+        let empty_prompt = check_empty_prompt(&t2s_first_stage_decoder, &t2s_stage_decoder, &t2s_device);
+        Ok(GptWeights {
+            t2s_first_stage_decoder,
+            t2s_stage_decoder,
+            t2s_device,
+            batch_t2s,
+            empty_prompt,
+            t2s_first_stage_decoder_path: config.t2s_first_stage_decoder_path,
+            t2s_stage_decoder_path: config.t2s_stage_decoder_path,
+        })
+    }
+
+    pub fn load_sovits_weights(&self, model_dir: &str) -> Result<SovitsWeights, TtsError> {
+        let config = TtsConfig::from_manifest(model_dir, &ModelManifest::load(model_dir)?);
+        let vq_model_latent = ChBertUtils::load_model(&config.vq_model_latent_path, &self.config.execution)?;
+        let vq_model = ChBertUtils::load_model(&config.vq_model_path, &self.config.execution)?;
+        check_sovits(&vq_model_latent, &vq_model)?;
+        Ok(SovitsWeights {
+            vq_model_latent,
+            vq_model,
+            vq_model_latent_path: config.vq_model_latent_path,
+            vq_model_path: config.vq_model_path,
+        })
+    }
+
+    /// 换上 weights，返回换下来的，失败时可以换回去
+    pub fn swap_gpt_weights(&mut self, mut weights: GptWeights) -> GptWeights {
+        std::mem::swap(&mut self.t2s_first_stage_decoder, &mut weights.t2s_first_stage_decoder);
+        std::mem::swap(&mut self.t2s_stage_decoder, &mut weights.t2s_stage_decoder);
+        std::mem::swap(&mut self.t2s_device, &mut weights.t2s_device);
+        std::mem::swap(&mut self.batch_t2s, &mut weights.batch_t2s);
+        std::mem::swap(&mut self.empty_prompt, &mut weights.empty_prompt);
+        std::mem::swap(&mut self.config.t2s_first_stage_decoder_path, &mut weights.t2s_first_stage_decoder_path);
+        std::mem::swap(&mut self.config.t2s_stage_decoder_path, &mut weights.t2s_stage_decoder_path);
+        weights
+    }
+
+    pub fn swap_sovits_weights(&mut self, mut weights: SovitsWeights) -> SovitsWeights {
+        std::mem::swap(&mut self.vq_model_latent, &mut weights.vq_model_latent);
+        std::mem::swap(&mut self.vq_model, &mut weights.vq_model);
+        std::mem::swap(&mut self.config.vq_model_latent_path, &mut weights.vq_model_latent_path);
+        std::mem::swap(&mut self.config.vq_model_path, &mut weights.vq_model_path);
+        weights
+    }
+
+    /// 参考音色：ssl_model、vq_model_latent、参考文字 bert 只在这里跑一次
+    pub fn reference_voice(&self, ref_wav_path: &str, prompt_text: &str) -> Result<ReferenceVoice, TtsError> {
+        ReferenceVoice::from_file(self, ref_wav_path, prompt_text)
+    }
+
+    /// 用还没换上的 SoVITS 权重生成参考音色，换权重前先把音色准备好
+    pub fn reference_voice_with(&self, weights: &SovitsWeights, ref_wav_path: &str, prompt_text: &str) -> Result<ReferenceVoice, TtsError> {
+        ReferenceVoice::from_file_with(self, &weights.vq_model_latent, ref_wav_path, prompt_text)
+    }
+
+    /// 多段参考音频合成一个音色，见 `ReferenceVoice::from_clips`
+    pub fn reference_voice_clips(&self, clips: &[ReferenceClip]) -> Result<ReferenceVoice, TtsError> {
+        ReferenceVoice::from_clips(self, clips)
+    }
+
+    /// t2s 不能用空的 prompt 时，ref_free 在这里报错，不等 onnxruntime 报形状错误
+    fn prompt<'a>(&self, reference: &'a ReferenceVoice, ref_free: bool) -> Result<Prompt<'a>, TtsError> {
+        if reference.empty_prompt(ref_free) && !self.empty_prompt {
+            return Err(TtsError::ModelSignature {
+                model: "t2s_first_stage_decoder".to_string(),
+                message: "does not accept an empty prompt, ref_free and voices without prompt text are not supported".to_string(),
+            });
+        }
+        Ok(reference.prompt(ref_free))
+    }
+
+    /// 混合中英日文本 -> (bert features, phones, norm text)
+    pub fn text_features(&self, text: &str) -> Result<(Array2<f32>, Vec<usize>, String), TtsError> {
+        self.text_features_timed(text, &mut SynthesisMetrics::default())
+    }
+
+    /// text_features，同时记录文本前端、bert 的耗时
+    fn text_features_timed(&self, text: &str, metrics: &mut SynthesisMetrics) -> Result<(Array2<f32>, Vec<usize>, String), TtsError> {
+        let start_frontend = Instant::now();
+        let (mut phones_list, word2ph_list, lang_list, norm_text_list) = self.text_util.get_cleaned_text_final(text);
+        metrics.text_frontend_ms += elapsed_ms(start_frontend);
+
+        let _bert = self.limits.bert.acquire();
+        let start_bert = Instant::now();
+        let features = ChBertUtils::get_bert_features(&self.ch_bert_util.tokenizer, &self.bert_model, &mut phones_list, &word2ph_list, &norm_text_list, &lang_list);
+        metrics.bert_ms += elapsed_ms(start_bert);
+        features
+    }
+
+    /// SSML 的一段：<lang> 不做语言检测，<phoneme> 直接用给的音素
+    fn ssml_features(&self, spans: &[SsmlSpan], metrics: &mut SynthesisMetrics) -> Result<(Array2<f32>, Vec<usize>, String), TtsError> {
+        let start_frontend = Instant::now();
+        let (mut phones_list, mut word2ph_list, mut lang_list, mut norm_text_list) = (vec![], vec![], vec![], vec![]);
+        for span in spans {
+            match span {
+                SsmlSpan::Text(text) => {
+                    let (phones, word2ph, lang, norm_text) = self.text_util.get_cleaned_text_final(text);
+                    phones_list.extend(phones);
+                    word2ph_list.extend(word2ph);
+                    lang_list.extend(lang);
+                    norm_text_list.extend(norm_text);
+                }
+                SsmlSpan::Lang { text, lang } => {
+                    let (phones, word2ph, lang, norm_text) = self.text_util.get_cleaned_text_lang(text, lang);
+                    phones_list.extend(phones);
+                    word2ph_list.extend(word2ph);
+                    lang_list.extend(lang);
+                    norm_text_list.extend(norm_text);
+                }
+                SsmlSpan::Phonemes { text, lang, phones, word2ph } => {
+                    phones_list.push(phones.clone());
+                    word2ph_list.push(word2ph.clone());
+                    lang_list.push(lang.clone());
+                    norm_text_list.push(text.clone());
+                }
+            }
+        }
+        metrics.text_frontend_ms += elapsed_ms(start_frontend);
+
+        let _bert = self.limits.bert.acquire();
+        let start_bert = Instant::now();
+        let features = ChBertUtils::get_bert_features(&self.ch_bert_util.tokenizer, &self.bert_model, &mut phones_list, &word2ph_list, &norm_text_list, &lang_list);
+        metrics.bert_ms += elapsed_ms(start_bert);
+        features
+    }
+
+    /// 单段文字 -> pcm16，None: 没有可以发音的内容
+    ///
+    /// params.retry 为 Some 时，生成检查不通过会换种子、降温度重试，都不通过就用最后一次的结果
+    fn synthesize_segment(&self, text: &str, reference: &ReferenceVoice, params: &SynthesisParams, metrics: &mut SynthesisMetrics) -> Result<Option<(Vec<i16>, GenerationDiagnostics)>, TtsError> {
+        let (bert_features2, phones_list_unpack2, _) = self.text_features_timed(text, metrics)?;
+        if phones_list_unpack2.is_empty() {
+            return Ok(None);
+        }
+        self.generate_segment(text, reference, params, &bert_features2, &phones_list_unpack2, None, metrics).map(Some)
+    }
+
+    /// 从第 first_attempt 次尝试开始生成一段，前面的尝试已经失败
+    fn generate_segment(
+        &self,
+        text: &str,
+        reference: &ReferenceVoice,
+        params: &SynthesisParams,
+        bert_features2: &Array2<f32>,
+        phones_list_unpack2: &Vec<usize>,
+        failed: Option<&GenerationDiagnostics>,
+        metrics: &mut SynthesisMetrics,
+    ) -> Result<(Vec<i16>, GenerationDiagnostics), TtsError> {
+        let max_retries = params.retry.as_ref().map(|retry| retry.max_retries).unwrap_or(0);
+        let prompt = self.prompt(reference, params.ref_free)?;
+
+        // failed: 已经跑过没通过的那次，重试接着它的种子
+        let mut attempt = failed.map(|diagnostics| diagnostics.attempts).unwrap_or(0);
+        let mut first_seed = failed.map(|diagnostics| diagnostics.seed);
+        loop {
+            let attempt_params = match (&params.retry, first_seed) {
+                (Some(retry), Some(seed)) if attempt > 0 => retry.params_for_retry(params, seed, attempt),
+                _ => params.clone(),
+            };
+            let (pred_semantic, mut diagnostics) = {
+                let _t2s = self.limits.t2s.acquire();
+                t2s_decode(
+                    &self.t2s_first_stage_decoder,
+                    &self.t2s_stage_decoder,
+                    &self.t2s_device,
+                    &prompt.semantic,
+                    &prompt.bert,
+                    bert_features2,
+                    &prompt.phones,
+                    phones_list_unpack2,
+                    &attempt_params,
+                    metrics,
+                    &mut |_| Ok(()),
+                )?
+            };
+            let audio = {
+                let _vocoder = self.limits.vocoder.acquire();
+                vq_decode_pcm16(&self.vq_model, &pred_semantic, phones_list_unpack2, &reference.wav32k_arr, &params.post_process, self.config.sampling_rate, metrics)?
+            };
+            diagnostics.attempts = attempt + 1;
+            first_seed.get_or_insert(diagnostics.seed);
+
+            let check = match &params.retry {
+                Some(retry) => retry.check(&diagnostics),
+                None => Ok(()),
+            };
+            match check {
+                Ok(()) => return Ok((audio, diagnostics)),
+                Err(reason) if attempt < max_retries => {
+                    warn!("segment {:?} attempt {} failed: {}, retry", text, attempt + 1, reason);
+                }
+                Err(reason) => {
+                    warn!("segment {:?} failed after {} attempts: {}", text, attempt + 1, reason);
+                    return Ok((audio, diagnostics));
+                }
+            }
+            attempt += 1;
+        }
+    }
+
+    /// 单段文字按 token 窗口流式输出，返回 false: 没有可以发音的内容
+    fn stream_segment_tokens(&self, text: &str, reference: &ReferenceVoice, params: &SynthesisParams, config: &TokenStreamConfig, metrics: &mut SynthesisMetrics, on_audio: &mut dyn FnMut(&[i16])) -> Result<bool, TtsError> {
+        let (bert_features2, phones_list_unpack2, _) = self.text_features_timed(text, metrics)?;
+        if phones_list_unpack2.is_empty() {
+            return Ok(false);
+        }
+        let prompt = self.prompt(reference, params.ref_free)?;
+        let mut streamer = TokenStreamer::new(&self.vq_model, &phones_list_unpack2, &reference.wav32k_arr, config, on_audio);
+        let t2s = self.limits.t2s.acquire();
+        let (pred_semantic, diagnostics) = t2s_decode(
+            &self.t2s_first_stage_decoder,
+            &self.t2s_stage_decoder,
+            &self.t2s_device,
+            &prompt.semantic,
+            &prompt.bert,
+            &bert_features2,
+            &prompt.phones,
+            &phones_list_unpack2,
+            params,
+            metrics,
+            &mut |tokens| {
+                if !streamer.window_ready(tokens.len()) {
+                    return Ok(());
+                }
+                let _vocoder = self.limits.vocoder.acquire();
+                streamer.push(tokens)
+            },
+        )?;
+        drop(t2s);
+        // decoder_ms 里包括了窗口解码的时间
+        metrics.decoder_ms -= streamer.vocoder_ms;
+        {
+            let _vocoder = self.limits.vocoder.acquire();
+            streamer.finish(&pred_semantic)?;
+        }
+        metrics.vocoder_ms += streamer.vocoder_ms;
+        // 音频已经发出去了，不能重试，只记录
+        if let Some(Err(reason)) = params.retry.as_ref().map(|retry| retry.check(&diagnostics)) {
+            warn!("segment {:?} failed: {}", text, reason);
+        }
+        Ok(true)
+    }
+
+    /// 边合成边回调 pcm16，params.token_streaming 为 Some 时不用等一段生成完
+    ///
+    /// 发到 channel：`engine.synthesize_with_callback(text, &voice, &params, &mut |pcm| { tx.send(pcm.to_vec()).ok(); })`
+    ///
+    /// 取消时已经合成的音频都回调过了，Cancelled 的 partial 是空的
+    pub fn synthesize_with_callback(&self, text: &str, reference: &ReferenceVoice, params: &SynthesisParams, on_audio: &mut dyn FnMut(&[i16])) -> Result<SynthesisMetrics, TtsError> {
+        // SSML 整段合成完再回调
+        if is_ssml(text) {
+            let audio = self.synthesize_ssml(text, reference, params)?;
+            on_audio(&audio.samples);
+            return Ok(audio.metrics);
+        }
+        let mut metrics = SynthesisMetrics { ssl_ms: reference.ssl_ms, ..SynthesisMetrics::default() };
+        let config = match &params.token_streaming {
+            Some(config) => config,
+            None => {
+                for chunk in self.synthesize_stream(text, reference, params) {
+                    let chunk = chunk?;
+                    on_audio(&chunk.samples);
+                    metrics.merge(&chunk.metrics);
+                }
+                metrics.log();
+                return Ok(metrics);
+            }
+        };
+
+        let start = Instant::now();
+        let mut samples = 0;
+
+        let texts = self.cut_texts(text, reference);
+
+        // 上一段有声音的结尾边界
+        let mut pending: Option<Boundary> = None;
+        for (text, boundary) in &texts {
+            params.check_cancelled()?;
+            // 停顿放在两段有声音的中间
+            if let Some(pending) = pending.take() {
+                let silence: Vec<i16> = vec![0; params.pause_samples(pending, self.config.sampling_rate)];
+                if !silence.is_empty() {
+                    on_audio(&silence);
+                    samples += silence.len();
+                }
+            }
+            // 一段共用一个 SoundTouch，窗口各自变速会在边界上咔哒、时间跳动
+            let mut effects = params.segment_effects(self.config.sampling_rate);
+            let mut count = |pcm: &[i16]| {
+                let pcm = effects.process(pcm);
+                samples += pcm.len();
+                on_audio(&pcm);
+            };
+            let voiced = self.stream_segment_tokens(text, reference, params, config, &mut metrics, &mut count)?;
+            let tail = effects.finish();
+            if !tail.is_empty() {
+                samples += tail.len();
+                on_audio(&tail);
+            }
+            if voiced {
+                pending = Some(*boundary);
+                metrics.segments += 1;
+            }
+        }
+        metrics.audio_seconds = samples as f64 / self.config.sampling_rate as f64;
+        metrics.total_ms = elapsed_ms(start);
+        metrics.log();
+        Ok(metrics)
+    }
+
+    /// 按参考文字长度切分，去掉空段，每段带上结尾的边界类型
+    fn cut_texts(&self, text: &str, reference: &ReferenceVoice) -> Vec<(String, Boundary)> {
+        cut_texts_with_boundaries(&self.text_util.lang_seg, text, reference.cut_len())
+    }
+
+    /// 多段一起跑 t2s，返回和 texts 一一对应的结果，None: 没有可以发音的内容
+    ///
+    /// 检查不通过的段单独重试
+    fn synthesize_segments_batch(&self, texts: &[String], reference: &ReferenceVoice, params: &SynthesisParams, metrics: &mut SynthesisMetrics) -> Result<Vec<Option<(Vec<i16>, GenerationDiagnostics)>>, TtsError> {
+        let mut features = vec![];
+        for text in texts {
+            features.push(self.text_features_timed(text, metrics)?);
+        }
+        let rows: Vec<usize> = (0..texts.len()).filter(|&i| !features[i].1.is_empty()).collect();
+        let segments: Vec<(&Array2<f32>, &Vec<usize>)> = rows.iter().map(|&i| (&features[i].0, &features[i].1)).collect();
+
+        let prompt = self.prompt(reference, params.ref_free)?;
+        let t2s = self.limits.t2s.acquire();
+        let decoded = t2s_decode_batch(
+            &self.t2s_first_stage_decoder,
+            &self.t2s_stage_decoder,
+            &self.t2s_device,
+            &prompt.semantic,
+            &prompt.bert,
+            &prompt.phones,
+            &segments,
+            params,
+            metrics,
+        )?;
+        // 重试时要重新拿
+        drop(t2s);
+
+        let mut results: Vec<Option<(Vec<i16>, GenerationDiagnostics)>> = (0..texts.len()).map(|_| None).collect();
+        for (&i, (pred_semantic, diagnostics)) in rows.iter().zip(decoded) {
+            let (bert_features2, phones_list_unpack2, _) = &features[i];
+            let max_retries = params.retry.as_ref().map(|retry| retry.max_retries).unwrap_or(0);
+            let check = match &params.retry {
+                Some(retry) => retry.check(&diagnostics),
+                None => Ok(()),
+            };
+            let segment = match check {
+                Err(reason) if max_retries > 0 => {
+                    warn!("segment {:?} attempt 1 failed: {}, retry", texts[i], reason);
+                    self.generate_segment(&texts[i], reference, params, bert_features2, phones_list_unpack2, Some(&diagnostics), metrics)?
+                }
+                check => {
+                    if let Err(reason) = check {
+                        warn!("segment {:?} failed after 1 attempts: {}", texts[i], reason);
+                    }
+                    let _vocoder = self.limits.vocoder.acquire();
+                    let audio = vq_decode_pcm16(&self.vq_model, &pred_semantic, phones_list_unpack2, &reference.wav32k_arr, &params.post_process, self.config.sampling_rate, metrics)?;
+                    (audio, diagnostics)
+                }
+            };
+            results[i] = Some(segment);
+        }
+        Ok(results)
+    }
+
+    /// 按 params.batch_size 分批合成，段与段之间按边界补停顿
+    fn synthesize_batched(&self, text: &str, reference: &ReferenceVoice, params: &SynthesisParams) -> Result<Audio, TtsError> {
+        let start = Instant::now();
+        let (texts, boundaries): (Vec<String>, Vec<Boundary>) = self.cut_texts(text, reference).into_iter().unzip();
+        let sample_rate = self.config.sampling_rate;
+
+        let mut samples: Vec<i16> = vec![];
+        let mut diagnostics = vec![];
+        let mut metrics = SynthesisMetrics { ssl_ms: reference.ssl_ms, ..SynthesisMetrics::default() };
+        // 上一段有声音的结尾边界，停顿只放在两段有声音的中间
+        let mut pending: Option<Boundary> = None;
+        for (batch_idx, batch) in texts.chunks(params.batch_size).enumerate() {
+            let results = params.check_cancelled()
+                .and_then(|_| self.synthesize_segments_batch(batch, reference, params, &mut metrics))
+                .map_err(|e| with_partial(e, &samples))?;
+            for (i, result) in results.into_iter().enumerate() {
+                let index = batch_idx * params.batch_size + i;
+                if let Some((segment, segment_diagnostics)) = result {
+                    if let Some(pending) = pending.take() {
+                        samples.extend(std::iter::repeat(0).take(params.pause_samples(pending, sample_rate)));
+                    }
+                    samples.extend(params.apply_effects(segment, sample_rate));
+                    pending = Some(boundaries[index]);
+                    diagnostics.push(segment_diagnostics);
+                    metrics.segments += 1;
+                }
+            }
+        }
+        metrics.audio_seconds = samples.len() as f64 / sample_rate as f64;
+        metrics.total_ms = elapsed_ms(start);
+        metrics.log();
+
+        Ok(Audio { samples, sample_rate, diagnostics, metrics })
+    }
+
+    /// 流式合成：按参考文字长度切分，每段 vq_model 跑完就返回这一段的音频，段与段之间按边界补停顿
+    pub fn synthesize_stream<'a>(&'a self, text: &str, reference: &'a ReferenceVoice, params: &'a SynthesisParams) -> SynthesisStream<'a> {
+        let texts = self.cut_texts(text, reference);
+        SynthesisStream { engine: self, reference, params, texts, idx: 0, pending: None }
+    }
+
+    /// SSML 输入：每段按 <prosody> 调整参数，段与段之间按 <break>、标点补停顿
+    pub fn synthesize_ssml(&self, ssml: &str, reference: &ReferenceVoice, params: &SynthesisParams) -> Result<Audio, TtsError> {
+        let start = Instant::now();
+        let segments = parse_ssml(&self.text_util, ssml, reference.cut_len())?;
+        let sample_rate = self.config.sampling_rate;
+
+        let mut samples: Vec<i16> = vec![];
+        let mut diagnostics = vec![];
+        let mut metrics = SynthesisMetrics { ssl_ms: reference.ssl_ms, ..SynthesisMetrics::default() };
+        // 上一段有声音的结尾停顿：采样点数，后面没有声音就不加
+        let mut pending: Option<usize> = None;
+        for segment in segments.iter() {
+            let segment_params = segment.prosody.apply(params);
+            let generated = segment_params.check_cancelled()
+                .and_then(|_| self.ssml_features(&segment.spans, &mut metrics))
+                .and_then(|(bert_features2, phones_list_unpack2, _)| {
+                    if phones_list_unpack2.is_empty() {
+                        return Ok(None);
+                    }
+                    self.generate_segment(&segment.text(), reference, &segment_params, &bert_features2, &phones_list_unpack2, None, &mut metrics).map(Some)
+                })
+                .map_err(|e| with_partial(e, &samples))?;
+            if let Some((audio, segment_diagnostics)) = generated {
+                if let Some(pause) = pending.take() {
+                    samples.extend(std::iter::repeat(0).take(pause));
+                }
+                samples.extend(segment_params.apply_effects(audio, sample_rate));
+                pending = Some(segment_params.pause_samples(segment.boundary, sample_rate));
+                diagnostics.push(segment_diagnostics);
+                metrics.segments += 1;
+            }
+        }
+        metrics.audio_seconds = samples.len() as f64 / sample_rate as f64;
+        metrics.total_ms = elapsed_ms(start);
+        metrics.log();
+
+        Ok(Audio { samples, sample_rate, diagnostics, metrics })
+    }
+
+    /// 合成整段文本：流式合成的结果拼接，params.batch_size > 1 时多段一起跑 t2s；`<speak>` 开头的按 SSML
+    ///
+    /// 取消或超时返回 Cancelled，partial 是已经合成完的分段
+    pub fn synthesize(&self, text: &str, reference: &ReferenceVoice, params: &SynthesisParams) -> Result<Audio, TtsError> {
+        if is_ssml(text) {
+            return self.synthesize_ssml(text, reference, params);
+        }
+        if params.batch_size > 1 {
+            if self.batch_t2s {
+                return self.synthesize_batched(text, reference, params);
+            }
+            warn!("t2s_stage_decoder does not support batching, batch_size {} ignored", params.batch_size);
+        }
+        let mut samples: Vec<i16> = vec![];
+        let mut diagnostics = vec![];
+        let mut metrics = SynthesisMetrics { ssl_ms: reference.ssl_ms, ..SynthesisMetrics::default() };
+        for chunk in self.synthesize_stream(text, reference, params) {
+            let mut chunk = chunk.map_err(|e| with_partial(e, &samples))?;
+            samples.append(&mut chunk.samples);
+            diagnostics.push(chunk.diagnostics);
+            metrics.merge(&chunk.metrics);
+        }
+        metrics.log();
+
+        Ok(Audio { samples, sample_rate: self.config.sampling_rate, diagnostics, metrics })
+    }
+}
+
+/// 检查失败不影响加载，只是不能 batch
+fn check_batch_t2s(t2s_first_stage_decoder: &Session, t2s_stage_decoder: &Session, t2s_device: &Provider) -> bool {
+    match probe_batch_mask(t2s_first_stage_decoder, t2s_stage_decoder, t2s_device) {
+        Ok(true) => true,
+        Ok(false) => {
+            info!("t2s_stage_decoder has no float logits or ignores xy_attn_mask, batch_size > 1 disabled");
+            false
+        }
+        Err(e) => {
+            warn!("t2s batch probe failed, batch_size > 1 disabled: {}", e);
+            false
+        }
+    }
+}
+
+fn check_empty_prompt(t2s_first_stage_decoder: &Session, t2s_stage_decoder: &Session, t2s_device: &Provider) -> bool {
+    match probe_empty_prompt(t2s_first_stage_decoder, t2s_stage_decoder, t2s_device) {
+        Ok(()) => true,
+        Err(e) => {
+            warn!("t2s decoder does not accept an empty prompt, ref_free disabled: {}", e);
+            false
+        }
+    }
+}
+
+/// 取消时带上已经合成的音频
+fn with_partial(e: TtsError, samples: &[i16]) -> TtsError {
+    match e {
+        TtsError::Cancelled { .. } => TtsError::Cancelled { partial: samples.to_vec() },
+        e => e,
+    }
+}
+
+impl<'a> Iterator for SynthesisStream<'a> {
+    type Item = Result<AudioChunk, TtsError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.idx < self.texts.len() {
+            let index = self.idx;
+            self.idx += 1;
+
+            let (text, boundary) = &self.texts[index];
+            let start = Instant::now();
+            let mut metrics = SynthesisMetrics::default();
+            let segment = self.params.check_cancelled()
+                .and_then(|_| self.engine.synthesize_segment(text, self.reference, self.params, &mut metrics));
+            let sample_rate = self.engine.config.sampling_rate;
+            let (segment, diagnostics) = match segment {
+                Ok(Some((samples, diagnostics))) => (self.params.apply_effects(samples, sample_rate), diagnostics),
+                Ok(None) => continue,
+                Err(e) => {
+                    // 出错后不再继续
+                    self.idx = self.texts.len();
+                    return Some(Err(e));
+                }
+            };
+
+            let mut samples = match self.pending.replace(*boundary) {
+                Some(pending) => vec![0; self.params.pause_samples(pending, sample_rate)],
+                None => vec![],
+            };
+            samples.extend(segment);
+            metrics.segments = 1;
+            metrics.audio_seconds = samples.len() as f64 / self.engine.config.sampling_rate as f64;
+            metrics.total_ms = elapsed_ms(start);
+
+            return Some(Ok(AudioChunk {
+                index,
+                text: text.clone(),
+                samples,
+                sample_rate: self.engine.config.sampling_rate,
+                diagnostics,
+                metrics,
+            }));
+        }
+        None
+    }
+}
@@ -0,0 +1,51 @@
+/// 单声道 pcm16 的 wav 头，data_len: 数据字节数，None: 流式输出时长度未知
+pub fn wav_header(sample_rate: i32, data_len: Option<u32>) -> Vec<u8> {
+    let channels: u16 = 1;
+    let bits: u16 = 16;
+    let block_align = channels * bits / 8;
+    let byte_rate = sample_rate as u32 * block_align as u32;
+    // 长度未知时按最大值写，播放器会一直读到结束
+    let data_len = data_len.unwrap_or(u32::MAX - 36);
+
+    let mut header = Vec::with_capacity(44);
+    header.extend_from_slice(b"RIFF");
+    header.extend_from_slice(&(36 + data_len).to_le_bytes());
+    header.extend_from_slice(b"WAVE");
+    header.extend_from_slice(b"fmt ");
+    header.extend_from_slice(&16u32.to_le_bytes());
+    header.extend_from_slice(&1u16.to_le_bytes());
+    header.extend_from_slice(&channels.to_le_bytes());
+    header.extend_from_slice(&(sample_rate as u32).to_le_bytes());
+    header.extend_from_slice(&byte_rate.to_le_bytes());
+    header.extend_from_slice(&block_align.to_le_bytes());
+    header.extend_from_slice(&bits.to_le_bytes());
+    header.extend_from_slice(b"data");
+    header.extend_from_slice(&data_len.to_le_bytes());
+    header
+}
+
+pub fn pcm16_to_bytes(samples: &[i16]) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(samples.len() * 2);
+    for s in samples {
+        bytes.extend_from_slice(&s.to_le_bytes());
+    }
+    bytes
+}
+
+/// 完整的 wav 文件内容
+pub fn wav_bytes(samples: &[i16], sample_rate: i32) -> Vec<u8> {
+    let mut bytes = wav_header(sample_rate, Some((samples.len() * 2) as u32));
+    bytes.append(&mut pcm16_to_bytes(samples));
+    bytes
+}
+
+#[test]
+fn test_wav_bytes() {
+    let bytes = wav_bytes(&[0, 1, -1], 32000);
+    assert_eq!(bytes.len(), 44 + 6);
+    assert_eq!(&bytes[0..4], b"RIFF");
+    assert_eq!(u32::from_le_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]), 36 + 6);
+    assert_eq!(u32::from_le_bytes([bytes[24], bytes[25], bytes[26], bytes[27]]), 32000);
+    assert_eq!(u32::from_le_bytes([bytes[40], bytes[41], bytes[42], bytes[43]]), 6);
+    assert_eq!(&bytes[46..48], &[0xff, 0xff]);
+}
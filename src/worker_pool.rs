@@ -0,0 +1,175 @@
+use std::sync::{Arc, Mutex, RwLock};
+use std::sync::mpsc::{channel, sync_channel, Receiver, SyncSender, TryRecvError, TrySendError};
+use std::thread::JoinHandle;
+use log::warn;
+use serde::{Deserialize, Serialize};
+use crate::concurrency::CancelToken;
+use crate::error::TtsError;
+use crate::reference_voice::ReferenceVoice;
+use crate::tts_engine::{Audio, SynthesisParams, TtsEngine};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PoolConfig {
+    /// 同时合成的请求数，每个阶段的上限见 TtsConfig.concurrency
+    pub workers: usize,
+    /// 排队等待的请求数，满了 submit 直接返回 QueueFull
+    pub queue_size: usize,
+}
+
+impl Default for PoolConfig {
+    fn default() -> Self {
+        PoolConfig { workers: 2, queue_size: 16 }
+    }
+}
+
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+/// 一套模型、多个工作线程：submit 的请求进有界队列，空闲的线程取出来跑
+///
+/// 引擎放在 RwLock 里：合成拿读锁可以并行，换权重拿写锁，等正在跑的请求结束
+pub struct WorkerPool {
+    engine: Arc<RwLock<TtsEngine>>,
+    sender: Option<SyncSender<Job>>,
+    workers: Vec<JoinHandle<()>>,
+    queue_size: usize,
+}
+
+/// submit 返回的句柄：等结果或取消
+pub struct JobHandle<T> {
+    result: Receiver<Result<T, TtsError>>,
+    cancel: CancelToken,
+}
+
+impl WorkerPool {
+    pub fn new(engine: TtsEngine, config: &PoolConfig) -> Self {
+        let engine = Arc::new(RwLock::new(engine));
+        let (sender, receiver) = sync_channel::<Job>(config.queue_size);
+        let receiver = Arc::new(Mutex::new(receiver));
+
+        let workers = (0..config.workers.max(1)).map(|i| {
+            let receiver = Arc::clone(&receiver);
+            std::thread::Builder::new()
+                .name(format!("tts-worker-{}", i))
+                .spawn(move || loop {
+                    // 只在取任务时持有锁
+                    let job = receiver.lock().unwrap().recv();
+                    match job {
+                        Ok(job) => job(),
+                        // pool drop 了
+                        Err(_) => break,
+                    }
+                })
+                .expect("spawn tts worker")
+        }).collect();
+
+        WorkerPool { engine, sender: Some(sender), workers, queue_size: config.queue_size }
+    }
+
+    /// 换权重等需要写锁的操作用
+    pub fn engine(&self) -> &Arc<RwLock<TtsEngine>> {
+        &self.engine
+    }
+
+    /// 排队执行 job，队列满了返回 QueueFull；job 开始前已经取消的直接返回 Cancelled
+    pub fn submit<T, F>(&self, job: F) -> Result<JobHandle<T>, TtsError>
+    where
+        T: Send + 'static,
+        F: FnOnce(&TtsEngine, &CancelToken) -> Result<T, TtsError> + Send + 'static,
+    {
+        self.submit_with_cancel(CancelToken::new(), job)
+    }
+
+    /// 同 submit，JobHandle::cancel 和传进来的 cancel 是同一个 token，取消哪边都一样
+    pub fn submit_with_cancel<T, F>(&self, cancel: CancelToken, job: F) -> Result<JobHandle<T>, TtsError>
+    where
+        T: Send + 'static,
+        F: FnOnce(&TtsEngine, &CancelToken) -> Result<T, TtsError> + Send + 'static,
+    {
+        let (result_tx, result_rx) = channel();
+        let engine = Arc::clone(&self.engine);
+        let job_cancel = cancel.clone();
+        let job: Job = Box::new(move || {
+            let result = if job_cancel.is_cancelled() {
+                Err(TtsError::Cancelled { partial: vec![] })
+            } else {
+                let engine = engine.read().unwrap_or_else(|e| e.into_inner());
+                job(&engine, &job_cancel)
+            };
+            // 调用方不等结果了
+            let _ = result_tx.send(result);
+        });
+
+        let sender = self.sender.as_ref().expect("worker pool is shut down");
+        match sender.try_send(job) {
+            Ok(()) => Ok(JobHandle { result: result_rx, cancel }),
+            Err(TrySendError::Full(_)) => Err(TtsError::QueueFull { capacity: self.queue_size }),
+            Err(TrySendError::Disconnected(_)) => Err(TtsError::Inference("worker pool is shut down".to_string())),
+        }
+    }
+
+    /// 排队合成整段文本，JobHandle::cancel 和调用方放在 params.cancel 里的 token 在合成中途都能停下
+    pub fn synthesize(&self, text: String, reference: Arc<ReferenceVoice>, params: SynthesisParams) -> Result<JobHandle<Audio>, TtsError> {
+        self.submit_with_cancel(job_cancel_token(&params), move |engine, cancel| {
+            let params = SynthesisParams { cancel: Some(cancel.clone()), ..params };
+            engine.synthesize(&text, &reference, &params)
+        })
+    }
+}
+
+/// 调用方自己带了 CancelToken 就沿用它，不另建一个把它丢掉
+fn job_cancel_token(params: &SynthesisParams) -> CancelToken {
+    params.cancel.clone().unwrap_or_default()
+}
+
+impl Drop for WorkerPool {
+    /// 队列里剩下的请求跑完再退出
+    fn drop(&mut self) {
+        drop(self.sender.take());
+        for worker in self.workers.drain(..) {
+            if worker.join().is_err() {
+                warn!("tts worker panicked");
+            }
+        }
+    }
+}
+
+impl<T> JobHandle<T> {
+    /// 还在排队的不会再跑；已经开始的由 job 自己检查 CancelToken，比如放进 SynthesisParams.cancel
+    pub fn cancel(&self) {
+        self.cancel.cancel();
+    }
+
+    pub fn cancel_token(&self) -> &CancelToken {
+        &self.cancel
+    }
+
+    /// 阻塞等结果
+    pub fn wait(self) -> Result<T, TtsError> {
+        self.result.recv().unwrap_or_else(|_| Err(TtsError::Inference("tts worker exited".to_string())))
+    }
+
+    /// 不阻塞：None 表示还没结束，给 async 服务轮询用
+    pub fn try_wait(&self) -> Option<Result<T, TtsError>> {
+        match self.result.try_recv() {
+            Ok(result) => Some(result),
+            Err(TryRecvError::Empty) => None,
+            Err(TryRecvError::Disconnected) => Some(Err(TtsError::Inference("tts worker exited".to_string()))),
+        }
+    }
+}
+
+#[test]
+fn test_caller_cancel_token() {
+    let caller = CancelToken::new();
+    let params = SynthesisParams { cancel: Some(caller.clone()), ..SynthesisParams::default() };
+    let job = job_cancel_token(&params);
+    assert!(!job.is_cancelled());
+
+    // 调用方取消，job 里看到的 token 也取消了
+    caller.cancel();
+    assert!(job.is_cancelled());
+    assert!(SynthesisParams { cancel: Some(job), ..params }.check_cancelled().is_err());
+
+    assert!(!job_cancel_token(&SynthesisParams::default()).is_cancelled());
+}